@@ -0,0 +1,99 @@
+//! Canonical forms of feed links, so a feed written in different ways is still one feed
+//!
+//! `canonicalize` only changes what no server tells apart, so the link still fetches the same.
+//! `feed_key` also ignores what seldom makes a difference, the scheme, a trailing slash
+//! and tracking parameters, to tell whether two links are of the same feed.
+
+use url::Url;
+
+/// Query parameters that only tell where a link was clicked
+fn is_tracking_param(name: &str) -> bool {
+    name.starts_with("utm_")
+        || ["fbclid", "gclid", "yclid", "mc_cid", "mc_eid", "_ga"].contains(&name)
+}
+
+/// `link` with the scheme and host in lowercase, without the default port and the fragment,
+/// and with `http://` if it has no scheme.
+/// Links that are not HTTP are only trimmed
+pub fn canonicalize(link: &str) -> String {
+    let link = link.trim();
+    let parsed = if link.contains("://") {
+        Url::parse(link)
+    } else {
+        Url::parse(&format!("http://{}", link))
+    };
+    let mut url = match parsed {
+        Ok(url) => url,
+        Err(_) => return link.to_owned(),
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return link.to_owned();
+    }
+    url.set_fragment(None);
+    url.into_string()
+}
+
+/// The same for links of the same feed
+pub fn feed_key(link: &str) -> String {
+    let link = canonicalize(link);
+    let url = match Url::parse(&link) {
+        Ok(url) => url,
+        Err(_) => return link,
+    };
+    let mut key = url.host_str().unwrap_or_default().to_owned();
+    if let Some(port) = url.port() {
+        key.push_str(&format!(":{}", port));
+    }
+    key.push_str(url.path().trim_right_matches('/'));
+    // filtered as is, decoding and encoding again may tell apart what the server doesn't
+    let query = url.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !is_tracking_param(pair.split('=').next().unwrap()))
+        .collect::<Vec<&str>>()
+        .join("&");
+    if !query.is_empty() {
+        key.push('?');
+        key.push_str(&query);
+    }
+    key
+}
+
+#[test]
+fn test_canonicalize() {
+    let data = vec![
+        ("http://example.com/feed", "http://example.com/feed"),
+        (" HTTP://Example.COM/feed ", "http://example.com/feed"),
+        ("example.com/feed", "http://example.com/feed"),
+        ("localhost:8080/feed", "http://localhost:8080/feed"),
+        ("http://example.com:80/feed", "http://example.com/feed"),
+        ("https://example.com:443/feed", "https://example.com/feed"),
+        ("https://example.com:8443/feed", "https://example.com:8443/feed"),
+        ("http://example.com", "http://example.com/"),
+        ("http://example.com/a/../feed/", "http://example.com/feed/"),
+        ("http://example.com/feed#top", "http://example.com/feed"),
+        ("http://example.com/feed?utm_medium=rss", "http://example.com/feed?utm_medium=rss"),
+        ("http://example.com/feed?a=b%2Fc&&d", "http://example.com/feed?a=b%2Fc&&d"),
+        ("ftp://Example.com/feed", "ftp://Example.com/feed"),
+        ("not a link", "not a link"),
+    ];
+    for (link, result) in data {
+        assert_eq!(canonicalize(link), result, "{}", link);
+    }
+}
+
+#[test]
+fn test_feed_key() {
+    let key = feed_key("http://x.com/feed");
+    assert_eq!(key, "x.com/feed");
+    for link in &["https://x.com/feed/", "HTTP://X.com/feed", "x.com/feed", "x.com/feed#a"] {
+        assert_eq!(feed_key(link), key, "{}", link);
+    }
+    assert_eq!(feed_key("http://x.com/"), "x.com");
+    assert_eq!(feed_key("http://x.com:8080/feed/?a=1"), "x.com:8080/feed?a=1");
+    assert_eq!(feed_key("http://x.com/feed?utm_source=a&id=1&fbclid=b"), "x.com/feed?id=1");
+    assert_eq!(feed_key("http://x.com/feed?utm_medium=rss"), key);
+    assert_eq!(feed_key("http://x.com/feed?a=b%2Fc&&d"), "x.com/feed?a=b%2Fc&d");
+    assert!(feed_key("http://x.com/feed?a=1") != key);
+    assert!(feed_key("http://x.com/feed/atom") != key);
+}
@@ -1,31 +1,74 @@
+use std::io::Cursor;
+
 use futures::future;
 use futures::prelude::*;
 use pinyin_order;
 use telebot;
 use telebot::functions::File;
 use telebot::functions::*;
+use telebot::objects::Message;
 use tokio_core::reactor::Handle;
 use tokio_curl::Session;
 
-use data::Database;
+use canonical::canonicalize;
+use data::{ChatSettings, Database, Feed, Options};
+use digest::{format_daily_time, parse_digest, parse_utc_offset, Digest};
 use errors::*;
 use feed;
-use opml::to_opml;
-use utlis::{format_and_split_msgs, gen_ua, log_error, send_multiple_messages,
-            to_chinese_error_msg, Escape, EscapeUrl};
+use filter::Filter;
+use i18n::{fill, Lang, Messages, LANGS};
+use opml::{from_opml, to_opml, Outline};
+use ratelimit::RateLimiter;
+use utlis::{format_and_split_msgs, format_duration, format_time, gen_ua, log_error,
+            send_multiple_messages, to_error_msg, to_feed_error_msg, Escape, EscapeUrl};
 
-pub fn register_commands(bot: &telebot::RcBot, db: &Database, lphandle: Handle) {
-    register_rss(bot, db.clone());
-    register_sub(bot, db.clone(), lphandle);
+pub fn register_commands(
+    bot: &telebot::RcBot,
+    db: &Database,
+    limiter: &RateLimiter,
+    lphandle: Handle,
+) {
+    register_rss(bot, db.clone(), limiter.clone());
+    register_status(bot, db.clone(), limiter.clone());
+    register_sub(bot, db.clone(), lphandle.clone());
     register_unsub(bot, db.clone());
     register_unsubthis(bot, db.clone());
     register_export(bot, db.clone());
+    register_filter(bot, db.clone());
+    register_switch(bot, db.clone(), EXCERPT);
+    register_switch(bot, db.clone(), MEDIA);
+    register_lang(bot, db.clone());
+    register_chat_setting(bot, db.clone(), DIGEST);
+    register_chat_setting(bot, db.clone(), TIMEZONE);
+    register_import(bot, db.clone(), limiter.clone(), lphandle);
+}
+
+/// The language to reply to `msg` in
+fn msg_lang(db: &Database, msg: &Message) -> Lang {
+    let language_code = msg.from
+        .as_ref()
+        .and_then(|user| user.language_code.as_ref())
+        .map(|s| s.as_str());
+    db.get_lang(msg.chat.id, Lang::from_language_code(language_code))
 }
 
-fn register_rss(bot: &telebot::RcBot, db: Database) {
+/// Messages sent without a command, like update notices, need a language too,
+/// so the first subscription of a chat keeps the language it was made in
+fn remember_lang(db: &Database, subscriber: i64, lang: Lang) {
+    let mut settings = db.get_chat_settings(subscriber);
+    if settings.lang.is_none() {
+        settings.lang = Some(lang);
+        if let Err(e) = db.set_chat_settings(subscriber, settings) {
+            log_error(&e);
+        }
+    }
+}
+
+fn register_rss(bot: &telebot::RcBot, db: Database, limiter: RateLimiter) {
     let handle = bot.new_cmd("/rss")
         .map_err(Some)
         .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
             let text = msg.text.unwrap();
             let args: Vec<&str> = text.split_whitespace().collect();
             let raw: bool;
@@ -43,7 +86,7 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
                         raw = false;
                         let channel = args[0];
                         let channel_id =
-                            check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id);
+                            check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
                         subscriber = future::Either::B(channel_id);
                     }
                 }
@@ -51,14 +94,12 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
                     raw = true;
                     let channel = args[0];
                     let channel_id =
-                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id);
+                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
                     subscriber = future::Either::B(channel_id);
                 }
                 _ => {
-                    let r = bot.message(
-                        msg.chat.id,
-                        "使用方法: /rss <Channel ID> <raw>".to_string(),
-                    ).send()
+                    let r = bot.message(msg.chat.id, lang.msgs().rss_usage.to_string())
+                        .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
                             Err(e) => Err(Some(e)),
@@ -74,16 +115,16 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
                     Ok(None) => Err(None),
                     Err(err) => Err(Some(err)),
                 })
-                .map(move |subscriber| (bot, db, subscriber, raw, chat_id));
+                .map(move |subscriber| (bot, db, subscriber, raw, chat_id, lang));
             future::Either::B(r)
         })
-        .and_then(|(bot, db, subscriber, raw, chat_id)| {
+        .and_then(|(bot, db, subscriber, raw, chat_id, lang)| {
             match db.get_subscribed_feeds(subscriber) {
-                Some(feeds) => Ok((bot, raw, chat_id, feeds)),
+                Some(feeds) => Ok((bot, raw, chat_id, feeds, lang)),
                 None => Err((bot, chat_id)),
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(chat_id, "订阅列表为空".to_string())
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().empty_list.to_string())
                         .send()
                         .then(|r| match r {
                             Ok(_) => Err(None),
@@ -91,8 +132,8 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
                         })
                 })
         })
-        .and_then(|(bot, raw, chat_id, mut feeds)| {
-            let text = String::from("订阅列表:");
+        .and_then(move |(bot, raw, chat_id, mut feeds, lang)| {
+            let text = String::from(lang.msgs().subscription_list);
             if !raw {
                 feeds.sort_by_key(|feed| pinyin_order::as_pinyin(&feed.title));
                 let msgs = format_and_split_msgs(text, &feeds, |feed| {
@@ -102,13 +143,13 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
                         Escape(&feed.title)
                     )
                 });
-                send_multiple_messages(&bot, chat_id, msgs)
+                send_multiple_messages(&bot, &limiter, chat_id, msgs)
             } else {
                 feeds.sort_by(|a, b| a.link.cmp(&b.link));
                 let msgs = format_and_split_msgs(text, &feeds, |feed| {
                     format!("{}: {}", Escape(&feed.title), Escape(&feed.link))
                 });
-                send_multiple_messages(&bot, chat_id, msgs)
+                send_multiple_messages(&bot, &limiter, chat_id, msgs)
             }.map_err(Some)
         })
         .then(|result| match result {
@@ -122,10 +163,118 @@ fn register_rss(bot: &telebot::RcBot, db: Database) {
     bot.register(handle);
 }
 
+fn format_status(feed: &Feed, lang: Lang) -> String {
+    let msgs = lang.msgs();
+    let time_or_never = |time: Option<i64>| time.map_or(msgs.status_never.to_owned(), format_time);
+    let mut lines = vec![
+        format!(
+            "<a href=\"{}\">{}</a>",
+            EscapeUrl(&feed.link),
+            Escape(&feed.title)
+        ),
+        fill(msgs.status_last_fetch, &[&time_or_never(feed.last_fetch)]),
+    ];
+    if let Some(ref e) = feed.last_error {
+        lines.push(fill(
+            msgs.status_last_error,
+            &[
+                &format_time(e.time),
+                &Escape(&to_feed_error_msg(e, lang)).to_string(),
+            ],
+        ));
+    }
+    if feed.error_count > 0 {
+        lines.push(fill(msgs.status_failures, &[&feed.error_count.to_string()]));
+    }
+    lines.push(fill(msgs.status_last_item, &[&time_or_never(feed.last_item)]));
+    if let Some(interval) = feed.interval {
+        lines.push(fill(msgs.status_interval, &[&format_duration(interval)]));
+    }
+    lines.join("\n")
+}
+
+fn register_status(bot: &telebot::RcBot, db: Database, limiter: RateLimiter) {
+    let handle = bot.new_cmd("/status")
+        .map_err(Some)
+        .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
+            let text = msg.text.unwrap();
+            let args: Vec<&str> = text.split_whitespace().collect();
+            let subscriber: future::Either<_, _>;
+            match args.len() {
+                0 => {
+                    subscriber = future::Either::A(future::ok(Some(msg.chat.id)));
+                }
+                1 => {
+                    let channel = args[0];
+                    let channel_id =
+                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
+                    subscriber = future::Either::B(channel_id);
+                }
+                _ => {
+                    let r = bot.message(msg.chat.id, lang.msgs().status_usage.to_string())
+                        .send()
+                        .then(|result| match result {
+                            Ok(_) => Err(None),
+                            Err(e) => Err(Some(e)),
+                        });
+                    return future::Either::A(r);
+                }
+            }
+            let db = db.clone();
+            let chat_id = msg.chat.id;
+            let r = subscriber
+                .then(|result| match result {
+                    Ok(Some(ok)) => Ok(ok),
+                    Ok(None) => Err(None),
+                    Err(err) => Err(Some(err)),
+                })
+                .map(move |subscriber| (bot, db, subscriber, chat_id, lang));
+            future::Either::B(r)
+        })
+        .and_then(|(bot, db, subscriber, chat_id, lang)| {
+            match db.get_subscribed_feeds(subscriber) {
+                Some(feeds) => Ok((bot, chat_id, feeds, lang)),
+                None => Err((bot, chat_id)),
+            }.into_future()
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().empty_list.to_string())
+                        .send()
+                        .then(|r| match r {
+                            Ok(_) => Err(None),
+                            Err(e) => Err(Some(e)),
+                        })
+                })
+        })
+        .and_then(move |(bot, chat_id, mut feeds, lang)| {
+            // the failing ones first
+            feeds.sort_by(|a, b| {
+                b.error_count.cmp(&a.error_count).then_with(|| {
+                    pinyin_order::as_pinyin(&a.title).cmp(&pinyin_order::as_pinyin(&b.title))
+                })
+            });
+            let text = String::from(lang.msgs().status_list);
+            let msgs = format_and_split_msgs(text, &feeds, |feed| {
+                format!("\n{}", format_status(feed, lang))
+            });
+            send_multiple_messages(&bot, &limiter, chat_id, msgs).map_err(Some)
+        })
+        .then(|result| match result {
+            Err(Some(err)) => {
+                error!("telebot: {:?}", err);
+                Ok::<(), ()>(())
+            }
+            _ => Ok(()),
+        });
+
+    bot.register(handle);
+}
+
 fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
     let handle = bot.new_cmd("/sub")
         .map_err(Some)
         .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
             let text = msg.text.unwrap();
             let args: Vec<&str> = text.split_whitespace().collect();
             let feed_link: &str;
@@ -138,15 +287,13 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                 2 => {
                     let channel = args[0];
                     let channel_id =
-                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id);
+                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
                     subscriber = future::Either::B(channel_id);
                     feed_link = args[1];
                 }
                 _ => {
-                    let r = bot.message(
-                        msg.chat.id,
-                        "使用方法: /sub [Channel ID] <RSS URL>".to_string(),
-                    ).send()
+                    let r = bot.message(msg.chat.id, lang.msgs().sub_usage.to_string())
+                        .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
                             Err(e) => Err(Some(e)),
@@ -155,7 +302,7 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                 }
             }
             let db = db.clone();
-            let feed_link = feed_link.to_owned();
+            let feed_link = canonicalize(feed_link);
             let chat_id = msg.chat.id;
             let lphandle = lphandle.clone();
             let r = subscriber
@@ -164,17 +311,17 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                     Ok(None) => Err(None),
                     Err(err) => Err(Some(err)),
                 })
-                .map(move |subscriber| (bot, db, subscriber, feed_link, chat_id, lphandle));
+                .map(move |subscriber| (bot, db, subscriber, feed_link, chat_id, lphandle, lang));
             future::Either::B(r)
         })
-        .and_then(|(bot, db, subscriber, feed_link, chat_id, lphandle)| {
+        .and_then(|(bot, db, subscriber, feed_link, chat_id, lphandle, lang)| {
             if db.is_subscribed(subscriber, &feed_link) {
                 Err((bot, chat_id))
             } else {
-                Ok((bot, db, subscriber, feed_link, chat_id, lphandle))
+                Ok((bot, db, subscriber, feed_link, chat_id, lphandle, lang))
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(chat_id, "已订阅过的 RSS".to_string())
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().already_subscribed.to_string())
                         .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
@@ -182,8 +329,8 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                         })
                 })
         })
-        .and_then(|(bot, db, subscriber, feed_link, chat_id, lphandle)| {
-            bot.message(chat_id, "处理中, 请稍候".to_owned())
+        .and_then(|(bot, db, subscriber, feed_link, chat_id, lphandle, lang)| {
+            bot.message(chat_id, lang.msgs().processing.to_owned())
                 .send()
                 .map_err(Some)
                 .map(move |(bot, msg)| {
@@ -195,20 +342,21 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                         chat_id,
                         msg.message_id,
                         lphandle,
+                        lang,
                     )
                 })
         })
         .and_then(
-            |(bot, db, subscriber, feed_link, chat_id, msg_id, lphandle)| {
+            |(bot, db, subscriber, feed_link, chat_id, msg_id, lphandle, lang)| {
                 let session = Session::new(lphandle);
                 let bot2 = bot.clone();
                 feed::fetch_feed(session, gen_ua(&bot), feed_link)
-                    .map(move |feed| (bot2, db, subscriber, chat_id, msg_id, feed))
+                    .map(move |feed| (bot2, db, subscriber, chat_id, msg_id, feed, lang))
                     .or_else(move |e| {
                         bot.edit_message_text(
                             chat_id,
                             msg_id,
-                            format!("订阅失败: {}", to_chinese_error_msg(e)),
+                            fill(lang.msgs().subscribe_failed, &[&to_error_msg(&e, lang)]),
                         ).send()
                             .then(|result| match result {
                                 Ok(_) => Err(None),
@@ -217,24 +365,27 @@ fn register_sub(bot: &telebot::RcBot, db: Database, lphandle: Handle) {
                     })
             },
         )
-        .and_then(|(bot, db, subscriber, chat_id, msg_id, feed)| {
+        .and_then(|(bot, db, subscriber, chat_id, msg_id, feed, lang)| {
             let source = feed.source.as_ref().unwrap();
             match db.subscribe(subscriber, source, &feed) {
-                Ok(_) => bot.edit_message_text(
-                    chat_id,
-                    msg_id,
-                    format!(
-                        "《<a href=\"{}\">{}</a>》订阅成功",
-                        EscapeUrl(source),
-                        Escape(&feed.title)
-                    ),
-                ).parse_mode("HTML")
-                    .disable_web_page_preview(true)
-                    .send(),
-                Err(Error(ErrorKind::AlreadySubscribed, _)) => {
-                    bot.edit_message_text(chat_id, msg_id, "已订阅过的 RSS".to_string())
+                Ok(_) => {
+                    remember_lang(&db, subscriber, lang);
+                    bot.edit_message_text(
+                        chat_id,
+                        msg_id,
+                        fill(
+                            lang.msgs().subscribed,
+                            &[&EscapeUrl(source).to_string(), &Escape(&feed.title).to_string()],
+                        ),
+                    ).parse_mode("HTML")
+                        .disable_web_page_preview(true)
                         .send()
                 }
+                Err(Error(ErrorKind::AlreadySubscribed, _)) => bot.edit_message_text(
+                    chat_id,
+                    msg_id,
+                    lang.msgs().already_subscribed.to_string(),
+                ).send(),
                 Err(e) => {
                     log_error(&e);
                     bot.edit_message_text(chat_id, msg_id, format!("error: {}", e))
@@ -257,6 +408,7 @@ fn register_unsub(bot: &telebot::RcBot, db: Database) {
     let handle = bot.new_cmd("/unsub")
         .map_err(Some)
         .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
             let text = msg.text.unwrap();
             let args: Vec<&str> = text.split_whitespace().collect();
             let feed_link: &str;
@@ -269,15 +421,13 @@ fn register_unsub(bot: &telebot::RcBot, db: Database) {
                 2 => {
                     let channel = args[0];
                     let channel_id =
-                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id);
+                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
                     subscriber = future::Either::B(channel_id);
                     feed_link = args[1];
                 }
                 _ => {
-                    let r = bot.message(
-                        msg.chat.id,
-                        "使用方法: /unsub [Channel ID] <RSS URL>".to_string(),
-                    ).send()
+                    let r = bot.message(msg.chat.id, lang.msgs().unsub_usage.to_string())
+                        .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
                             Err(e) => Err(Some(e)),
@@ -286,7 +436,7 @@ fn register_unsub(bot: &telebot::RcBot, db: Database) {
                 }
             }
             let db = db.clone();
-            let feed_link = feed_link.to_owned();
+            let feed_link = canonicalize(feed_link);
             let chat_id = msg.chat.id;
             let r = subscriber
                 .then(|result| match result {
@@ -294,23 +444,22 @@ fn register_unsub(bot: &telebot::RcBot, db: Database) {
                     Ok(None) => Err(None),
                     Err(err) => Err(Some(err)),
                 })
-                .map(move |subscriber| (bot, db, subscriber, feed_link, chat_id));
+                .map(move |subscriber| (bot, db, subscriber, feed_link, chat_id, lang));
             future::Either::B(r)
         })
-        .and_then(|(bot, db, subscriber, feed_link, chat_id)| {
+        .and_then(|(bot, db, subscriber, feed_link, chat_id, lang)| {
             match db.unsubscribe(subscriber, &feed_link) {
                 Ok(feed) => bot.message(
                     chat_id,
-                    format!(
-                        "《<a href=\"{}\">{}</a>》退订成功",
-                        EscapeUrl(&feed.link),
-                        Escape(&feed.title)
+                    fill(
+                        lang.msgs().unsubscribed,
+                        &[&EscapeUrl(&feed.link).to_string(), &Escape(&feed.title).to_string()],
                     ),
                 ).parse_mode("HTML")
                     .disable_web_page_preview(true)
                     .send(),
                 Err(Error(ErrorKind::NotSubscribed, _)) => {
-                    bot.message(chat_id, "未订阅过的 RSS".to_string())
+                    bot.message(chat_id, lang.msgs().not_subscribed.to_string())
                         .send()
                 }
                 Err(e) => {
@@ -334,37 +483,33 @@ fn register_unsubthis(bot: &telebot::RcBot, db: Database) {
     let handle = bot.new_cmd("/unsubthis")
         .map_err(Some)
         .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
             if let Some(reply_msg) = msg.reply_to_message {
-                Ok((bot, db.clone(), msg.chat.id, reply_msg))
+                Ok((bot, db.clone(), msg.chat.id, reply_msg, lang))
             } else {
                 Err((bot, msg.chat.id))
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(
-                        chat_id,
-                        "使用方法: \
-                         使用此命令回复想要退订的 RSS 消息即可退订,\
-                         不支持 Channel"
-                            .to_string(),
-                    ).send()
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().unsubthis_usage.to_string())
+                        .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
                             Err(e) => Err(Some(e)),
                         })
                 })
         })
-        .and_then(|(bot, db, chat_id, reply_msg)| {
+        .and_then(|(bot, db, chat_id, reply_msg, lang)| {
             if let Some(m) = reply_msg.text {
                 if let Some(title) = m.lines().next() {
-                    Ok((bot, db, chat_id, title.to_string()))
+                    Ok((bot, db, chat_id, title.to_string(), lang))
                 } else {
                     Err((bot, chat_id))
                 }
             } else {
                 Err((bot, chat_id))
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(chat_id, "无法识别的消息".to_string())
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().unrecognized_message.to_string())
                         .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
@@ -372,7 +517,7 @@ fn register_unsubthis(bot: &telebot::RcBot, db: Database) {
                         })
                 })
         })
-        .and_then(|(bot, db, chat_id, title)| {
+        .and_then(|(bot, db, chat_id, title, lang)| {
             if let Some(feed_link) = db.get_subscribed_feeds(chat_id)
                 .unwrap_or_default()
                 .iter()
@@ -380,12 +525,12 @@ fn register_unsubthis(bot: &telebot::RcBot, db: Database) {
                 .map(|feed| feed.link.clone())
                 .next()
             {
-                Ok((bot, db, chat_id, feed_link))
+                Ok((bot, db, chat_id, feed_link, lang))
             } else {
                 Err((bot, chat_id))
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(chat_id, "无法找到此订阅".to_string())
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().subscription_not_found.to_string())
                         .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
@@ -393,14 +538,13 @@ fn register_unsubthis(bot: &telebot::RcBot, db: Database) {
                         })
                 })
         })
-        .and_then(|(bot, db, chat_id, feed_link)| {
+        .and_then(|(bot, db, chat_id, feed_link, lang)| {
             match db.unsubscribe(chat_id, &feed_link) {
                 Ok(feed) => bot.message(
                     chat_id,
-                    format!(
-                        "《<a href=\"{}\">{}</a>》退订成功",
-                        EscapeUrl(&feed.link),
-                        Escape(&feed.title)
+                    fill(
+                        lang.msgs().unsubscribed,
+                        &[&EscapeUrl(&feed.link).to_string(), &Escape(&feed.title).to_string()],
                     ),
                 ).parse_mode("HTML")
                     .disable_web_page_preview(true)
@@ -427,6 +571,7 @@ fn check_channel<'a>(
     channel: &str,
     chat_id: i64,
     user_id: i64,
+    lang: Lang,
 ) -> impl Future<Item = Option<i64>, Error = telebot::Error> + 'a {
     let channel = channel
         .parse::<i64>()
@@ -446,19 +591,20 @@ fn check_channel<'a>(
         });
     let bot = bot.clone();
     async_block! {
-        let msg = await!(bot.message(chat_id, "正在验证 Channel".to_string()).send())?.1;
+        let msgs = lang.msgs();
+        let msg = await!(bot.message(chat_id, msgs.verifying_channel.to_string()).send())?.1;
         let msg_id = msg.message_id;
         let channel = match await!(bot.get_chat(channel).send()) {
             Ok((_, channel)) => channel,
             Err(telebot::Error::Telegram(_, err_msg, _)) => {
-                let msg = format!("无法找到目标 Channel: {}", err_msg);
+                let msg = fill(msgs.channel_not_found, &[&err_msg]);
                 await!(bot.edit_message_text(chat_id, msg_id, msg).send())?;
                 return Ok(None);
             }
             Err(e) => return Err(e),
         };
         if channel.kind != "channel" {
-            let msg = "目标需为 Channel".to_string();
+            let msg = msgs.not_a_channel.to_string();
             await!(bot.edit_message_text(chat_id, msg_id, msg).send())?;
             return Ok(None);
         }
@@ -470,7 +616,7 @@ fn check_channel<'a>(
                 .map(|member| member.user.id)
                 .collect::<Vec<i64>>(),
             Err(telebot::Error::Telegram(_, err_msg, _)) => {
-                let msg = format!("请先将本 Bot 加入目标 Channel并设为管理员: {}", err_msg);
+                let msg = fill(msgs.bot_not_in_channel, &[&err_msg]);
                 await!(bot.edit_message_text(chat_id, msg_id, msg).send())?;
                 return Ok(None);
             }
@@ -478,13 +624,13 @@ fn check_channel<'a>(
         };
 
         if !admins_list.contains(&bot.inner.id) {
-            let msg = "请将本 Bot 设为管理员".to_string();
+            let msg = msgs.bot_not_admin.to_string();
             await!(bot.edit_message_text(chat_id, msg_id, msg).send())?;
             return Ok(None);
         }
 
         if !admins_list.contains(&user_id) {
-            let msg = "该命令只能由 Channel 管理员使用".to_string();
+            let msg = msgs.user_not_admin.to_string();
             await!(bot.edit_message_text(chat_id, msg_id, msg).send())?;
             return Ok(None);
         }
@@ -499,6 +645,7 @@ fn register_export(bot: &telebot::RcBot, db: Database) {
     let handle = bot.new_cmd("/export")
         .map_err(Some)
         .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
             let text = msg.text.unwrap();
             let args: Vec<&str> = text.split_whitespace().collect();
             let subscriber: future::Either<_, _>;
@@ -509,14 +656,12 @@ fn register_export(bot: &telebot::RcBot, db: Database) {
                 1 => {
                     let channel = args[0];
                     let channel_id =
-                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id);
+                        check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
                     subscriber = future::Either::B(channel_id);
                 }
                 _ => {
-                    let r = bot.message(
-                        msg.chat.id,
-                        "使用方法: /export <Channel ID>".to_string(),
-                    ).send()
+                    let r = bot.message(msg.chat.id, lang.msgs().export_usage.to_string())
+                        .send()
                         .then(|result| match result {
                             Ok(_) => Err(None),
                             Err(e) => Err(Some(e)),
@@ -532,16 +677,16 @@ fn register_export(bot: &telebot::RcBot, db: Database) {
                     Ok(None) => Err(None),
                     Err(err) => Err(Some(err)),
                 })
-                .map(move |subscriber| (bot, db, subscriber, chat_id));
+                .map(move |subscriber| (bot, db, subscriber, chat_id, lang));
             future::Either::B(r)
         })
-        .and_then(|(bot, db, subscriber, chat_id)| {
+        .and_then(|(bot, db, subscriber, chat_id, lang)| {
             match db.get_subscribed_feeds(subscriber) {
-                Some(feeds) => Ok((bot, chat_id, feeds)),
+                Some(feeds) => Ok((bot, subscriber, chat_id, feeds)),
                 None => Err((bot, chat_id)),
             }.into_future()
-                .or_else(|(bot, chat_id)| {
-                    bot.message(chat_id, "订阅列表为空".to_string())
+                .or_else(move |(bot, chat_id)| {
+                    bot.message(chat_id, lang.msgs().empty_list.to_string())
                         .send()
                         .then(|r| match r {
                             Ok(_) => Err(None),
@@ -549,10 +694,10 @@ fn register_export(bot: &telebot::RcBot, db: Database) {
                         })
                 })
         })
-        .and_then(|(bot, chat_id, feeds)| {
+        .and_then(|(bot, subscriber, chat_id, feeds)| {
             bot.document(
                 chat_id,
-                File::new("feeds.opml".into(), to_opml(feeds).into_bytes()),
+                File::new("feeds.opml".into(), to_opml(feeds, subscriber).into_bytes()),
             ).send()
                 .map_err(Some)
         })
@@ -566,3 +711,632 @@ fn register_export(bot: &telebot::RcBot, db: Database) {
 
     bot.register(handle);
 }
+
+enum FilterAction {
+    Show,
+    Include(String),
+    Exclude(String),
+    Clear,
+}
+
+fn format_filter(feed_link: &str, filter: &Filter, lang: Lang) -> String {
+    let feed_link = Escape(feed_link).to_string();
+    if filter.is_empty() {
+        return fill(lang.msgs().no_filter, &[&feed_link]);
+    }
+    fill(
+        lang.msgs().filter_rules,
+        &[
+            &feed_link,
+            &Escape(&filter.include.join(", ")).to_string(),
+            &Escape(&filter.exclude.join(", ")).to_string(),
+        ],
+    )
+}
+
+fn register_filter(bot: &telebot::RcBot, db: Database) {
+    let handle = bot.new_cmd("/filter")
+        .map_err(Some)
+        .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
+            let text = msg.text.unwrap();
+            let args: Vec<&str> = text.split_whitespace().collect();
+            let is_action = |s: &str| s == "include" || s == "exclude" || s == "clear";
+            let (channel, args) = if args.len() >= 2 && !is_action(args[1]) {
+                (Some(args[0]), &args[1..])
+            } else {
+                (None, &args[..])
+            };
+            let action = match args.len() {
+                0 => None,
+                1 => Some(FilterAction::Show),
+                2 if args[1] == "clear" => Some(FilterAction::Clear),
+                2 => None,
+                _ if args[1] == "include" => Some(FilterAction::Include(args[2..].join(" "))),
+                _ if args[1] == "exclude" => Some(FilterAction::Exclude(args[2..].join(" "))),
+                _ => None,
+            };
+            let action = match action {
+                Some(action) => action,
+                None => {
+                    let r = bot.message(msg.chat.id, lang.msgs().filter_usage.to_string())
+                        .send()
+                        .then(|result| match result {
+                            Ok(_) => Err(None),
+                            Err(e) => Err(Some(e)),
+                        });
+                    return future::Either::A(r);
+                }
+            };
+            let subscriber = if let Some(channel) = channel {
+                let channel_id =
+                    check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
+                future::Either::B(channel_id)
+            } else {
+                future::Either::A(future::ok(Some(msg.chat.id)))
+            };
+            let db = db.clone();
+            let feed_link = args[0].to_owned();
+            let chat_id = msg.chat.id;
+            let r = subscriber
+                .then(|result| match result {
+                    Ok(Some(ok)) => Ok(ok),
+                    Ok(None) => Err(None),
+                    Err(err) => Err(Some(err)),
+                })
+                .map(move |subscriber| (bot, db, subscriber, feed_link, action, chat_id, lang));
+            future::Either::B(r)
+        })
+        .and_then(|(bot, db, subscriber, feed_link, action, chat_id, lang)| {
+            let r = db.get_options(subscriber, &feed_link).and_then(|mut options| {
+                match action {
+                    FilterAction::Show => return Ok(options.filter),
+                    FilterAction::Include(rule) => options.filter.add_include(&rule)?,
+                    FilterAction::Exclude(rule) => options.filter.add_exclude(&rule)?,
+                    FilterAction::Clear => options.filter = Filter::default(),
+                }
+                let filter = options.filter.clone();
+                db.set_options(subscriber, &feed_link, options)?;
+                Ok(filter)
+            });
+            match r {
+                Ok(filter) => bot.message(chat_id, format_filter(&feed_link, &filter, lang))
+                    .parse_mode("HTML")
+                    .disable_web_page_preview(true)
+                    .send(),
+                Err(Error(ErrorKind::NotSubscribed, _)) => {
+                    bot.message(chat_id, lang.msgs().not_subscribed.to_string())
+                        .send()
+                }
+                Err(Error(ErrorKind::Regex(e), _)) => {
+                    bot.message(chat_id, fill(lang.msgs().invalid_regex, &[&e.to_string()]))
+                        .send()
+                }
+                Err(e) => {
+                    log_error(&e);
+                    bot.message(chat_id, format!("error: {}", e)).send()
+                }
+            }.map_err(Some)
+        })
+        .then(|result| match result {
+            Err(Some(err)) => {
+                error!("telebot: {:?}", err);
+                Ok::<(), ()>(())
+            }
+            _ => Ok(()),
+        });
+
+    bot.register(handle);
+}
+
+/// An on/off option of subscriptions, set with `<cmd> [Channel ID] <RSS URL> [on|off]`
+struct Switch {
+    cmd: &'static str,
+    option: fn(&mut Options) -> &mut bool,
+    /// The usage, on and off messages
+    msgs: fn(&'static Messages) -> (&'static str, &'static str, &'static str),
+}
+
+const EXCERPT: Switch = Switch {
+    cmd: "/excerpt",
+    option: excerpt_option,
+    msgs: excerpt_msgs,
+};
+
+fn excerpt_option(options: &mut Options) -> &mut bool {
+    &mut options.excerpt
+}
+
+fn excerpt_msgs(msgs: &'static Messages) -> (&'static str, &'static str, &'static str) {
+    (msgs.excerpt_usage, msgs.excerpt_on, msgs.excerpt_off)
+}
+
+const MEDIA: Switch = Switch {
+    cmd: "/media",
+    option: media_option,
+    msgs: media_msgs,
+};
+
+fn media_option(options: &mut Options) -> &mut bool {
+    &mut options.media
+}
+
+fn media_msgs(msgs: &'static Messages) -> (&'static str, &'static str, &'static str) {
+    (msgs.media_usage, msgs.media_on, msgs.media_off)
+}
+
+fn register_switch(bot: &telebot::RcBot, db: Database, Switch { cmd, option, msgs }: Switch) {
+    let handle = bot.new_cmd(cmd)
+        .map_err(Some)
+        .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
+            let text = msg.text.unwrap();
+            let args: Vec<&str> = text.split_whitespace().collect();
+            let is_switch = |s: &str| s == "on" || s == "off";
+            let (channel, args) = if args.len() == 3 || (args.len() == 2 && !is_switch(args[1])) {
+                (Some(args[0]), &args[1..])
+            } else {
+                (None, &args[..])
+            };
+            let switch = match args.len() {
+                1 => None,
+                2 if is_switch(args[1]) => Some(args[1] == "on"),
+                _ => {
+                    let (usage, _, _) = msgs(lang.msgs());
+                    let r = bot.message(msg.chat.id, usage.to_string())
+                        .send()
+                        .then(|result| match result {
+                            Ok(_) => Err(None),
+                            Err(e) => Err(Some(e)),
+                        });
+                    return future::Either::A(r);
+                }
+            };
+            let subscriber = if let Some(channel) = channel {
+                let channel_id =
+                    check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
+                future::Either::B(channel_id)
+            } else {
+                future::Either::A(future::ok(Some(msg.chat.id)))
+            };
+            let db = db.clone();
+            let feed_link = args[0].to_owned();
+            let chat_id = msg.chat.id;
+            let r = subscriber
+                .then(|result| match result {
+                    Ok(Some(ok)) => Ok(ok),
+                    Ok(None) => Err(None),
+                    Err(err) => Err(Some(err)),
+                })
+                .map(move |subscriber| (bot, db, subscriber, feed_link, switch, chat_id, lang));
+            future::Either::B(r)
+        })
+        .and_then(move |(bot, db, subscriber, feed_link, switch, chat_id, lang)| {
+            let r = db.get_options(subscriber, &feed_link).and_then(|mut options| {
+                if let Some(switch) = switch {
+                    *option(&mut options) = switch;
+                    db.set_options(subscriber, &feed_link, options.clone())?;
+                }
+                Ok(*option(&mut options))
+            });
+            match r {
+                Ok(on) => {
+                    let (_, on_msg, off_msg) = msgs(lang.msgs());
+                    let template = if on { on_msg } else { off_msg };
+                    bot.message(chat_id, fill(template, &[&Escape(&feed_link).to_string()]))
+                        .parse_mode("HTML")
+                        .disable_web_page_preview(true)
+                        .send()
+                }
+                Err(Error(ErrorKind::NotSubscribed, _)) => {
+                    bot.message(chat_id, lang.msgs().not_subscribed.to_string())
+                        .send()
+                }
+                Err(e) => {
+                    log_error(&e);
+                    bot.message(chat_id, format!("error: {}", e)).send()
+                }
+            }.map_err(Some)
+        })
+        .then(|result| match result {
+            Err(Some(err)) => {
+                error!("telebot: {:?}", err);
+                Ok::<(), ()>(())
+            }
+            _ => Ok(()),
+        });
+
+    bot.register(handle);
+}
+
+fn register_lang(bot: &telebot::RcBot, db: Database) {
+    let handle = bot.new_cmd("/lang")
+        .map_err(Some)
+        .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
+            let text = msg.text.unwrap();
+            let args: Vec<&str> = text.split_whitespace().collect();
+            let is_lang = |s: &str| LANGS.iter().any(|lang| lang.code() == s);
+            let (channel, new_lang) = match args.len() {
+                0 => (None, None),
+                1 if is_lang(args[0]) => (None, Lang::from_code(args[0])),
+                1 => (Some(args[0]), None),
+                2 if is_lang(args[1]) => (Some(args[0]), Lang::from_code(args[1])),
+                _ => {
+                    let r = bot.message(msg.chat.id, lang.msgs().lang_usage.to_string())
+                        .send()
+                        .then(|result| match result {
+                            Ok(_) => Err(None),
+                            Err(e) => Err(Some(e)),
+                        });
+                    return future::Either::A(r);
+                }
+            };
+            let subscriber = if let Some(channel) = channel {
+                let channel_id =
+                    check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
+                future::Either::B(channel_id)
+            } else {
+                future::Either::A(future::ok(Some(msg.chat.id)))
+            };
+            let db = db.clone();
+            let chat_id = msg.chat.id;
+            let r = subscriber
+                .then(|result| match result {
+                    Ok(Some(ok)) => Ok(ok),
+                    Ok(None) => Err(None),
+                    Err(err) => Err(Some(err)),
+                })
+                .map(move |subscriber| (bot, db, subscriber, new_lang, chat_id, lang));
+            future::Either::B(r)
+        })
+        .and_then(|(bot, db, subscriber, new_lang, chat_id, lang)| {
+            let r = match new_lang {
+                Some(new_lang) => {
+                    let mut settings = db.get_chat_settings(subscriber);
+                    settings.lang = Some(new_lang);
+                    db.set_chat_settings(subscriber, settings)
+                        .map(|_| fill(new_lang.msgs().lang_set, &[new_lang.msgs().name]))
+                }
+                None => {
+                    // channels don't have a user to follow
+                    let default = if subscriber == chat_id {
+                        lang
+                    } else {
+                        Lang::default()
+                    };
+                    let current = db.get_lang(subscriber, default);
+                    let available: Vec<&str> = LANGS.iter().map(|lang| lang.code()).collect();
+                    Ok(fill(
+                        lang.msgs().current_lang,
+                        &[current.msgs().name, current.code(), &available.join(", ")],
+                    ))
+                }
+            };
+            match r {
+                Ok(text) => bot.message(chat_id, text).send(),
+                Err(e) => {
+                    log_error(&e);
+                    bot.message(chat_id, format!("error: {}", e)).send()
+                }
+            }.map_err(Some)
+        })
+        .then(|result| match result {
+            Err(Some(err)) => {
+                error!("telebot: {:?}", err);
+                Ok::<(), ()>(())
+            }
+            _ => Ok(()),
+        });
+
+    bot.register(handle);
+}
+
+/// A setting of a chat, set with `<cmd> [Channel ID] [value]`
+struct ChatSetting {
+    cmd: &'static str,
+    usage: fn(&'static Messages) -> &'static str,
+    /// Sets the value, `false` if it's not valid
+    set: fn(&mut ChatSettings, &str) -> bool,
+    /// Describes the current value
+    show: fn(&ChatSettings, Lang) -> String,
+}
+
+const DIGEST: ChatSetting = ChatSetting {
+    cmd: "/digest",
+    usage: digest_usage,
+    set: set_digest,
+    show: show_digest,
+};
+
+fn digest_usage(msgs: &'static Messages) -> &'static str {
+    msgs.digest_usage
+}
+
+fn set_digest(settings: &mut ChatSettings, value: &str) -> bool {
+    match parse_digest(value) {
+        Some(digest) => {
+            settings.digest = digest;
+            true
+        }
+        None => false,
+    }
+}
+
+fn show_digest(settings: &ChatSettings, lang: Lang) -> String {
+    let msgs = lang.msgs();
+    match settings.digest {
+        None => msgs.digest_off.to_string(),
+        Some(Digest::Hourly) => msgs.digest_hourly.to_string(),
+        Some(Digest::Daily(minute)) => fill(
+            msgs.digest_daily,
+            &[
+                &format_daily_time(minute),
+                &format!("UTC{}", settings.offset()),
+            ],
+        ),
+    }
+}
+
+const TIMEZONE: ChatSetting = ChatSetting {
+    cmd: "/timezone",
+    usage: timezone_usage,
+    set: set_timezone,
+    show: show_timezone,
+};
+
+fn timezone_usage(msgs: &'static Messages) -> &'static str {
+    msgs.timezone_usage
+}
+
+fn set_timezone(settings: &mut ChatSettings, value: &str) -> bool {
+    match parse_utc_offset(value) {
+        Some(offset) => {
+            settings.utc_offset = Some(offset);
+            true
+        }
+        None => false,
+    }
+}
+
+fn show_timezone(settings: &ChatSettings, lang: Lang) -> String {
+    fill(
+        lang.msgs().current_timezone,
+        &[&format!("UTC{}", settings.offset())],
+    )
+}
+
+fn register_chat_setting(
+    bot: &telebot::RcBot,
+    db: Database,
+    ChatSetting {
+        cmd,
+        usage,
+        set,
+        show,
+    }: ChatSetting,
+) {
+    let handle = bot.new_cmd(cmd)
+        .map_err(Some)
+        .and_then(move |(bot, msg)| {
+            let lang = msg_lang(&db, &msg);
+            let text = msg.text.unwrap();
+            let args: Vec<&str> = text.split_whitespace().collect();
+            // a value may have spaces, the first argument is a channel if the whole is not valid
+            let is_value = |args: &[&str]| set(&mut ChatSettings::default(), &args.join(" "));
+            let (channel, value) = if args.is_empty() || is_value(&args) {
+                (None, &args[..])
+            } else {
+                (Some(args[0]), &args[1..])
+            };
+            if !value.is_empty() && !is_value(value) {
+                let r = bot.message(msg.chat.id, usage(lang.msgs()).to_string())
+                    .send()
+                    .then(|result| match result {
+                        Ok(_) => Err(None),
+                        Err(e) => Err(Some(e)),
+                    });
+                return future::Either::A(r);
+            }
+            let value = if value.is_empty() {
+                None
+            } else {
+                Some(value.join(" "))
+            };
+            let subscriber = if let Some(channel) = channel {
+                let channel_id =
+                    check_channel(&bot, channel, msg.chat.id, msg.from.unwrap().id, lang);
+                future::Either::B(channel_id)
+            } else {
+                future::Either::A(future::ok(Some(msg.chat.id)))
+            };
+            let db = db.clone();
+            let chat_id = msg.chat.id;
+            let r = subscriber
+                .then(|result| match result {
+                    Ok(Some(ok)) => Ok(ok),
+                    Ok(None) => Err(None),
+                    Err(err) => Err(Some(err)),
+                })
+                .map(move |subscriber| (bot, db, subscriber, value, chat_id, lang));
+            future::Either::B(r)
+        })
+        .and_then(move |(bot, db, subscriber, value, chat_id, lang)| {
+            let mut settings = db.get_chat_settings(subscriber);
+            let r = match value {
+                Some(value) => {
+                    set(&mut settings, &value);
+                    db.set_chat_settings(subscriber, settings.clone())
+                }
+                None => Ok(()),
+            };
+            match r {
+                Ok(()) => bot.message(chat_id, show(&settings, lang)).send(),
+                Err(e) => {
+                    log_error(&e);
+                    bot.message(chat_id, format!("error: {}", e)).send()
+                }
+            }.map_err(Some)
+        })
+        .then(|result| match result {
+            Err(Some(err)) => {
+                error!("telebot: {:?}", err);
+                Ok::<(), ()>(())
+            }
+            _ => Ok(()),
+        });
+
+    bot.register(handle);
+}
+
+fn register_import(bot: &telebot::RcBot, db: Database, limiter: RateLimiter, lphandle: Handle) {
+    let handle = bot.new_cmd("/import")
+        .and_then(move |(bot, msg)| {
+            import_opml(bot, db.clone(), limiter.clone(), lphandle.clone(), msg)
+        })
+        .then(|result| {
+            if let Err(err) = result {
+                error!("telebot: {:?}", err);
+            }
+            Ok::<(), ()>(())
+        });
+
+    bot.register(handle);
+}
+
+#[async]
+fn import_opml(
+    bot: telebot::RcBot,
+    db: Database,
+    limiter: RateLimiter,
+    lphandle: Handle,
+    msg: Message,
+) -> ::std::result::Result<(), telebot::Error> {
+    let lang = msg_lang(&db, &msg);
+    let msgs = lang.msgs();
+    let chat_id = msg.chat.id;
+    let user_id = msg.from.as_ref().map(|user| user.id);
+    let args: Vec<String> = msg.text
+        .as_ref()
+        .map(|text| text.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    // the file can be sent with the command as its caption, or be replied to
+    let document = match msg.document {
+        Some(document) => Some(document),
+        None => msg.reply_to_message.and_then(|msg| msg.document),
+    };
+    let file_id = document
+        .map(|document| document.file_id)
+        .filter(|_| args.len() <= 1);
+    let file_id = match file_id {
+        Some(file_id) => file_id,
+        None => {
+            await!(bot.message(chat_id, msgs.import_usage.to_string()).send())?;
+            return Ok(());
+        }
+    };
+    let channel = args.into_iter().next();
+    let subscriber = match channel {
+        Some(channel) => {
+            let channel_id = check_channel(&bot, &channel, chat_id, user_id.unwrap(), lang);
+            match await!(channel_id)? {
+                Some(channel_id) => channel_id,
+                None => return Ok(()),
+            }
+        }
+        None => chat_id,
+    };
+
+    let file_path = match await!(bot.get_file(file_id).send()) {
+        Ok((_, file)) => file.file_path,
+        Err(telebot::Error::Telegram(_, err_msg, _)) => {
+            let msg = fill(msgs.import_invalid, &[&err_msg]);
+            await!(bot.message(chat_id, msg).send())?;
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    let data = match file_path {
+        Some(file_path) => await!(bot.inner.download_file(&file_path))?,
+        None => Vec::new(),
+    };
+    let outlines = match from_opml(Cursor::new(data)) {
+        Ok(outlines) => outlines,
+        Err(e) => {
+            let msg = fill(msgs.import_invalid, &[&e.to_string()]);
+            await!(bot.message(chat_id, msg).send())?;
+            return Ok(());
+        }
+    };
+    if outlines.is_empty() {
+        await!(bot.message(chat_id, msgs.import_empty.to_string()).send())?;
+        return Ok(());
+    }
+
+    let msg = fill(msgs.importing, &[&outlines.len().to_string()]);
+    let msg_id = await!(bot.message(chat_id, msg).send())?.1.message_id;
+    let session = Session::new(lphandle);
+    let mut results = Vec::with_capacity(outlines.len());
+    for outline in outlines {
+        let result = if db.is_subscribed(subscriber, &outline.link) {
+            Err(msgs.already_subscribed.to_string())
+        } else {
+            let feed = feed::fetch_feed(session.clone(), gen_ua(&bot), outline.link.clone());
+            match await!(feed) {
+                Ok(feed) => subscribe_outline(&db, subscriber, &outline, &feed, lang),
+                Err(e) => Err(to_error_msg(&e, lang)),
+            }
+        };
+        results.push((outline, result));
+    }
+
+    await!(bot.delete_message(chat_id, msg_id).send())?;
+    let reply = format_and_split_msgs(
+        msgs.import_result.to_string(),
+        &results,
+        |&(ref outline, ref result)| match *result {
+            Ok(ref title) => format!(
+                "✔ <a href=\"{}\">{}</a>",
+                EscapeUrl(&outline.link),
+                Escape(title)
+            ),
+            Err(ref e) => format!("✘ {}: {}", Escape(&outline.link), Escape(e)),
+        },
+    );
+    await!(send_multiple_messages(&bot, &limiter, chat_id, reply))?;
+    Ok(())
+}
+
+/// Returns the title of the feed, or why it can't be subscribed
+fn subscribe_outline(
+    db: &Database,
+    subscriber: i64,
+    outline: &Outline,
+    feed: &feed::RSS,
+    lang: Lang,
+) -> ::std::result::Result<String, String> {
+    let source = feed.source.as_ref().unwrap();
+    match db.subscribe(subscriber, source, feed) {
+        Ok(_) => (),
+        Err(Error(ErrorKind::AlreadySubscribed, _)) => {
+            return Err(lang.msgs().already_subscribed.to_string())
+        }
+        Err(e) => {
+            log_error(&e);
+            return Err(e.to_string());
+        }
+    }
+    remember_lang(db, subscriber, lang);
+    if let Some(ref filter) = outline.filter {
+        let mut options = db.get_options(subscriber, source).unwrap_or_default();
+        options.filter = filter.clone();
+        if let Err(e) = db.set_options(subscriber, source, options) {
+            log_error(&e);
+        }
+    }
+    if feed.title.is_empty() {
+        Ok(outline.title.clone().unwrap_or_else(|| source.to_owned()))
+    } else {
+        Ok(feed.title.clone())
+    }
+}
@@ -1,24 +1,35 @@
-use std;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::path::Path;
+use std::cmp::{max, min, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hasher;
 use std::rc::Rc;
 
-use serde_json;
+use chrono::{FixedOffset, Utc};
+use siphasher::sip::SipHasher;
 
+use canonical::{canonicalize, feed_key};
+use digest::Digest;
 use errors::*;
 use feed;
+use filter::Filter;
+use i18n::Lang;
+use storage::{Backend, Change, JsonStorage, State, Storage};
 
-fn get_hash<T: Hash>(t: &T) -> u64 {
-    let mut hasher = std::collections::hash_map::DefaultHasher::default();
-    t.hash(&mut hasher);
+/// Version of the item hashes, feeds saved with an older one are re-seeded on next update,
+/// 2 since item links are resolved against `xml:base`, which changes the hashes of some
+const HASH_VERSION: u32 = 2;
+/// Up to how many hashes are kept for feeds pushed by a hub, which may send only the new items
+const MAX_HASH_LIST_LEN: usize = 256;
+
+/// SipHash-2-4 with fixed keys over the UTF-8 bytes, it's persisted so it must be stable
+pub fn get_hash(s: &str) -> u64 {
+    let mut hasher = SipHasher::new_with_keys(0, 0);
+    hasher.write(s.as_bytes());
     hasher.finish()
 }
 
-type FeedID = u64;
-type SubscriberID = i64;
+pub type FeedID = u64;
+pub type SubscriberID = i64;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Feed {
@@ -27,26 +38,194 @@ pub struct Feed {
     pub error_count: u32,
     pub subscribers: HashSet<SubscriberID>,
     hash_list: Vec<u64>,
+    #[serde(default)]
+    hash_version: u32,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    #[serde(default)]
+    pub options: HashMap<SubscriberID, Options>,
+    /// The WebSub subscription, if the feed has a hub and it's enabled
+    #[serde(default)]
+    pub hub: Option<Hub>,
+    /// Unix time of the last fetch that worked
+    #[serde(default)]
+    pub last_fetch: Option<i64>,
+    /// Seconds between the fetches, as last scheduled
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// The last failed fetch, kept after the feed works again
+    #[serde(default)]
+    pub last_error: Option<FeedError>,
+    /// Unix time of the first of the failures in a row, `None` if the last fetch worked
+    #[serde(default)]
+    pub failing_since: Option<i64>,
+    /// Unix time of the last fetch or push that had new items
+    #[serde(default)]
+    pub last_item: Option<i64>,
+}
+
+/// A failed fetch of a feed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedError {
+    /// Unix time of the failure
+    pub time: i64,
+    pub kind: FeedErrorKind,
+    /// The error as it's logged
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedErrorKind {
+    Network,
+    Http(u32),
+    Encoding,
+    /// Not a valid feed
+    Parse,
+    Other,
+}
+
+impl FeedError {
+    pub fn new(e: &Error, time: i64) -> FeedError {
+        let kind = match *e.kind() {
+            ErrorKind::Curl(_) | ErrorKind::TooManyRedirects => FeedErrorKind::Network,
+            ErrorKind::Http(code) => FeedErrorKind::Http(code),
+            ErrorKind::Utf8(_) => FeedErrorKind::Encoding,
+            ErrorKind::Xml(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::EOF
+            | ErrorKind::EmptyFeed
+            | ErrorKind::WebPage(_) => FeedErrorKind::Parse,
+            _ => FeedErrorKind::Other,
+        };
+        FeedError {
+            time: time,
+            kind: kind,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Settings of a single (subscriber, feed) pair
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Options {
+    #[serde(default)]
+    pub filter: Filter,
+    /// Add an excerpt of the content under the title
+    #[serde(default)]
+    pub excerpt: bool,
+    /// Send items with photos or audio as Telegram media
+    #[serde(default)]
+    pub media: bool,
+}
+
+/// Settings of a chat or channel, shared by all its subscriptions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatSettings {
+    /// Chosen with `/lang`, or the language of the first subscription
+    pub lang: Option<Lang>,
+    /// Chosen with `/digest`, updates are sent as they come if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+    /// Seconds east of UTC, chosen with `/timezone`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<i32>,
+    /// Updates waiting for the next digest, see `digest`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<PendingDigest>,
+}
+
+impl ChatSettings {
+    /// The time zone of the chat, UTC if it's not set
+    pub fn offset(&self) -> FixedOffset {
+        self.utc_offset
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or_else(|| FixedOffset::east(0))
+    }
+}
+
+/// Updates collected for the next digest of a chat
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingDigest {
+    /// Unix time of the first update, the digest is sent at the first scheduled time after it
+    pub since: i64,
+    pub items: Vec<DigestItem>,
+}
+
+/// An update in a digest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestItem {
+    /// Link of the feed, updates are grouped by it
+    pub feed: String,
+    /// Title of the feed
+    pub title: String,
+    /// The update as a line of HTML
+    pub line: String,
+}
+
+/// A message waiting to be delivered, it's removed after Telegram accepts it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub chat: SubscriberID,
+    pub text: String,
+    /// Sent in place of a text message, `text` becomes the caption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+}
+
+/// Media of an outbox entry, by URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Attachment {
+    Photo(String),
+    Audio(String),
+    /// An album, 2 to 10 photos
+    Photos(Vec<String>),
+}
+
+/// A message to be put into the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxMessage {
+    pub text: String,
+    pub attachment: Option<Attachment>,
+}
+
+impl From<String> for OutboxMessage {
+    fn from(text: String) -> Self {
+        OutboxMessage {
+            text: text,
+            attachment: None,
+        }
+    }
 }
 
+/// A WebSub subscription to the hub of a feed, see `websub`
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Hub {
+    pub url: String,
     pub callback: String,
+    /// Key of the HMAC signatures of pushed content
     pub secret: String,
+    /// Unix time of the last subscription request
+    pub requested_at: i64,
+    /// Unix time when the lease ends, `None` until the hub verifies the subscription
+    pub expires_at: Option<i64>,
 }
 
 #[derive(Debug)]
 struct DatabaseInner {
-    path: String,
+    storage: Box<Storage>,
     feeds: HashMap<FeedID, Feed>,
+    /// `feed_key` of the link of each feed, to find a feed however its link is written
+    feed_keys: HashMap<String, FeedID>,
     subscribers: HashMap<SubscriberID, HashSet<FeedID>>,
+    outbox: BTreeMap<u64, OutboxEntry>,
+    next_outbox_id: u64,
+    chats: HashMap<SubscriberID, ChatSettings>,
 }
 
 impl DatabaseInner {
-    fn get_all_feeds(&self) -> Vec<Feed> {
-        self.feeds.iter().map(|(_, v)| v.clone()).collect()
-    }
-
     fn get_all_subscribers(&self) -> Vec<SubscriberID> {
         self.subscribers.iter().map(|(k, _)| *k).collect()
     }
@@ -61,29 +240,46 @@ impl DatabaseInner {
         })
     }
 
-    fn inc_error_count(&mut self, rss_link: &str) -> u32 {
-        let feed_id = get_hash(&rss_link);
-        self.feeds
-            .get_mut(&feed_id)
-            .map(|feed| {
-                feed.error_count += 1;
-                feed.error_count
-            })
-            .unwrap_or_default()
+    /// Records a failed fetch, returns the time of the first of the failures in a row
+    /// and the time of the one before, which is the same for the first
+    fn record_error(&mut self, rss_link: &str, error: FeedError) -> Option<(i64, i64)> {
+        let feed_id = get_hash(rss_link);
+        let times = {
+            let feed = self.feeds.get_mut(&feed_id)?;
+            let previous = match feed.last_error {
+                Some(ref last_error) if feed.failing_since.is_some() => last_error.time,
+                _ => error.time,
+            };
+            let since = *feed.failing_since.get_or_insert(error.time);
+            feed.error_count += 1;
+            feed.last_error = Some(error);
+            (since, previous)
+        };
+        self.save_bookkeeping(&[rss_link]);
+        Some(times)
     }
 
     fn reset_error_count(&mut self, rss_link: &str) {
-        let feed_id = get_hash(&rss_link);
-        self.feeds
+        let feed_id = get_hash(rss_link);
+        let was_failing = self.feeds
             .get_mut(&feed_id)
-            .map(|feed| feed.error_count = 0)
+            .map(|feed| {
+                feed.error_count = 0;
+                feed.failing_since.take().is_some()
+            })
             .unwrap_or_default();
+        // saved only when it stops failing, or a restart would take it as failing since then
+        if was_failing {
+            self.save_bookkeeping(&[rss_link]);
+        }
     }
 
     fn is_subscribed(&self, subscriber: SubscriberID, rss_link: &str) -> bool {
+        let link = self.feed_link(rss_link);
+        let rss_link = link.as_str();
         self.subscribers
             .get(&subscriber)
-            .map(|feeds| feeds.contains(&get_hash(&rss_link)))
+            .map(|feeds| feeds.contains(&get_hash(rss_link)))
             .unwrap_or(false)
     }
 
@@ -93,7 +289,9 @@ impl DatabaseInner {
         rss_link: &str,
         rss: &feed::RSS,
     ) -> Result<()> {
-        let feed_id = get_hash(&rss_link);
+        let link = self.feed_link(rss_link);
+        let rss_link = link.as_str();
+        let feed_id = get_hash(rss_link);
         {
             let subscribed_feeds = self.subscribers
                 .entry(subscriber)
@@ -108,15 +306,28 @@ impl DatabaseInner {
                 title: rss.title.to_owned(),
                 error_count: 0,
                 hash_list: rss.items.iter().map(gen_item_hash).collect(),
+                hash_version: HASH_VERSION,
                 subscribers: HashSet::new(),
+                etag: None,
+                last_modified: None,
+                options: HashMap::new(),
+                hub: None,
+                last_fetch: None,
+                interval: None,
+                last_error: None,
+                failing_since: None,
+                last_item: None,
             });
             feed.subscribers.insert(subscriber);
         }
-        self.save()
+        self.feed_keys.entry(feed_key(rss_link)).or_insert(feed_id);
+        self.save(&[rss_link])
     }
 
     fn unsubscribe(&mut self, subscriber: SubscriberID, rss_link: &str) -> Result<Feed> {
-        let feed_id = get_hash(&rss_link);
+        let link = self.feed_link(rss_link);
+        let rss_link = link.as_str();
+        let feed_id = get_hash(rss_link);
 
         let clear_subscriber;
         if let Some(subscribed_feeds) = self.subscribers.get_mut(&subscriber) {
@@ -136,6 +347,7 @@ impl DatabaseInner {
         let clear_feed;
         if let Some(feed) = self.feeds.get_mut(&feed_id) {
             if feed.subscribers.remove(&subscriber) {
+                feed.options.remove(&subscriber);
                 clear_feed = feed.subscribers.is_empty();
                 result = feed.clone();
             } else {
@@ -145,9 +357,9 @@ impl DatabaseInner {
             return Err(ErrorKind::NotSubscribed.into());
         };
         if clear_feed {
-            self.feeds.remove(&feed_id);
+            self.remove(feed_id);
         }
-        self.save()?;
+        self.save(&[rss_link])?;
         Ok(result)
     }
 
@@ -159,26 +371,75 @@ impl DatabaseInner {
                 }
             })
             .unwrap_or_default();
+        // nobody to deliver to
+        let ids: Vec<u64> = self.outbox
+            .values()
+            .filter(|entry| entry.chat == subscriber)
+            .map(|entry| entry.id)
+            .collect();
+        self.remove_outbox(&ids);
+        if self.chats.remove(&subscriber).is_some() {
+            self.save_changes(&[Change::Chat(subscriber)])
+                .unwrap_or_default();
+        }
     }
 
     fn update_subscriber(&mut self, from: SubscriberID, to: SubscriberID) {
-        let feeds = self.subscribers.remove(&from).unwrap();
+        let mut moved = Vec::new();
+        for entry in self.outbox.values_mut().filter(|entry| entry.chat == from) {
+            entry.chat = to;
+            moved.push(entry.id);
+        }
+        let settings = self.chats.remove(&from);
+        let moved_settings = settings.is_some();
+        if let Some(settings) = settings {
+            self.chats.insert(to, settings);
+        }
+        // it may have pending messages without any subscription
+        let feeds = self.subscribers.remove(&from).unwrap_or_default();
+        let mut links = Vec::with_capacity(feeds.len());
         for feed_id in &feeds {
             let feed = self.feeds.get_mut(&feed_id).unwrap();
             feed.subscribers.remove(&from);
             feed.subscribers.insert(to);
+            if let Some(options) = feed.options.remove(&from) {
+                feed.options.insert(to, options);
+            }
+            links.push(feed.link.clone());
+        }
+        if !feeds.is_empty() {
+            self.subscribers.insert(to, feeds);
+        }
+        let mut changes: Vec<Change> = moved.into_iter().map(Change::Outbox).collect();
+        changes.extend(links.iter().map(|link| Change::Feed(link.as_str())));
+        if moved_settings {
+            changes.push(Change::Chat(from));
+            changes.push(Change::Chat(to));
         }
-        self.subscribers.insert(to, feeds);
+        self.save_changes(&changes).unwrap_or_default();
     }
 
+    /// Returns the new items, their hashes are saved by the following `commit_updates`
     fn update(&mut self, rss_link: &str, items: Vec<feed::Item>) -> Vec<feed::Item> {
-        let feed_id = get_hash(&rss_link);
+        let feed_id = get_hash(rss_link);
         if self.feeds.get(&feed_id).is_none() {
             return Vec::new();
         }
 
         self.reset_error_count(rss_link);
 
+        if self.feeds[&feed_id].hash_version != HASH_VERSION {
+            // hashes were made by an old `get_hash`, comparing with them
+            // would make every item look new, so just start over silently
+            {
+                let feed = self.feeds.get_mut(&feed_id).unwrap();
+                feed.hash_list = items.iter().map(gen_item_hash).collect();
+                feed.hash_version = HASH_VERSION;
+            }
+            self.save(&[rss_link]).unwrap_or_default();
+            return Vec::new();
+        }
+
         let mut result = Vec::new();
         let mut new_hash_list = Vec::new();
         let items_len = items.len();
@@ -191,8 +452,11 @@ impl DatabaseInner {
         }
         if !result.is_empty() {
             {
-                let max_size = items_len * 2;
                 let feed = self.feeds.get_mut(&feed_id).unwrap();
+                // content pushed by a hub may have only the new items,
+                // which must not push out the hashes of the others
+                let kept = feed.hash_list.len() + new_hash_list.len();
+                let max_size = max(items_len * 2, min(kept, MAX_HASH_LIST_LEN));
                 let mut append: Vec<u64> = feed.hash_list
                     .iter()
                     .take(max_size - new_hash_list.len())
@@ -200,26 +464,409 @@ impl DatabaseInner {
                     .collect();
                 new_hash_list.append(&mut append);
                 feed.hash_list = new_hash_list;
+                feed.last_item = Some(Utc::now().timestamp());
             }
-            self.save().unwrap_or_default();
         }
         result
     }
 
+    /// Saves the new hashes of `rss_link` together with the messages of its updates
+    /// and the updates for digests, so updates are neither lost nor sent twice if the bot stops
+    fn commit_updates(
+        &mut self,
+        rss_link: &str,
+        messages: Vec<(SubscriberID, OutboxMessage)>,
+        digests: Vec<(SubscriberID, DigestItem)>,
+    ) -> Result<()> {
+        let mut changes: Vec<Change> = self.push_outbox(messages);
+        changes.extend(self.push_digests(digests, Utc::now().timestamp()));
+        changes.push(Change::Feed(rss_link));
+        self.save_changes(&changes)
+    }
+
+    /// Adds updates to the pending digests, a digest started at `now` if there's none
+    fn push_digests(
+        &mut self,
+        items: Vec<(SubscriberID, DigestItem)>,
+        now: i64,
+    ) -> Vec<Change<'static>> {
+        let mut changes = Vec::new();
+        for (chat, item) in items {
+            self.chats
+                .entry(chat)
+                .or_insert_with(ChatSettings::default)
+                .pending
+                .get_or_insert_with(|| PendingDigest {
+                    since: now,
+                    items: Vec::new(),
+                })
+                .items
+                .push(item);
+            if !changes.contains(&Change::Chat(chat)) {
+                changes.push(Change::Chat(chat));
+            }
+        }
+        changes
+    }
+
+    /// Chats that have updates waiting for a digest
+    fn get_pending_digests(&self) -> Vec<(SubscriberID, ChatSettings)> {
+        self.chats
+            .iter()
+            .filter(|&(_, settings)| settings.pending.is_some())
+            .map(|(&chat, settings)| (chat, settings.clone()))
+            .collect()
+    }
+
+    /// Replaces the pending digest of `chat` with its messages in the outbox
+    fn commit_digest(&mut self, chat: SubscriberID, messages: Vec<String>) -> Result<()> {
+        if let Some(settings) = self.chats.get_mut(&chat) {
+            settings.pending = None;
+        }
+        let messages = messages.into_iter().map(|text| (chat, text.into())).collect();
+        let mut changes = self.push_outbox(messages);
+        changes.push(Change::Chat(chat));
+        self.save_changes(&changes)
+    }
+
+    fn push_outbox(
+        &mut self,
+        messages: Vec<(SubscriberID, OutboxMessage)>,
+    ) -> Vec<Change<'static>> {
+        let mut changes = Vec::with_capacity(messages.len());
+        for (chat, message) in messages {
+            let id = self.next_outbox_id;
+            self.next_outbox_id += 1;
+            self.outbox.insert(
+                id,
+                OutboxEntry {
+                    id: id,
+                    chat: chat,
+                    text: message.text,
+                    attachment: message.attachment,
+                },
+            );
+            changes.push(Change::Outbox(id));
+        }
+        changes
+    }
+
+    fn enqueue(&mut self, messages: Vec<(SubscriberID, String)>) -> Result<()> {
+        let messages = messages
+            .into_iter()
+            .map(|(chat, text)| (chat, text.into()))
+            .collect();
+        let changes = self.push_outbox(messages);
+        self.save_changes(&changes)
+    }
+
+    /// Chats that have messages waiting
+    fn get_outbox_chats(&self) -> Vec<SubscriberID> {
+        let mut chats: Vec<SubscriberID> = self.outbox.values().map(|entry| entry.chat).collect();
+        chats.sort();
+        chats.dedup();
+        chats
+    }
+
+    /// Messages waiting for `chat`, oldest first
+    fn get_outbox(&self, chat: SubscriberID) -> Vec<OutboxEntry> {
+        self.outbox
+            .values()
+            .filter(|entry| entry.chat == chat)
+            .cloned()
+            .collect()
+    }
+
+    fn remove_outbox(&mut self, ids: &[u64]) {
+        let mut changes = Vec::with_capacity(ids.len());
+        for id in ids {
+            if self.outbox.remove(id).is_some() {
+                changes.push(Change::Outbox(*id));
+            }
+        }
+        if !changes.is_empty() {
+            self.save_changes(&changes).unwrap_or_default();
+        }
+    }
+
     fn update_title(&mut self, rss_link: &str, new_title: &str) {
-        let feed_id = get_hash(&rss_link);
+        let feed_id = get_hash(rss_link);
+        let changed = match self.feeds.get_mut(&feed_id) {
+            Some(ref mut feed) if feed.title != new_title => {
+                feed.title = new_title.to_owned();
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.save_bookkeeping(&[rss_link]);
+        }
+    }
+
+    fn get_options(&self, subscriber: SubscriberID, rss_link: &str) -> Result<Options> {
+        let link = self.feed_link(rss_link);
+        let rss_link = link.as_str();
+        if !self.is_subscribed(subscriber, rss_link) {
+            return Err(ErrorKind::NotSubscribed.into());
+        }
+        let feed_id = get_hash(rss_link);
+        Ok(self.feeds[&feed_id]
+            .options
+            .get(&subscriber)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn set_options(
+        &mut self,
+        subscriber: SubscriberID,
+        rss_link: &str,
+        options: Options,
+    ) -> Result<()> {
+        let link = self.feed_link(rss_link);
+        let rss_link = link.as_str();
+        if !self.is_subscribed(subscriber, rss_link) {
+            return Err(ErrorKind::NotSubscribed.into());
+        }
+        let feed_id = get_hash(rss_link);
         self.feeds
             .get_mut(&feed_id)
-            .map(|feed| feed.title = new_title.to_owned())
-            .unwrap_or_default();
+            .unwrap()
+            .options
+            .insert(subscriber, options);
+        self.save(&[rss_link])
     }
 
-    fn save(&self) -> Result<()> {
-        let feeds_list: Vec<&Feed> = self.feeds.iter().map(|(_id, feed)| feed).collect();
-        let mut file =
-            File::create(&self.path).chain_err(|| ErrorKind::DatabaseSave(self.path.to_owned()))?;
-        serde_json::to_writer(&mut file, &feeds_list)
-            .chain_err(|| ErrorKind::DatabaseSave(self.path.to_owned()))
+    fn get_chat_settings(&self, chat: SubscriberID) -> ChatSettings {
+        self.chats.get(&chat).cloned().unwrap_or_default()
+    }
+
+    fn set_chat_settings(&mut self, chat: SubscriberID, settings: ChatSettings) -> Result<()> {
+        self.chats.insert(chat, settings);
+        self.save_changes(&[Change::Chat(chat)])
+    }
+
+    fn update_validators(&mut self, rss_link: &str, validators: feed::Validators) {
+        let feed_id = get_hash(rss_link);
+        let changed = match self.feeds.get_mut(&feed_id) {
+            Some(ref mut feed)
+                if feed.etag != validators.etag || feed.last_modified != validators.last_modified =>
+            {
+                feed.etag = validators.etag;
+                feed.last_modified = validators.last_modified;
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.save_bookkeeping(&[rss_link]);
+        }
+    }
+
+    /// Records a fetch that worked at `time` and the `interval` to the next one
+    fn record_fetch(&mut self, rss_link: &str, time: i64, interval: u64) {
+        let feed_id = get_hash(rss_link);
+        let found = self.feeds
+            .get_mut(&feed_id)
+            .map(|feed| {
+                feed.last_fetch = Some(time);
+                feed.interval = Some(interval);
+            })
+            .is_some();
+        if found {
+            self.save_bookkeeping(&[rss_link]);
+        }
+    }
+
+    fn get_fetch_times(&self) -> Vec<(FeedID, Option<i64>, Option<u64>)> {
+        self.feeds
+            .iter()
+            .map(|(&feed_id, feed)| (feed_id, feed.last_fetch, feed.interval))
+            .collect()
+    }
+
+    fn get_feed(&self, feed_id: FeedID) -> Option<Feed> {
+        self.feeds.get(&feed_id).cloned()
+    }
+
+    fn set_hub(&mut self, rss_link: &str, hub: Option<Hub>) -> Result<()> {
+        let feed_id = get_hash(rss_link);
+        match self.feeds.get_mut(&feed_id) {
+            Some(feed) => feed.hub = hub,
+            None => return Err(ErrorKind::NotSubscribed.into()),
+        }
+        self.save(&[rss_link])
+    }
+
+    /// The link of the feed `rss_link` is the same as, or else the canonical form of it
+    fn feed_link(&self, rss_link: &str) -> String {
+        if self.feeds.contains_key(&get_hash(rss_link)) {
+            return rss_link.to_owned();
+        }
+        let link = canonicalize(rss_link);
+        if self.feeds.contains_key(&get_hash(&link)) {
+            return link;
+        }
+        self.feed_keys
+            .get(&feed_key(&link))
+            .and_then(|feed_id| self.feeds.get(feed_id))
+            .map_or(link, |feed| feed.link.clone())
+    }
+
+    /// Merges the feeds of the same `feed_key` and moves the others to their canonical links,
+    /// for the feeds saved before links were canonicalized
+    fn canonicalize_feeds(&mut self) -> Result<()> {
+        let mut groups: HashMap<String, Vec<FeedID>> = HashMap::new();
+        for (&feed_id, feed) in &self.feeds {
+            groups
+                .entry(feed_key(&feed.link))
+                .or_insert_with(Vec::new)
+                .push(feed_id);
+        }
+        let mut changed = Vec::new();
+        for (_, feed_ids) in groups {
+            if feed_ids.len() == 1 {
+                let link = &self.feeds[&feed_ids[0]].link;
+                if canonicalize(link) == *link {
+                    continue;
+                }
+            }
+            let mut feeds: Vec<Feed> = feed_ids
+                .into_iter()
+                .filter_map(|feed_id| self.remove(feed_id))
+                .collect();
+            // HTTPS first, then the most subscribed
+            feeds.sort_by_key(|feed| {
+                (
+                    !feed.link.starts_with("https://"),
+                    Reverse(feed.subscribers.len()),
+                )
+            });
+            let mut merged = feeds.remove(0);
+            let old_link = merged.link.clone();
+            merged.link = canonicalize(&old_link);
+            if merged.link != old_link {
+                // the hub knows it by the id of the old link
+                merged.hub = None;
+            }
+            changed.push(old_link);
+            for feed in feeds {
+                info!("merging '{}' into '{}'", feed.link, merged.link);
+                changed.push(feed.link.clone());
+                merge_feed(&mut merged, feed);
+            }
+            changed.push(merged.link.clone());
+            self.insert(merged);
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let changed: Vec<&str> = changed.iter().map(|s| s.as_str()).collect();
+        self.save(&changed)
+    }
+
+    /// Moves the feed at `rss_link` to `new_link`, into the feed there if there's one
+    fn move_feed(&mut self, rss_link: &str, new_link: &str) -> Result<()> {
+        let new_link = canonicalize(new_link);
+        let mut feed = self.remove(get_hash(rss_link))
+            .ok_or_else(|| Error::from(ErrorKind::NotSubscribed))?;
+        feed.link = new_link.clone();
+        // the hub knows it by the id of the old link
+        feed.hub = None;
+        let feed = match self.remove(get_hash(&new_link)) {
+            Some(mut existing) => {
+                merge_feed(&mut existing, feed);
+                existing
+            }
+            None => feed,
+        };
+        self.insert(feed);
+        self.save(&[rss_link, &new_link])
+    }
+
+    fn remove(&mut self, feed_id: FeedID) -> Option<Feed> {
+        let feed = self.feeds.remove(&feed_id)?;
+        let key = feed_key(&feed.link);
+        if self.feed_keys.get(&key) == Some(&feed_id) {
+            self.feed_keys.remove(&key);
+        }
+        for subscriber in &feed.subscribers {
+            let is_empty = self.subscribers
+                .get_mut(subscriber)
+                .map_or(false, |feeds| {
+                    feeds.remove(&feed_id);
+                    feeds.is_empty()
+                });
+            if is_empty {
+                self.subscribers.remove(subscriber);
+            }
+        }
+        Some(feed)
+    }
+
+    fn insert(&mut self, feed: Feed) {
+        let feed_id = get_hash(&feed.link);
+        for subscriber in &feed.subscribers {
+            let subscribed_feeds = self.subscribers
+                .entry(subscriber.to_owned())
+                .or_insert_with(HashSet::new);
+            subscribed_feeds.insert(feed_id);
+        }
+        self.feed_keys.insert(feed_key(&feed.link), feed_id);
+        self.feeds.insert(feed_id, feed);
+    }
+
+    fn import(&mut self, feeds_list: Vec<Feed>) -> Result<usize> {
+        let mut links = Vec::new();
+        for feed in feeds_list {
+            if self.feeds.contains_key(&get_hash(&feed.link)) {
+                continue;
+            }
+            links.push(feed.link.clone());
+            self.insert(feed);
+        }
+        let changed: Vec<&str> = links.iter().map(|s| s.as_str()).collect();
+        self.save(&changed)?;
+        self.canonicalize_feeds()?;
+        Ok(links.len())
+    }
+
+    fn save(&mut self, changed: &[&str]) -> Result<()> {
+        let changes: Vec<Change> = changed.iter().map(|&link| Change::Feed(link)).collect();
+        self.save_changes(&changes)
+    }
+
+    fn save_changes(&mut self, changes: &[Change]) -> Result<()> {
+        let state = State {
+            feeds: &self.feeds,
+            outbox: &self.outbox,
+            chats: &self.chats,
+        };
+        self.storage.save(state, changes)
+    }
+
+    /// Saves what changes on nearly every fetch only if the storage is incremental,
+    /// rewriting the whole JSON file for it would cost a full write per feed,
+    /// there it's written with the next change that has to be saved
+    fn save_bookkeeping(&mut self, changed: &[&str]) {
+        if self.storage.is_incremental() {
+            self.save(changed).unwrap_or_default();
+        }
+    }
+}
+
+/// Adds the subscribers of `feed` to `into`, and the hashes so its items are not sent again
+fn merge_feed(into: &mut Feed, feed: Feed) {
+    into.subscribers.extend(&feed.subscribers);
+    for (subscriber, options) in feed.options {
+        into.options.entry(subscriber).or_insert(options);
+    }
+    if feed.hash_version == into.hash_version {
+        for hash in feed.hash_list {
+            if !into.hash_list.contains(&hash) {
+                into.hash_list.push(hash);
+            }
+        }
     }
 }
 
@@ -237,7 +884,7 @@ impl Clone for Database {
 }
 
 fn gen_item_hash(item: &feed::Item) -> u64 {
-    item.id.as_ref().map(|id| get_hash(&id)).unwrap_or_else(|| {
+    item.id.as_ref().map(|id| get_hash(id)).unwrap_or_else(|| {
         let title = item.title.as_ref().map(|s| s.as_str()).unwrap_or_default();
         let link = item.link.as_ref().map(|s| s.as_str()).unwrap_or_default();
         get_hash(&format!("{}{}", title, link))
@@ -245,57 +892,43 @@ fn gen_item_hash(item: &feed::Item) -> u64 {
 }
 
 impl Database {
-    pub fn create(path: &str) -> Result<Database> {
-        let feeds: HashMap<FeedID, Feed> = HashMap::new();
-        let subscribers: HashMap<SubscriberID, HashSet<FeedID>> = HashMap::new();
-        let result = Database {
-            inner: Rc::new(RefCell::new(DatabaseInner {
-                path: path.to_owned(),
-                feeds: feeds,
-                subscribers: subscribers,
-            })),
-        };
-
-        result.save()?;
-
-        Ok(result)
-    }
-
-    pub fn open(path: &str) -> Result<Database> {
-        let p = Path::new(path);
-        if p.exists() {
-            let f = File::open(path).chain_err(|| ErrorKind::DatabaseOpen(path.to_owned()))?;
-            let feeds_list: Vec<Feed> =
-                serde_json::from_reader(&f).chain_err(|| ErrorKind::DatabaseFormat)?;
+    pub fn open(path: &str, backend: Backend) -> Result<Database> {
+        let mut storage = backend.open(path)?;
+        let snapshot = storage.load()?;
+        let is_empty =
+            snapshot.feeds.is_empty() && snapshot.outbox.is_empty() && snapshot.chats.is_empty();
 
-            let mut feeds: HashMap<FeedID, Feed> = HashMap::with_capacity(feeds_list.len());
-            let mut subscribers: HashMap<SubscriberID, HashSet<FeedID>> = HashMap::new();
-
-            for feed in feeds_list {
-                let feed_id = get_hash(&feed.link);
-                for subscriber in &feed.subscribers {
-                    let subscribed_feeds = subscribers
-                        .entry(subscriber.to_owned())
-                        .or_insert_with(HashSet::new);
-                    subscribed_feeds.insert(feed_id);
-                }
-                feeds.insert(feed_id, feed);
-            }
-
-            Ok(Database {
-                inner: Rc::new(RefCell::new(DatabaseInner {
-                    path: path.to_owned(),
-                    feeds: feeds,
-                    subscribers: subscribers,
-                })),
-            })
-        } else {
-            Database::create(path)
+        let mut inner = DatabaseInner {
+            storage: storage,
+            feeds: HashMap::with_capacity(snapshot.feeds.len()),
+            feed_keys: HashMap::with_capacity(snapshot.feeds.len()),
+            subscribers: HashMap::new(),
+            outbox: BTreeMap::new(),
+            next_outbox_id: 0,
+            chats: snapshot.chats,
+        };
+        for feed in snapshot.feeds {
+            inner.insert(feed);
+        }
+        inner.canonicalize_feeds()?;
+        for entry in snapshot.outbox {
+            inner.next_outbox_id = ::std::cmp::max(inner.next_outbox_id, entry.id + 1);
+            inner.outbox.insert(entry.id, entry);
         }
+        if is_empty {
+            // make sure it's writable
+            inner.save(&[])?;
+        }
+
+        Ok(Database {
+            inner: Rc::new(RefCell::new(inner)),
+        })
     }
 
-    pub fn get_all_feeds(&self) -> Vec<Feed> {
-        self.inner.borrow().get_all_feeds()
+    /// Import feeds from a JSON database, feeds that already exist are skipped
+    pub fn import_json(&self, json_path: &str) -> Result<usize> {
+        let snapshot = JsonStorage::new(json_path).load()?;
+        self.inner.borrow_mut().import(snapshot.feeds)
     }
 
     pub fn get_all_subscribers(&self) -> Vec<SubscriberID> {
@@ -306,8 +939,8 @@ impl Database {
         self.inner.borrow().get_subscribed_feeds(subscriber)
     }
 
-    pub fn inc_error_count(&self, rss_link: &str) -> u32 {
-        self.inner.borrow_mut().inc_error_count(rss_link)
+    pub fn record_error(&self, rss_link: &str, error: FeedError) -> Option<(i64, i64)> {
+        self.inner.borrow_mut().record_error(rss_link, error)
     }
 
     pub fn reset_error_count(&self, rss_link: &str) {
@@ -331,6 +964,10 @@ impl Database {
         self.inner.borrow_mut().unsubscribe(subscriber, rss_link)
     }
 
+    pub fn move_feed(&self, rss_link: &str, new_link: &str) -> Result<()> {
+        self.inner.borrow_mut().move_feed(rss_link, new_link)
+    }
+
     pub fn delete_subscriber(&self, subscriber: SubscriberID) {
         self.inner.borrow_mut().delete_subscriber(subscriber);
     }
@@ -343,11 +980,353 @@ impl Database {
         self.inner.borrow_mut().update(rss_link, items)
     }
 
+    pub fn commit_updates(
+        &self,
+        rss_link: &str,
+        messages: Vec<(SubscriberID, OutboxMessage)>,
+        digests: Vec<(SubscriberID, DigestItem)>,
+    ) -> Result<()> {
+        self.inner
+            .borrow_mut()
+            .commit_updates(rss_link, messages, digests)
+    }
+
+    pub fn get_pending_digests(&self) -> Vec<(SubscriberID, ChatSettings)> {
+        self.inner.borrow().get_pending_digests()
+    }
+
+    pub fn commit_digest(&self, chat: SubscriberID, messages: Vec<String>) -> Result<()> {
+        self.inner.borrow_mut().commit_digest(chat, messages)
+    }
+
+    /// Queues messages for the sender
+    pub fn enqueue(&self, messages: Vec<(SubscriberID, String)>) -> Result<()> {
+        self.inner.borrow_mut().enqueue(messages)
+    }
+
+    pub fn get_outbox_chats(&self) -> Vec<SubscriberID> {
+        self.inner.borrow().get_outbox_chats()
+    }
+
+    pub fn get_outbox(&self, chat: SubscriberID) -> Vec<OutboxEntry> {
+        self.inner.borrow().get_outbox(chat)
+    }
+
+    pub fn remove_outbox(&self, ids: &[u64]) {
+        self.inner.borrow_mut().remove_outbox(ids)
+    }
+
     pub fn update_title(&self, rss_link: &str, new_title: &str) {
         self.inner.borrow_mut().update_title(rss_link, new_title)
     }
 
-    fn save(&self) -> Result<()> {
-        self.inner.borrow().save()
+    pub fn get_options(&self, subscriber: SubscriberID, rss_link: &str) -> Result<Options> {
+        self.inner.borrow().get_options(subscriber, rss_link)
+    }
+
+    pub fn set_options(
+        &self,
+        subscriber: SubscriberID,
+        rss_link: &str,
+        options: Options,
+    ) -> Result<()> {
+        self.inner
+            .borrow_mut()
+            .set_options(subscriber, rss_link, options)
+    }
+
+    pub fn get_chat_settings(&self, chat: SubscriberID) -> ChatSettings {
+        self.inner.borrow().get_chat_settings(chat)
+    }
+
+    pub fn set_chat_settings(&self, chat: SubscriberID, settings: ChatSettings) -> Result<()> {
+        self.inner.borrow_mut().set_chat_settings(chat, settings)
+    }
+
+    /// The chosen language of `chat`, or `default`
+    pub fn get_lang(&self, chat: SubscriberID, default: Lang) -> Lang {
+        self.get_chat_settings(chat).lang.unwrap_or(default)
+    }
+
+    pub fn update_validators(&self, rss_link: &str, validators: feed::Validators) {
+        self.inner
+            .borrow_mut()
+            .update_validators(rss_link, validators)
+    }
+
+    pub fn record_fetch(&self, rss_link: &str, time: i64, interval: u64) {
+        self.inner
+            .borrow_mut()
+            .record_fetch(rss_link, time, interval)
+    }
+
+    /// The last fetch that worked and the interval after it, of each feed
+    pub fn get_fetch_times(&self) -> Vec<(FeedID, Option<i64>, Option<u64>)> {
+        self.inner.borrow().get_fetch_times()
+    }
+
+    pub fn get_feed(&self, feed_id: FeedID) -> Option<Feed> {
+        self.inner.borrow().get_feed(feed_id)
+    }
+
+    pub fn set_hub(&self, rss_link: &str, hub: Option<Hub>) -> Result<()> {
+        self.inner.borrow_mut().set_hub(rss_link, hub)
+    }
+}
+
+#[test]
+fn test_get_hash() {
+    // these must never change, they are saved in databases
+    assert_eq!(get_hash(""), 2202906307356721367);
+    assert_eq!(get_hash("http://example.com/feed.xml"), 108507818954657874);
+    assert_eq!(get_hash("Rust 中文"), 10844221329475133308);
+}
+
+#[test]
+fn test_update_old_hash_version() {
+    fn item(id: &str) -> feed::Item {
+        feed::Item {
+            title: None,
+            link: None,
+            id: Some(id.into()),
+            summary: None,
+            content: None,
+            media: vec![],
+            thumbnail: None,
+            published: None,
+            updated: None,
+        }
     }
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let mut feed = Feed::default();
+    feed.link = "link".into();
+    feed.subscribers.insert(1);
+    feed.hash_list = vec![1, 2, 3];
+    db.inner.borrow_mut().insert(feed);
+
+    assert!(db.update("link", vec![item("1"), item("2")]).is_empty());
+    assert_eq!(db.update("link", vec![item("1"), item("2"), item("3")]), vec![item("3")]);
+}
+
+#[test]
+fn test_update_before_link_resolving() {
+    fn item(link: &str) -> feed::Item {
+        feed::Item {
+            title: Some("title".into()),
+            link: Some(link.into()),
+            id: None,
+            summary: None,
+            content: None,
+            media: vec![],
+            thumbnail: None,
+            published: None,
+            updated: None,
+        }
+    }
+    // saved by version 1, when the links were left relative
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let mut feed = Feed::default();
+    feed.link = "link".into();
+    feed.subscribers.insert(1);
+    feed.hash_list = vec![gen_item_hash(&item("/1")), gen_item_hash(&item("/2"))];
+    feed.hash_version = 1;
+    db.inner.borrow_mut().insert(feed);
+
+    let resolved = vec![item("http://example.com/1"), item("http://example.com/2")];
+    assert!(db.update("link", resolved.clone()).is_empty());
+    assert!(db.update("link", resolved.clone()).is_empty());
+    let mut items = resolved;
+    items.insert(0, item("http://example.com/3"));
+    assert_eq!(db.update("link", items), vec![item("http://example.com/3")]);
+}
+
+#[test]
+fn test_canonicalize_feeds() {
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    for &(link, subscriber) in &[
+        ("http://x.com/feed", 1),
+        ("https://x.com/feed/", 2),
+        ("HTTP://Y.com/a#b", 3),
+    ] {
+        let mut feed = Feed::default();
+        feed.link = link.into();
+        feed.subscribers.insert(subscriber);
+        feed.options.insert(subscriber, Options::default());
+        feed.hash_list = vec![subscriber as u64];
+        feed.hash_version = HASH_VERSION;
+        db.inner.borrow_mut().insert(feed);
+    }
+    db.inner.borrow_mut().canonicalize_feeds().unwrap();
+
+    let mut links: Vec<String> = db.inner
+        .borrow()
+        .feeds
+        .values()
+        .map(|feed| feed.link.clone())
+        .collect();
+    links.sort();
+    assert_eq!(links, vec!["http://y.com/a", "https://x.com/feed/"]);
+    let feed = db.get_feed(get_hash("https://x.com/feed/")).unwrap();
+    let mut subscribers: Vec<SubscriberID> = feed.subscribers.into_iter().collect();
+    subscribers.sort();
+    assert_eq!(subscribers, vec![1, 2]);
+    assert_eq!(feed.options.len(), 2);
+    assert_eq!(feed.hash_list, vec![2, 1]);
+    assert_eq!(db.get_subscribed_feeds(3).unwrap()[0].link, "http://y.com/a");
+
+    // however it's written
+    assert!(db.is_subscribed(1, "x.com/feed"));
+    assert!(db.is_subscribed(2, "HTTP://X.com/feed#top"));
+    assert!(db.is_subscribed(2, "https://x.com/feed?utm_source=rss"));
+    assert!(db.get_options(3, "http://y.com:80/a").is_ok());
+    assert_eq!(db.unsubscribe(1, "https://x.com/feed").unwrap().link, "https://x.com/feed/");
+    assert!(!db.is_subscribed(1, "https://x.com/feed/"));
+    assert!(db.unsubscribe(1, "http://x.com/feed").is_err());
+
+    db.move_feed("http://y.com/a", "https://y.com/a").unwrap();
+    assert_eq!(db.get_subscribed_feeds(3).unwrap()[0].link, "https://y.com/a");
+    db.move_feed("https://y.com/a", "https://x.com/feed/").unwrap();
+    let feed = db.get_feed(get_hash("https://x.com/feed/")).unwrap();
+    assert!(feed.subscribers.contains(&3));
+    assert_eq!(db.inner.borrow().feeds.len(), 1);
+    assert_eq!(db.inner.borrow().feed_keys.len(), 1);
+}
+
+#[test]
+fn test_outbox() {
+    use std::fs;
+
+    let dir = ::storage::temp_dir("outbox");
+    let path = dir.join("db.sqlite").to_string_lossy().into_owned();
+    let db = Database::open(&path, Backend::Sqlite).unwrap();
+    let mut feed = Feed::default();
+    feed.link = "link".into();
+    feed.subscribers.insert(1);
+    db.inner.borrow_mut().insert(feed);
+
+    let messages = vec![(1, "a"), (2, "b"), (1, "c")]
+        .into_iter()
+        .map(|(chat, text)| (chat, text.to_owned().into()))
+        .collect();
+    db.commit_updates("link", messages, Vec::new()).unwrap();
+    assert_eq!(db.get_outbox_chats(), vec![1, 2]);
+    let texts: Vec<String> = db.get_outbox(1).into_iter().map(|e| e.text).collect();
+    assert_eq!(texts, vec!["a", "c"]);
+
+    let first = db.get_outbox(1)[0].id;
+    db.remove_outbox(&[first]);
+    db.update_subscriber(1, 3);
+    assert_eq!(db.get_outbox_chats(), vec![2, 3]);
+    assert!(db.is_subscribed(3, "link"));
+    // chat 2 has no subscription, only messages
+    db.update_subscriber(2, 4);
+    assert_eq!(db.get_outbox_chats(), vec![3, 4]);
+
+    // ids go on from the saved ones after reopening
+    drop(db);
+    let db = Database::open(&path, Backend::Sqlite).unwrap();
+    assert_eq!(db.get_outbox_chats(), vec![3, 4]);
+    db.enqueue(vec![(4, "d".to_owned())]).unwrap();
+    let ids: Vec<u64> = db.get_outbox(4).into_iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![1, 3]);
+
+    db.delete_subscriber(3);
+    assert_eq!(db.get_outbox_chats(), vec![4]);
+    let snapshot = db.inner.borrow_mut().storage.load().unwrap();
+    assert_eq!(snapshot.outbox.len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bookkeeping_saved() {
+    use std::fs;
+
+    let dir = ::storage::temp_dir("bookkeeping");
+    let path = dir.join("db.sqlite").to_string_lossy().into_owned();
+    // already canonical, or opening it again would move the feed
+    let link = "http://example.com/feed";
+    {
+        let db = Database::open(&path, Backend::Sqlite).unwrap();
+        let mut feed = Feed::default();
+        feed.link = link.into();
+        db.inner.borrow_mut().insert(feed);
+        db.update_title(link, "title");
+        let validators = feed::Validators {
+            etag: Some("etag".into()),
+            last_modified: Some("Sat, 01 Jan 2000 00:00:00 GMT".into()),
+        };
+        db.update_validators(link, validators);
+        db.record_fetch(link, 100, 600);
+    }
+    let db = Database::open(&path, Backend::Sqlite).unwrap();
+    let inner = db.inner.borrow();
+    let feed = &inner.feeds[&get_hash(link)];
+    assert_eq!(feed.title, "title");
+    assert_eq!(feed.etag, Some("etag".into()));
+    assert_eq!(feed.last_modified, Some("Sat, 01 Jan 2000 00:00:00 GMT".into()));
+    assert_eq!(feed.last_fetch, Some(100));
+    assert_eq!(feed.interval, Some(600));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_digest() {
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let mut feed = Feed::default();
+    feed.link = "link".into();
+    feed.subscribers.insert(1);
+    db.inner.borrow_mut().insert(feed);
+    let item = |line: &str| DigestItem {
+        feed: "link".into(),
+        title: "title".into(),
+        line: line.into(),
+    };
+
+    let digests = vec![(1, item("a")), (1, item("b"))];
+    db.commit_updates("link", Vec::new(), digests).unwrap();
+    {
+        let mut inner = db.inner.borrow_mut();
+        let changes = inner.push_digests(vec![(1, item("c")), (1, item("d"))], 100);
+        assert_eq!(changes, vec![Change::Chat(1)]);
+        inner.save_changes(&changes).unwrap();
+    }
+    assert!(db.get_outbox_chats().is_empty());
+    let pending = db.get_pending_digests();
+    assert_eq!(pending.len(), 1);
+    let digest = pending[0].1.pending.clone().unwrap();
+    // started by the first update
+    assert!(digest.since > 100);
+    let lines: Vec<String> = digest.items.into_iter().map(|item| item.line).collect();
+    assert_eq!(lines, vec!["a", "b", "c", "d"]);
+    let snapshot = db.inner.borrow_mut().storage.load().unwrap();
+    assert_eq!(snapshot.chats[&1].pending.as_ref().unwrap().items.len(), 4);
+
+    db.commit_digest(1, vec!["digest".into()]).unwrap();
+    assert!(db.get_pending_digests().is_empty());
+    assert_eq!(db.get_outbox(1)[0].text, "digest");
+}
+
+#[test]
+fn test_record_error() {
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let mut feed = Feed::default();
+    feed.link = "link".into();
+    db.inner.borrow_mut().insert(feed);
+    let error = |time| FeedError::new(&ErrorKind::Http(404).into(), time);
+
+    assert_eq!(db.record_error("nothing", error(100)), None);
+    assert_eq!(db.record_error("link", error(100)), Some((100, 100)));
+    assert_eq!(db.record_error("link", error(200)), Some((100, 100)));
+    assert_eq!(db.record_error("link", error(300)), Some((100, 200)));
+    let feed = db.get_feed(get_hash("link")).unwrap();
+    assert_eq!(feed.error_count, 3);
+    assert_eq!(feed.failing_since, Some(100));
+    assert_eq!(feed.last_error.unwrap().kind, FeedErrorKind::Http(404));
+
+    db.reset_error_count("link");
+    let feed = db.get_feed(get_hash("link")).unwrap();
+    assert_eq!(feed.error_count, 0);
+    assert_eq!(feed.failing_since, None);
+    assert_eq!(feed.last_error, Some(error(300)));
+    assert_eq!(db.record_error("link", error(400)), Some((400, 400)));
 }
@@ -0,0 +1,182 @@
+//! Dates of feeds, RFC 822 for RSS and RFC 3339 for Atom and JSON Feed in theory,
+//! but plenty of feeds get them slightly wrong
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Weekday};
+
+/// Parses a date in any of the formats seen in feeds, a date without a time zone is in UTC
+pub fn parse(s: &str) -> Option<DateTime<FixedOffset>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    parse_rfc3339(s).or_else(|| parse_rfc822(s))
+}
+
+/// `2006-01-02T15:04:05Z` and the variants, like a space instead of `T`,
+/// `+0700` as the offset, no seconds, no time zone or no time at all
+fn parse_rfc3339(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Some(date);
+    }
+    if s.len() < 10 || !s.is_char_boundary(10) {
+        return None;
+    }
+    let (date, rest) = s.split_at(10);
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let rest = rest.trim_left_matches(|c: char| c == 'T' || c == 't' || c == ' ');
+    let zone_start = rest.find(|c: char| c == 'Z' || c == 'z' || c == '+' || c == '-' || c == ' ');
+    let (time, zone) = rest.split_at(zone_start.unwrap_or(rest.len()));
+    let time = if time.is_empty() {
+        NaiveTime::from_hms(0, 0, 0)
+    } else {
+        parse_time(time)?
+    };
+    let offset = parse_zone(zone.trim())?;
+    offset.from_local_datetime(&date.and_time(time)).single()
+}
+
+/// `Mon, 02 Jan 2006 15:04:05 -0700` and the variants, like a wrong or no weekday,
+/// full month names, `Jan 02, 2006`, two-digit years, no seconds or no time zone
+fn parse_rfc822(s: &str) -> Option<DateTime<FixedOffset>> {
+    let mut tokens = s.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.is_empty())
+        .peekable();
+    // the weekday is ignored, since some feeds get it wrong
+    if tokens.peek().map_or(false, |token| parse_weekday(token).is_some()) {
+        tokens.next();
+    }
+    let (mut day, mut month) = (tokens.next()?, tokens.next()?);
+    if day.starts_with(|c: char| c.is_alphabetic()) {
+        ::std::mem::swap(&mut day, &mut month);
+    }
+    let day = day.parse().ok()?;
+    let month = parse_month(month)?;
+    let year: i32 = tokens.next()?.parse().ok()?;
+    let year = match year {
+        0...49 => year + 2000,
+        50...99 => year + 1900,
+        _ => year,
+    };
+    let time = match tokens.next() {
+        Some(time) => parse_time(time)?,
+        None => NaiveTime::from_hms(0, 0, 0),
+    };
+    // anything after the zone, like `(PST)`, is a comment
+    let offset = parse_zone(tokens.next().unwrap_or(""))?;
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    offset.from_local_datetime(&date.and_time(time)).single()
+}
+
+/// `Mon` or `Monday`
+pub fn parse_weekday(s: &str) -> Option<Weekday> {
+    let s = s.to_lowercase();
+    let days = [
+        ("mon", Weekday::Mon),
+        ("tue", Weekday::Tue),
+        ("wed", Weekday::Wed),
+        ("thu", Weekday::Thu),
+        ("fri", Weekday::Fri),
+        ("sat", Weekday::Sat),
+        ("sun", Weekday::Sun),
+    ];
+    days.iter()
+        .find(|&&(name, _)| s.starts_with(name))
+        .map(|&(_, day)| day)
+}
+
+/// `Jan`, `January` or `Sept`
+fn parse_month(s: &str) -> Option<u32> {
+    let s = s.to_lowercase();
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    months
+        .iter()
+        .position(|month| s.starts_with(month))
+        .map(|i| i as u32 + 1)
+}
+
+/// `15:04`, `15:04:05` or `15:04:05.999`
+fn parse_time(s: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M:%S%.f")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .ok()
+}
+
+/// An offset like `+07:00`, `+0700` or `+07`, or a zone name of RFC 822 and a few
+/// more common ones. Unknown names are taken as UTC, they are only hours off
+fn parse_zone(s: &str) -> Option<FixedOffset> {
+    let name = s.to_uppercase();
+    let is_name = name.chars().all(|c| c.is_ascii_alphabetic());
+    let hours = match name.as_str() {
+        "" | "Z" | "UT" | "UTC" | "GMT" => 0,
+        "EST" => -5,
+        "EDT" => -4,
+        // also China Standard Time, but RFC 822 says it's this one
+        "CST" => -6,
+        "CDT" => -5,
+        "MST" => -7,
+        "MDT" => -6,
+        "PST" => -8,
+        "PDT" => -7,
+        "BST" | "CET" => 1,
+        "CEST" | "EET" => 2,
+        "EEST" | "MSK" => 3,
+        "HKT" => 8,
+        "JST" | "KST" => 9,
+        "AEST" => 10,
+        "AEDT" => 11,
+        // `GMT+8`
+        _ if name.starts_with("GMT") || name.starts_with("UTC") => return parse_offset(&s[3..]),
+        _ if is_name => 0,
+        _ => return parse_offset(s),
+    };
+    FixedOffset::east_opt(hours * 3600)
+}
+
+pub fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (&digits[..], "0"),
+        3 => digits.split_at(1),
+        4 => digits.split_at(2),
+        _ => return None,
+    };
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[test]
+fn test_parse() {
+    let data = vec![
+        ("2006-01-02T15:04:05Z", "2006-01-02T15:04:05+00:00"),
+        ("2006-01-02T15:04:05.999-07:00", "2006-01-02T15:04:05.999-07:00"),
+        ("2006-01-02 15:04:05", "2006-01-02T15:04:05+00:00"),
+        ("2006-01-02t15:04+0700", "2006-01-02T15:04:00+07:00"),
+        ("2006-01-02T15:04:05 +07", "2006-01-02T15:04:05+07:00"),
+        ("2006-01-02", "2006-01-02T00:00:00+00:00"),
+        ("Mon, 02 Jan 2006 15:04:05 -0700", "2006-01-02T15:04:05-07:00"),
+        (" Mon, 02 Jan 2006 15:04:05 GMT\n", "2006-01-02T15:04:05+00:00"),
+        ("Tue, 02 Jan 2006 15:04:05 EST", "2006-01-02T15:04:05-05:00"),
+        ("Monday, 2 January 06 15:04 +07:00", "2006-01-02T15:04:00+07:00"),
+        ("02 Sept 2006 15:04:05 GMT+8", "2006-09-02T15:04:05+08:00"),
+        ("Jan 02, 2006 5:04:05 +0000", "2006-01-02T05:04:05+00:00"),
+        ("Mon, 02 Jan 2006 15:04:05 XYZ (comment)", "2006-01-02T15:04:05+00:00"),
+        ("Mon, 02 Jan 2006", "2006-01-02T00:00:00+00:00"),
+    ];
+    for (s, result) in data {
+        assert_eq!(parse(s), DateTime::parse_from_rfc3339(result).ok(), "{}", s);
+    }
+    for s in &["", "yesterday", "2006-13-02", "Mon, 32 Jan 2006", "02 Jan 2006 25:00"] {
+        assert_eq!(parse(s), None, "{}", s);
+    }
+}
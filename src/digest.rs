@@ -0,0 +1,227 @@
+//! Digests, the updates of a chat collected and sent together on a schedule, see `/digest`
+//!
+//! The items are kept in the settings of the chat until the digest is due,
+//! then every feed becomes one message, or more if it's too long.
+
+use std::time::Duration;
+
+use chrono::{FixedOffset, TimeZone, Timelike, Utc};
+use futures::prelude::*;
+use tokio_core::reactor::{Handle, Interval};
+
+use data::{self, DigestItem};
+use date::parse_offset;
+use utlis::{format_and_split_msgs, log_error, Escape};
+
+/// How often pending digests are checked, in seconds
+const CHECK_INTERVAL: u64 = 60;
+
+/// When a digest is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Digest {
+    /// At the start of every hour
+    Hourly,
+    /// Every day at this minute of the day, in the time zone of the chat
+    Daily(u32),
+}
+
+/// `hourly`, `daily HH:MM`, or `off` for `None`
+pub fn parse_digest(s: &str) -> Option<Option<Digest>> {
+    let args: Vec<&str> = s.split_whitespace().collect();
+    match args.len() {
+        1 if args[0] == "off" => Some(None),
+        1 if args[0] == "hourly" => Some(Some(Digest::Hourly)),
+        2 if args[0] == "daily" => {
+            let mut parts = args[1].splitn(2, ':');
+            let hour: u32 = parts.next()?.parse().ok()?;
+            let minute: u32 = parts.next()?.parse().ok()?;
+            if hour < 24 && minute < 60 {
+                Some(Some(Digest::Daily(hour * 60 + minute)))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `+08:00`, `-5`, or `UTC`, as seconds east of UTC
+pub fn parse_utc_offset(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return Some(0);
+    }
+    let s = match s.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("utc") => &s[3..],
+        _ => s,
+    };
+    parse_offset(s).map(|offset| offset.local_minus_utc())
+}
+
+/// `HH:MM` of a daily digest
+pub fn format_daily_time(minute: u32) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// Unix time when a digest with updates since `since` is due
+pub fn next_digest(digest: Digest, since: i64, offset: FixedOffset) -> i64 {
+    let seconds = i64::from(offset.timestamp(since, 0).num_seconds_from_midnight());
+    match digest {
+        Digest::Hourly => since - seconds % 3600 + 3600,
+        Digest::Daily(minute) => {
+            let at = i64::from(minute) * 60;
+            let midnight = since - seconds;
+            if seconds < at {
+                midnight + at
+            } else {
+                midnight + at + 24 * 3600
+            }
+        }
+    }
+}
+
+/// One message for every feed, in the order of their first update
+pub fn format_digest(items: &[DigestItem]) -> Vec<String> {
+    let mut feeds: Vec<&str> = Vec::new();
+    for item in items {
+        if !feeds.contains(&item.feed.as_str()) {
+            feeds.push(&item.feed);
+        }
+    }
+    let mut msgs = Vec::new();
+    for feed in feeds {
+        let updates: Vec<&DigestItem> = items.iter().filter(|item| item.feed == feed).collect();
+        msgs.extend(format_and_split_msgs(
+            format!("<b>{}</b>", Escape(&updates[0].title)),
+            &updates,
+            |item| item.line.clone(),
+        ));
+    }
+    msgs
+}
+
+/// Moves the digests that are due to the outbox, they are delivered by `sender`
+fn flush_digests(db: &data::Database, now: i64) {
+    for (chat, settings) in db.get_pending_digests() {
+        let pending = match settings.pending {
+            Some(ref pending) => pending,
+            None => continue,
+        };
+        // items left after `/digest off` are sent at once
+        let due = settings
+            .digest
+            .map(|digest| next_digest(digest, pending.since, settings.offset()) <= now)
+            .unwrap_or(true);
+        if due {
+            let msgs = format_digest(&pending.items);
+            if let Err(e) = db.commit_digest(chat, msgs) {
+                log_error(&e);
+            }
+        }
+    }
+}
+
+pub fn spawn_digests(db: data::Database, handle: Handle) {
+    let lop = Interval::new(Duration::from_secs(CHECK_INTERVAL), &handle)
+        .expect("failed to start digest loop")
+        .for_each(move |_| {
+            flush_digests(&db, Utc::now().timestamp());
+            Ok(())
+        })
+        .map_err(|e| error!("digest loop: {}", e));
+    handle.spawn(lop);
+}
+
+#[test]
+fn test_parse_digest() {
+    assert_eq!(parse_digest("off"), Some(None));
+    assert_eq!(parse_digest("hourly"), Some(Some(Digest::Hourly)));
+    assert_eq!(parse_digest("daily 09:00"), Some(Some(Digest::Daily(540))));
+    assert_eq!(parse_digest("daily  9:05"), Some(Some(Digest::Daily(545))));
+    assert_eq!(parse_digest("daily 23:59"), Some(Some(Digest::Daily(1439))));
+    assert_eq!(parse_digest("daily 24:00"), None);
+    assert_eq!(parse_digest("daily 09:60"), None);
+    assert_eq!(parse_digest("daily 9"), None);
+    assert_eq!(parse_digest("daily"), None);
+    assert_eq!(parse_digest("weekly"), None);
+    assert_eq!(parse_digest(""), None);
+}
+
+#[test]
+fn test_parse_utc_offset() {
+    assert_eq!(parse_utc_offset("+08:00"), Some(8 * 3600));
+    assert_eq!(parse_utc_offset("+0530"), Some(5 * 3600 + 30 * 60));
+    assert_eq!(parse_utc_offset("-5"), Some(-5 * 3600));
+    assert_eq!(parse_utc_offset("UTC+8"), Some(8 * 3600));
+    assert_eq!(parse_utc_offset("utc"), Some(0));
+    assert_eq!(parse_utc_offset("8"), None);
+    assert_eq!(parse_utc_offset("-1001234567890"), None);
+    assert_eq!(parse_utc_offset("@channel"), None);
+    assert_eq!(parse_utc_offset("日本"), None);
+}
+
+#[test]
+fn test_next_digest() {
+    let utc = FixedOffset::east(0);
+    let cst = FixedOffset::east(8 * 3600);
+    let time = |s: &str| {
+        ::chrono::DateTime::parse_from_rfc3339(s)
+            .unwrap()
+            .timestamp()
+    };
+    let data = vec![
+        (Digest::Hourly, "2018-06-01T10:20:00Z", utc, "2018-06-01T11:00:00Z"),
+        (Digest::Hourly, "2018-06-01T10:00:00Z", utc, "2018-06-01T11:00:00Z"),
+        (Digest::Hourly, "2018-06-01T23:59:59Z", utc, "2018-06-02T00:00:00Z"),
+        (
+            Digest::Hourly,
+            "2018-06-01T10:20:00Z",
+            FixedOffset::east(5 * 3600 + 30 * 60),
+            "2018-06-01T10:30:00Z",
+        ),
+        (Digest::Daily(540), "2018-06-01T08:59:00Z", utc, "2018-06-01T09:00:00Z"),
+        (Digest::Daily(540), "2018-06-01T09:00:00Z", utc, "2018-06-02T09:00:00Z"),
+        (Digest::Daily(540), "2018-06-01T10:00:00Z", utc, "2018-06-02T09:00:00Z"),
+        // 09:00 in UTC+8 is 01:00 in UTC
+        (Digest::Daily(540), "2018-06-01T00:30:00Z", cst, "2018-06-01T01:00:00Z"),
+        (Digest::Daily(540), "2018-06-01T02:00:00Z", cst, "2018-06-02T01:00:00Z"),
+        // the day in UTC+8 starts at 16:00 in UTC
+        (Digest::Daily(540), "2018-06-01T17:00:00Z", cst, "2018-06-02T01:00:00Z"),
+        (
+            Digest::Daily(0),
+            "2018-06-01T12:00:00Z",
+            FixedOffset::west(5 * 3600),
+            "2018-06-02T05:00:00Z",
+        ),
+    ];
+    for (digest, since, offset, due) in data {
+        assert_eq!(
+            next_digest(digest, time(since), offset),
+            time(due),
+            "{:?} {} {}",
+            digest,
+            since,
+            offset
+        );
+    }
+}
+
+#[test]
+fn test_format_digest() {
+    let item = |feed: &str, line: &str| DigestItem {
+        feed: feed.to_owned(),
+        title: format!("{} & co", feed),
+        line: line.to_owned(),
+    };
+    let items = vec![
+        item("b", "1"),
+        item("a", "2"),
+        item("b", "3"),
+    ];
+    assert_eq!(
+        format_digest(&items),
+        vec!["<b>b &amp; co</b>\n1\n3", "<b>a &amp; co</b>\n2"]
+    );
+    assert!(format_digest(&[]).is_empty());
+}
@@ -16,6 +16,11 @@ error_chain! {
             description("feed is empty or not valid")
         }
 
+        WebPage(feeds: Vec<::html::FeedLink>) {
+            description("a web page instead of a feed")
+            display("a web page linking to {} feeds", feeds.len())
+        }
+
         Http(code: u32) {
             description("unexpected HTTP response code")
             display("HTTP {} ({})", code, response_code(*code).unwrap_or("Unknown"))
@@ -41,10 +46,12 @@ error_chain! {
     foreign_links {
         Curl(::tokio_curl::PerformError);
         Utf8(::std::str::Utf8Error);
+        Json(::serde_json::Error);
+        Regex(::regex::Error);
     }
 }
 
-fn response_code(code: u32) -> Option<&'static str> {
+pub fn response_code(code: u32) -> Option<&'static str> {
     match code {
         100 => Some("Continue"),
         101 => Some("Switching Protocols"),
@@ -1,23 +1,92 @@
 use std;
 use std::borrow::Cow;
+use std::cmp::{max, min};
 use std::str;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use curl::easy::Easy;
+use chrono::{DateTime, FixedOffset, Weekday};
+use curl::easy::{Easy, List};
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GB18030, GBK, SHIFT_JIS, UTF_8};
+use futures::future;
 use futures::prelude::*;
 use quick_xml::events::attributes::Attributes;
 use quick_xml::events::BytesStart;
 use quick_xml::events::Event as XmlEvent;
 use quick_xml::reader::Reader as XmlReader;
+use regex::bytes::Regex as BytesRegex;
 use regex::Regex;
+use serde_json;
 use tokio_curl::Session;
+use url::Url;
 
+use date;
 use errors::*;
+use html;
+use utlis::Escape;
 
 pub trait FromXml: Sized {
-    fn from_xml<B: std::io::BufRead>(reader: &mut XmlReader<B>, start: &BytesStart)
-        -> Result<Self>;
+    fn from_xml<B: std::io::BufRead>(
+        reader: &mut XmlReader<B>,
+        start: &BytesStart,
+        base: &Base,
+    ) -> Result<Self>;
+}
+
+/// What the relative links in an element are resolved against
+#[derive(Debug, Clone, Default)]
+pub struct Base {
+    /// The URL the feed is fetched from
+    document: Option<Url>,
+    /// From `xml:base` of the element or its ancestors, relative to the document
+    xml_base: Option<Url>,
+}
+
+impl Base {
+    fn new(document: &str) -> Base {
+        Base {
+            document: Url::parse(document).ok(),
+            xml_base: None,
+        }
+    }
+
+    /// The base inside an element with `xml:base` of `value`
+    fn with_xml_base(&self, value: &str) -> Base {
+        let parent = self.xml_base.as_ref().or_else(|| self.document.as_ref());
+        let xml_base = match parent {
+            Some(parent) => parent.join(value).ok(),
+            None => Url::parse(value).ok(),
+        };
+        Base {
+            document: self.document.clone(),
+            xml_base: xml_base.or_else(|| self.xml_base.clone()),
+        }
+    }
+
+    /// The base inside the element started by `start`
+    fn of<B: std::io::BufRead>(&self, reader: &XmlReader<B>, start: &BytesStart) -> Result<Base> {
+        for attribute in start.attributes() {
+            let attribute = attribute?;
+            if reader.decode(attribute.key) == "xml:base" {
+                return Ok(self.with_xml_base(&attribute.unescape_and_decode_value(reader)?));
+            }
+        }
+        Ok(self.clone())
+    }
+
+    /// Resolves `link` against `xml:base`, without one it's left to `fix_relative_url`,
+    /// since the channel link comes first then
+    fn resolve(&self, link: String) -> String {
+        match self.xml_base {
+            Some(ref base) => join_url(base, &link).unwrap_or(link),
+            None => link,
+        }
+    }
+}
+
+/// `link` resolved against `base` as RFC 3986 says
+fn join_url(base: &Url, link: &str) -> Option<String> {
+    base.join(link.trim()).ok().map(|url| url.into_string())
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -25,29 +94,42 @@ enum AtomLink<'a> {
     Alternate(String),
     Source(String),
     Hub(String),
+    Enclosure(Media),
     Other(String, Cow<'a, str>),
 }
 
 fn parse_atom_link<'a, B: std::io::BufRead>(
     reader: &mut XmlReader<B>,
     attributes: Attributes<'a>,
+    base: &Base,
 ) -> Result<Option<AtomLink<'a>>> {
     let mut href = None;
     let mut rel = None;
+    let mut mime_type = None;
+    let mut length = None;
+    let mut xml_base = None;
     for attribute in attributes {
         let attribute = attribute?;
         match reader.decode(attribute.key).as_ref() {
             "href" => href = Some(attribute.unescape_and_decode_value(reader)?),
             "rel" => rel = Some(reader.decode(attribute.value)),
+            "type" => mime_type = Some(reader.decode(attribute.value).into_owned()),
+            "length" => length = reader.decode(attribute.value).trim().parse().ok(),
+            "xml:base" => xml_base = Some(attribute.unescape_and_decode_value(reader)?),
             _ => (),
         }
     }
+    let href = href.map(|href| match xml_base {
+        Some(xml_base) => base.with_xml_base(&xml_base).resolve(href),
+        None => base.resolve(href),
+    });
     Ok(href.map(move |href| {
         if let Some(rel) = rel {
             match &*rel {
                 "alternate" => AtomLink::Alternate(href),
                 "self" => AtomLink::Source(href),
                 "hub" => AtomLink::Hub(href),
+                "enclosure" => AtomLink::Enclosure(Media::new(href, mime_type, length, None)),
                 _ => AtomLink::Other(href, rel),
             }
         } else {
@@ -72,6 +154,12 @@ fn skip_element<B: std::io::BufRead>(reader: &mut XmlReader<B>) -> Result<()> {
     Ok(())
 }
 
+fn try_parse_date<B: std::io::BufRead>(
+    reader: &mut XmlReader<B>,
+) -> Result<Option<DateTime<FixedOffset>>> {
+    Ok(try_parse_text(reader)?.and_then(|s| date::parse(&s)))
+}
+
 fn try_parse_text<'a, B: std::io::BufRead>(reader: &mut XmlReader<B>) -> Result<Option<String>> {
     let mut buf = Vec::new();
     let mut content: Option<String> = None;
@@ -97,28 +185,213 @@ fn try_parse_text<'a, B: std::io::BufRead>(reader: &mut XmlReader<B>) -> Result<
     Ok(content)
 }
 
+/// Reads the content of an element as markup, the nested elements of XHTML are kept as is
+fn parse_markup<B: std::io::BufRead>(reader: &mut XmlReader<B>, xhtml: bool) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut markup = String::new();
+    let mut depth = 0;
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(XmlEvent::Start(ref e)) => {
+                depth += 1;
+                markup.push('<');
+                markup.push_str(&reader.decode(e));
+                markup.push('>');
+            }
+            Ok(XmlEvent::Empty(ref e)) => {
+                markup.push('<');
+                markup.push_str(&reader.decode(e));
+                markup.push_str("/>");
+            }
+            Ok(XmlEvent::End(ref e)) => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+                markup.push_str("</");
+                markup.push_str(&reader.decode(e.name()));
+                markup.push('>');
+            }
+            Ok(XmlEvent::Text(ref e)) => {
+                if xhtml {
+                    markup.push_str(&reader.decode(e));
+                } else {
+                    markup.push_str(&e.unescape_and_decode(reader)?);
+                }
+            }
+            Ok(XmlEvent::CData(ref e)) => {
+                markup.push_str(&reader.decode(e));
+            }
+            Ok(XmlEvent::Eof) => break,
+            Err(err) => return Err(err.into()),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(markup)
+}
+
+/// Parses RSS `enclosure` and `media:content` of Media RSS
+fn parse_media<'a, B: std::io::BufRead>(
+    reader: &mut XmlReader<B>,
+    attributes: Attributes<'a>,
+    base: &Base,
+) -> Result<Option<Media>> {
+    let mut url = None;
+    let mut mime_type = None;
+    let mut length = None;
+    let mut medium = None;
+    for attribute in attributes {
+        let attribute = attribute?;
+        match reader.decode(attribute.key).as_ref() {
+            "url" => url = Some(attribute.unescape_and_decode_value(reader)?),
+            "type" => mime_type = Some(reader.decode(attribute.value).into_owned()),
+            "length" | "fileSize" => length = reader.decode(attribute.value).trim().parse().ok(),
+            "medium" => medium = Some(reader.decode(attribute.value).into_owned()),
+            _ => (),
+        }
+    }
+    Ok(url.map(|url| {
+        let medium = medium.as_ref().map(|s| s.as_str());
+        Media::new(base.resolve(url), mime_type, length, medium)
+    }))
+}
+
+/// `media:content` has a `url`, which Atom `content` never has
+fn is_media_content(e: &BytesStart) -> bool {
+    e.attributes()
+        .with_checks(false)
+        .any(|attribute| attribute.map(|a| a.key == b"url").unwrap_or(false))
+}
+
+/// Parses `media:thumbnail` of Media RSS
+fn parse_thumbnail<'a, B: std::io::BufRead>(
+    reader: &mut XmlReader<B>,
+    attributes: Attributes<'a>,
+    base: &Base,
+) -> Result<Option<String>> {
+    for attribute in attributes {
+        let attribute = attribute?;
+        if reader.decode(attribute.key) == "url" {
+            return Ok(Some(base.resolve(attribute.unescape_and_decode_value(reader)?)));
+        }
+    }
+    Ok(None)
+}
+
+/// Parses `description`, `content:encoded` and Atom's `summary` and `content`,
+/// `html` is used if the element doesn't tell its type
+fn parse_content<'a, B: std::io::BufRead>(
+    reader: &mut XmlReader<B>,
+    attributes: Attributes<'a>,
+    html: bool,
+) -> Result<Option<Content>> {
+    let mut kind = None;
+    let mut external = false;
+    for attribute in attributes {
+        let attribute = attribute?;
+        match reader.decode(attribute.key).as_ref() {
+            "type" => kind = Some(reader.decode(attribute.value).to_lowercase()),
+            "src" => external = true,
+            _ => (),
+        }
+    }
+    let xhtml = match kind.as_ref().map(|s| s.as_str()) {
+        Some("xhtml") | Some("application/xhtml+xml") => true,
+        _ => false,
+    };
+    let html = match kind.as_ref().map(|s| s.as_str()) {
+        Some("html") | Some("text/html") => true,
+        Some("text") | Some("text/plain") => false,
+        _ => html || xhtml,
+    };
+    let markup = parse_markup(reader, xhtml)?;
+    // Atom content can link to somewhere else instead
+    if external || markup.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(if html {
+        Content::Html(markup)
+    } else {
+        Content::Text(markup)
+    }))
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct RSS {
     pub title: String,
     pub link: String,
     pub source: Option<String>,
+    /// The WebSub hub the feed advertises
+    pub hub: Option<String>,
+    pub hints: UpdateHints,
     pub items: Vec<Item>,
 }
 
+/// What a feed says about how often it should be fetched
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateHints {
+    /// `<ttl>`, in minutes
+    pub ttl: Option<u64>,
+    /// `sy:updatePeriod` divided by `sy:updateFrequency`, in seconds
+    pub update_period: Option<u64>,
+    /// `<skipHours>`, 0 to 23 in UTC
+    pub skip_hours: Vec<u32>,
+    /// `<skipDays>`
+    pub skip_days: Vec<Weekday>,
+}
+
+/// Seconds of a `sy:updatePeriod`
+fn parse_update_period(s: &str) -> Option<u64> {
+    match s.trim() {
+        "hourly" => Some(60 * 60),
+        "daily" => Some(24 * 60 * 60),
+        "weekly" => Some(7 * 24 * 60 * 60),
+        "monthly" => Some(30 * 24 * 60 * 60),
+        "yearly" => Some(365 * 24 * 60 * 60),
+        _ => None,
+    }
+}
+
+/// The text of each element in a list like `<skipHours>`
+fn parse_text_list<B: std::io::BufRead>(reader: &mut XmlReader<B>) -> Result<Vec<String>> {
+    let mut buf = Vec::new();
+    let mut list = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(XmlEvent::Start(_)) => {
+                if let Some(text) = try_parse_text(reader)? {
+                    list.push(text);
+                }
+            }
+            Ok(XmlEvent::End(_)) | Ok(XmlEvent::Eof) => break,
+            Err(err) => return Err(err.into()),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(list)
+}
+
 impl FromXml for RSS {
     fn from_xml<B: std::io::BufRead>(
         reader: &mut XmlReader<B>,
-        _start: &BytesStart,
+        start: &BytesStart,
+        base: &Base,
     ) -> Result<Self> {
+        let base = base.of(reader, start)?;
         let mut buf = Vec::new();
         let mut rss = RSS::default();
+        let mut update_period = None;
+        let mut update_frequency = None;
         loop {
             match reader.read_event(&mut buf) {
                 Ok(XmlEvent::Empty(ref e)) => {
                     if reader.decode(e.local_name()) == "link" {
-                        match parse_atom_link(reader, e.attributes())? {
+                        match parse_atom_link(reader, e.attributes(), &base)? {
                             Some(AtomLink::Alternate(link)) => rss.link = link,
                             Some(AtomLink::Source(link)) => rss.source = Some(link),
+                            Some(AtomLink::Hub(link)) => rss.hub = Some(link),
                             _ => {}
                         }
                     }
@@ -127,9 +400,11 @@ impl FromXml for RSS {
                     match reader.decode(e.local_name()).as_ref() {
                         "channel" => {
                             // RSS 0.9 1.0
-                            let rdf = RSS::from_xml(reader, e)?;
+                            let rdf = RSS::from_xml(reader, e, &base)?;
                             rss.title = rdf.title;
                             rss.link = rdf.link;
+                            rss.hub = rdf.hub;
+                            rss.hints = rdf.hints;
                         }
                         "title" => {
                             if let Some(title) = try_parse_text(reader)? {
@@ -139,18 +414,45 @@ impl FromXml for RSS {
                         "link" => {
                             if let Some(link) = try_parse_text(reader)? {
                                 // RSS
-                                rss.link = link;
+                                rss.link = base.of(reader, e)?.resolve(link);
                             } else {
                                 // ATOM
-                                match parse_atom_link(reader, e.attributes())? {
+                                match parse_atom_link(reader, e.attributes(), &base)? {
                                     Some(AtomLink::Alternate(link)) => rss.link = link,
                                     Some(AtomLink::Source(link)) => rss.source = Some(link),
+                                    Some(AtomLink::Hub(link)) => rss.hub = Some(link),
                                     _ => {}
                                 }
                             }
                         }
                         "item" | "entry" => {
-                            rss.items.push(Item::from_xml(reader, e)?);
+                            rss.items.push(Item::from_xml(reader, e, &base)?);
+                        }
+                        "ttl" => {
+                            rss.hints.ttl = try_parse_text(reader)?
+                                .and_then(|s| s.trim().parse().ok());
+                        }
+                        "updatePeriod" => {
+                            update_period = try_parse_text(reader)?
+                                .and_then(|s| parse_update_period(&s));
+                        }
+                        "updateFrequency" => {
+                            update_frequency = try_parse_text(reader)?
+                                .and_then(|s| s.trim().parse().ok());
+                        }
+                        // the case varies in the wild
+                        "skipHours" | "skiphours" => {
+                            rss.hints.skip_hours = parse_text_list(reader)?
+                                .iter()
+                                .filter_map(|s| s.trim().parse().ok())
+                                .filter(|&hour| hour < 24)
+                                .collect();
+                        }
+                        "skipDays" | "skipdays" => {
+                            rss.hints.skip_days = parse_text_list(reader)?
+                                .iter()
+                                .filter_map(|s| date::parse_weekday(s.trim()))
+                                .collect();
                         }
                         _ => skip_element(reader)?,
                     }
@@ -161,54 +463,243 @@ impl FromXml for RSS {
             }
             buf.clear();
         }
+        // once a day if only one of them is given
+        if update_period.is_some() || update_frequency.is_some() {
+            let frequency = max(update_frequency.unwrap_or(1), 1);
+            rss.hints.update_period = Some(update_period.unwrap_or(24 * 60 * 60) / frequency);
+        }
         Ok(rss)
     }
 }
 
+/// The body of an item, HTML comes straight from the feed and is not sanitized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    Text(String),
+    Html(String),
+}
+
+impl Content {
+    /// The content as plain text, whitespaces are collapsed
+    pub fn to_text(&self) -> String {
+        let text = match *self {
+            Content::Text(ref text) => Cow::Borrowed(text.as_str()),
+            Content::Html(ref html) => Cow::Owned(html::to_text(html)),
+        };
+        text.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    /// The content in the markup of Telegram's HTML parse mode
+    pub fn to_telegram_html(&self) -> String {
+        match *self {
+            Content::Text(ref text) => Escape(text.trim()).to_string(),
+            Content::Html(ref html) => html::to_telegram_html(html),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Audio,
+    Video,
+    Other,
+}
+
+impl MediaKind {
+    /// Guesses the kind from the `medium` of Media RSS, the MIME type or the file extension
+    fn guess(medium: Option<&str>, mime_type: Option<&str>, url: &str) -> MediaKind {
+        let kind = medium.or_else(|| mime_type.and_then(|t| t.split('/').next()));
+        match kind.map(|s| s.trim().to_lowercase()).as_ref().map(|s| s.as_str()) {
+            Some("image") => return MediaKind::Image,
+            Some("audio") => return MediaKind::Audio,
+            Some("video") => return MediaKind::Video,
+            // `application/octet-stream` and the like
+            _ => (),
+        }
+        let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let extension = match name.rfind('.') {
+            Some(i) => name[i + 1..].to_lowercase(),
+            None => return MediaKind::Other,
+        };
+        match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" => MediaKind::Image,
+            "mp3" | "m4a" | "ogg" | "oga" | "opus" | "aac" | "flac" | "wav" => MediaKind::Audio,
+            "mp4" | "m4v" | "webm" | "mov" => MediaKind::Video,
+            _ => MediaKind::Other,
+        }
+    }
+}
+
+/// A file attached to an item, from `enclosure`, Media RSS or JSON Feed's `attachments`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Media {
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// Size in bytes
+    pub length: Option<u64>,
+    pub kind: MediaKind,
+}
+
+impl Media {
+    fn new(
+        url: String,
+        mime_type: Option<String>,
+        length: Option<u64>,
+        medium: Option<&str>,
+    ) -> Media {
+        let kind = MediaKind::guess(medium, mime_type.as_ref().map(|s| s.as_str()), &url);
+        Media {
+            url: url,
+            mime_type: mime_type,
+            length: length,
+            kind: kind,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Item {
     pub title: Option<String>,
     pub link: Option<String>,
     pub id: Option<String>,
+    /// `description` of RSS, `summary` of Atom and JSON Feed
+    pub summary: Option<Content>,
+    /// `content:encoded` of RSS, `content` of Atom and JSON Feed
+    pub content: Option<Content>,
+    /// `enclosure` of RSS and Atom, `media:content` of Media RSS
+    pub media: Vec<Media>,
+    /// `media:thumbnail` of Media RSS
+    pub thumbnail: Option<String>,
+    /// `pubDate` of RSS, `dc:date`, `published` of Atom 1.0 or `issued` of Atom 0.3
+    pub published: Option<DateTime<FixedOffset>>,
+    /// `updated` of Atom 1.0 or `modified` of Atom 0.3
+    pub updated: Option<DateTime<FixedOffset>>,
+}
+
+impl Item {
+    /// When the item was published, or else last updated
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.published.or(self.updated)
+    }
+
+    /// Feeds often put the same file in both `enclosure` and `media:content`
+    fn add_media(&mut self, media: Media) {
+        if self.media.iter().all(|m| m.url != media.url) {
+            self.media.push(media);
+        }
+    }
+
+    /// An excerpt of the item in Telegram HTML, at most `max` chars of text
+    pub fn excerpt(&self, max: usize) -> Option<String> {
+        let content = self.summary.as_ref().or_else(|| self.content.as_ref())?;
+        let text = content.to_text();
+        // some feeds just repeat the title
+        if text.is_empty() || Some(&text) == self.title.as_ref() {
+            return None;
+        }
+        Some(html::truncate(&content.to_telegram_html(), max))
+    }
 }
 
 impl FromXml for Item {
     fn from_xml<B: std::io::BufRead>(
         reader: &mut XmlReader<B>,
-        _start: &BytesStart,
+        start: &BytesStart,
+        base: &Base,
     ) -> Result<Self> {
+        let base = base.of(reader, start)?;
         let mut buf = Vec::new();
         let mut item = Item::default();
         loop {
             match reader.read_event(&mut buf) {
-                Ok(XmlEvent::Empty(ref e)) => {
-                    if reader.decode(e.name()).as_ref() == "link" {
-                        if let Some(AtomLink::Alternate(link)) =
-                            parse_atom_link(reader, e.attributes())?
-                        {
-                            item.link = Some(link);
+                Ok(XmlEvent::Empty(ref e)) => match reader.decode(e.local_name()).as_ref() {
+                    "link" => match parse_atom_link(reader, e.attributes(), &base)? {
+                        Some(AtomLink::Alternate(link)) => item.link = Some(link),
+                        Some(AtomLink::Enclosure(media)) => item.add_media(media),
+                        _ => (),
+                    },
+                    // and `media:content`
+                    "enclosure" | "content" => {
+                        if let Some(media) = parse_media(reader, e.attributes(), &base)? {
+                            item.add_media(media);
                         }
                     }
-                }
+                    // `media:thumbnail`
+                    "thumbnail" if item.thumbnail.is_none() => {
+                        item.thumbnail = parse_thumbnail(reader, e.attributes(), &base)?;
+                    }
+                    _ => (),
+                },
                 Ok(XmlEvent::Start(ref e)) => {
-                    match reader.decode(e.name()).as_ref() {
-                        "title" => {
+                    // feeds choose their own prefixes, so only the local name is matched,
+                    // the first element wins over the ones of other namespaces, like `itunes:title`
+                    match reader.decode(e.local_name()).as_ref() {
+                        "title" if item.title.is_none() => {
                             item.title = try_parse_text(reader)?;
                         }
                         "link" => {
                             if let Some(link) = try_parse_text(reader)? {
                                 // RSS
-                                item.link = Some(link);
-                            } else if let Some(AtomLink::Alternate(link)) =
-                                parse_atom_link(reader, e.attributes())?
-                            {
+                                item.link = Some(base.of(reader, e)?.resolve(link));
+                            } else {
                                 // ATOM
-                                item.link = Some(link);
+                                match parse_atom_link(reader, e.attributes(), &base)? {
+                                    Some(AtomLink::Alternate(link)) => item.link = Some(link),
+                                    Some(AtomLink::Enclosure(media)) => item.add_media(media),
+                                    _ => (),
+                                }
                             }
                         }
                         "id" | "guid" => {
                             item.id = try_parse_text(reader)?;
                         }
+                        // Atom 0.3 may have multiple content, the first one is used
+                        "description" if item.summary.is_none() => {
+                            item.summary = parse_content(reader, e.attributes(), true)?;
+                        }
+                        "summary" if item.summary.is_none() => {
+                            item.summary = parse_content(reader, e.attributes(), false)?;
+                        }
+                        // `content:encoded`
+                        "encoded" if item.content.is_none() => {
+                            item.content = parse_content(reader, e.attributes(), true)?;
+                        }
+                        "content" if !is_media_content(e) && item.content.is_none() => {
+                            item.content = parse_content(reader, e.attributes(), false)?;
+                        }
+                        // and `media:content`, other `content` have no `url` and are skipped
+                        "enclosure" | "content" => {
+                            if let Some(media) = parse_media(reader, e.attributes(), &base)? {
+                                item.add_media(media);
+                            }
+                            skip_element(reader)?;
+                        }
+                        "thumbnail" => {
+                            if item.thumbnail.is_none() {
+                                item.thumbnail = parse_thumbnail(reader, e.attributes(), &base)?;
+                            }
+                            skip_element(reader)?;
+                        }
+                        // `dc:date`
+                        "pubDate" | "date" | "published" | "issued"
+                            if item.published.is_none() =>
+                        {
+                            item.published = try_parse_date(reader)?;
+                        }
+                        "updated" | "modified" if item.updated.is_none() => {
+                            item.updated = try_parse_date(reader)?;
+                        }
+                        // `media:group`
+                        "group" => {
+                            // a group has the same media elements as an item
+                            let group = Item::from_xml(reader, e, &base)?;
+                            for media in group.media {
+                                item.add_media(media);
+                            }
+                            item.thumbnail = item.thumbnail.or(group.thumbnail);
+                        }
                         _ => skip_element(reader)?,
                     }
                 }
@@ -222,16 +713,23 @@ impl FromXml for Item {
     }
 }
 
+/// Parses a feed without a document URL, its relative links are kept as they are
+#[cfg(test)]
 pub fn parse<B: std::io::BufRead>(reader: B) -> Result<RSS> {
+    parse_with_base(reader, &Base::default())
+}
+
+fn parse_with_base<B: std::io::BufRead>(reader: B, base: &Base) -> Result<RSS> {
     let mut reader = XmlReader::from_reader(reader);
     reader.trim_text(true);
     let mut buf = Vec::new();
     loop {
         match reader.read_event(&mut buf) {
-            Ok(XmlEvent::Start(ref e)) => match reader.decode(e.name()).as_ref() {
+            Ok(XmlEvent::Start(ref e)) => match reader.decode(e.local_name()).as_ref() {
                 "rss" => continue,
-                "channel" | "feed" | "rdf:RDF" => {
-                    return RSS::from_xml(&mut reader, e);
+                // `rdf:RDF`
+                "channel" | "feed" | "RDF" => {
+                    return RSS::from_xml(&mut reader, e, base);
                 }
                 _ => skip_element(&mut reader)?,
             },
@@ -243,122 +741,587 @@ pub fn parse<B: std::io::BufRead>(reader: B) -> Result<RSS> {
     }
 }
 
-fn set_url_relative_to_absolute(link: &mut String, host: &str) {
-    match link.as_str() {
-        _ if link.starts_with("//") => {
-            let mut s = String::from("http:");
-            s.push_str(link);
-            *link = s;
+#[derive(Deserialize)]
+struct JsonFeed {
+    version: String,
+    #[serde(default)]
+    title: String,
+    home_page_url: Option<String>,
+    feed_url: Option<String>,
+    #[serde(default)]
+    hubs: Vec<JsonFeedHub>,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedHub {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedItem {
+    // it's a string in the spec, but some feeds use numbers
+    id: Option<serde_json::Value>,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    image: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    #[serde(default)]
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: Option<String>,
+    size_in_bytes: Option<u64>,
+}
+
+impl From<JsonFeedItem> for Item {
+    fn from(item: JsonFeedItem) -> Item {
+        let id = match item.id {
+            Some(serde_json::Value::String(id)) => Some(id),
+            Some(serde_json::Value::Number(id)) => Some(id.to_string()),
+            _ => None,
+        };
+        let content_text = item.content_text;
+        let content = item.content_html
+            .map(Content::Html)
+            .or_else(|| content_text.map(Content::Text));
+        let mut result = Item {
+            title: item.title,
+            link: item.url.or(item.external_url),
+            id: id,
+            summary: item.summary.map(Content::Text),
+            content: content,
+            media: Vec::new(),
+            thumbnail: None,
+            published: item.date_published.and_then(|s| date::parse(&s)),
+            updated: item.date_modified.and_then(|s| date::parse(&s)),
+        };
+        if let Some(image) = item.image {
+            result.add_media(Media::new(image, None, None, Some("image")));
         }
-        _ if link.starts_with('/') => {
-            let mut s = String::from(host);
-            s.push_str(link);
-            *link = s;
+        for attachment in item.attachments {
+            let media = Media::new(
+                attachment.url,
+                attachment.mime_type,
+                attachment.size_in_bytes,
+                None,
+            );
+            result.add_media(media);
         }
-        _ => (),
+        result
     }
 }
 
-fn fix_relative_url(mut rss: RSS, rss_link: &str) -> RSS {
+/// Parse JSON Feed 1.0 and 1.1, https://jsonfeed.org/version/1.1
+pub fn parse_json<R: std::io::Read>(reader: R) -> Result<RSS> {
+    let feed: JsonFeed = serde_json::from_reader(reader)?;
+    if !feed.version.starts_with("https://jsonfeed.org/version/") {
+        return Err(ErrorKind::EmptyFeed.into());
+    }
+    Ok(RSS {
+        title: feed.title,
+        link: feed.home_page_url.unwrap_or_default(),
+        source: feed.feed_url,
+        hub: feed.hubs
+            .into_iter()
+            .find(|hub| hub.kind.eq_ignore_ascii_case("WebSub"))
+            .map(|hub| hub.url),
+        hints: UpdateHints::default(),
+        items: feed.items.into_iter().map(Item::from).collect(),
+    })
+}
+
+fn strip_bom(body: &[u8]) -> &[u8] {
+    if body.starts_with(b"\xEF\xBB\xBF") {
+        &body[3..]
+    } else {
+        body
+    }
+}
+
+/// The `charset` of a `Content-Type` header
+fn charset_of_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut param = param.splitn(2, '=');
+            match (param.next(), param.next()) {
+                (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("charset") => {
+                    let label = v.trim().trim_matches(|c: char| c == '"' || c == '\'');
+                    Encoding::for_label(label.as_bytes())
+                }
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// The `encoding` of an XML declaration
+fn charset_of_xml_declaration(body: &[u8]) -> Option<&'static Encoding> {
+    lazy_static! {
+        static ref ENCODING: BytesRegex =
+            BytesRegex::new(r#"^\s*<\?xml[^>]*?\sencoding\s*=\s*["']([^"']+)["']"#).unwrap();
+    }
+    let head = &body[..min(body.len(), 200)];
+    ENCODING
+        .captures(head)
+        .and_then(|captures| Encoding::for_label(captures.get(1).unwrap().as_bytes()))
+}
+
+/// Converts the body of a feed into UTF-8.
+///
+/// The charset is from a BOM, or else the `Content-Type` header or the XML declaration,
+/// whichever decodes the body without errors, since servers are often wrong about it.
+/// A single-byte charset decodes anything, so a body that is valid UTF-8 is taken as UTF-8
+/// instead. Without a charset it's UTF-8, and the invalid bytes are replaced if nothing fits.
+///
+/// The XML declaration is changed to UTF-8 as well, or the XML reader would decode
+/// the text again with the original encoding.
+fn decode_body<'a>(content_type: Option<&str>, body: &'a [u8]) -> Cow<'a, str> {
     lazy_static! {
-        static ref HOST: Regex = Regex::new(r"^(https?://[^/]+)").unwrap();
+        static ref ENCODING: Regex =
+            Regex::new(r#"^\s*<\?xml[^>]*?\sencoding\s*=\s*["']([^"']+)["']"#).unwrap();
+    }
+    let text = decode_with_charset(content_type, body);
+    let declared = ENCODING.captures(&text).map(|captures| {
+        let encoding = captures.get(1).unwrap();
+        (encoding.start()..encoding.end(), encoding.as_str().eq_ignore_ascii_case("UTF-8"))
+    });
+    match declared {
+        Some((range, false)) => {
+            let mut text = text.into_owned();
+            text.replace_range(range, "UTF-8");
+            Cow::Owned(text)
+        }
+        _ => text,
+    }
+}
+
+fn decode_with_charset<'a>(content_type: Option<&str>, body: &'a [u8]) -> Cow<'a, str> {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(body) {
+        return encoding.decode_without_bom_handling(&body[bom_len..]).0;
+    }
+    let candidates = [
+        content_type.and_then(charset_of_content_type),
+        charset_of_xml_declaration(body),
+        Some(UTF_8),
+    ];
+    for encoding in candidates.iter().filter_map(|&encoding| encoding) {
+        // mislabeled UTF-8 is far more common than Latin-1 text that happens to be valid UTF-8
+        if is_single_byte(encoding) {
+            if let Ok(text) = str::from_utf8(body) {
+                return Cow::Borrowed(text);
+            }
+        }
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(body) {
+            return text;
+        }
     }
-    let rss_host = HOST.captures(rss_link)
-        .map_or(rss_link, |r| r.get(0).unwrap().as_str());
-    match rss.link.as_str() {
-        "" | "/" => rss.link = rss_host.to_owned(),
-        _ => set_url_relative_to_absolute(&mut rss.link, rss_host),
+    let encoding = candidates.iter().filter_map(|&encoding| encoding).next().unwrap();
+    encoding.decode_without_bom_handling(body).0
+}
+
+/// Single-byte charsets map every byte to a char, so they never fail to decode
+fn is_single_byte(encoding: &'static Encoding) -> bool {
+    let multi_byte = [UTF_8, GBK, GB18030, BIG5, EUC_JP, EUC_KR, SHIFT_JIS];
+    encoding.is_ascii_compatible() && !multi_byte.contains(&encoding)
+}
+
+fn is_json_feed(content_type: Option<&str>, body: &[u8]) -> bool {
+    match content_type {
+        Some(t) if t.contains("json") => true,
+        _ => strip_bom(body).iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{'),
+    }
+}
+
+fn is_html(content_type: Option<&str>, body: &[u8]) -> bool {
+    match content_type {
+        Some(t) if t.contains("html") => true,
+        _ => {
+            let body = strip_bom(body);
+            let start = body.iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .unwrap_or_else(|| body.len());
+            let head = body[start..min(start + 14, body.len())].to_ascii_lowercase();
+            head.starts_with(b"<!doctype html") || head.starts_with(b"<html")
+        }
     }
+}
+
+/// Resolves `link` found in the page at `base`
+fn resolve_url(link: &str, base: &str) -> String {
+    Url::parse(base)
+        .ok()
+        .and_then(|base| join_url(&base, link))
+        .unwrap_or_else(|| link.to_owned())
+}
+
+/// Resolves the links left relative after parsing, against the channel link,
+/// or else `rss_link` the feed is fetched from
+fn fix_relative_url(mut rss: RSS, rss_link: &str) -> RSS {
+    let document = match Url::parse(rss_link) {
+        Ok(url) => url,
+        Err(_) => return rss,
+    };
+    let link = match rss.link.trim() {
+        // the home page
+        "" => join_url(&document, "/"),
+        link => join_url(&document, link),
+    };
+    if let Some(link) = link {
+        rss.link = link;
+    }
+    // a channel link that's not a web page, like `mailto:`, is no base
+    let base = Url::parse(&rss.link)
+        .ok()
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+        .unwrap_or(document);
+    let resolve = |link: &mut String| {
+        if let Some(resolved) = join_url(&base, link) {
+            *link = resolved;
+        }
+    };
     for item in &mut rss.items {
         if let Some(link) = item.link.as_mut() {
-            set_url_relative_to_absolute(link, rss_host);
+            resolve(link);
+        }
+        for media in &mut item.media {
+            resolve(&mut media.url);
+        }
+        if let Some(thumbnail) = item.thumbnail.as_mut() {
+            resolve(thumbnail);
         }
     }
 
     rss
 }
 
-#[async]
+/// HTTP cache validators of a feed, used to make conditional requests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// A conditional fetch of a feed
+#[derive(Debug)]
+pub struct Fetched {
+    /// `None` if the feed is not modified
+    pub rss: Option<RSS>,
+    pub validators: Validators,
+    /// Seconds the response is fresh for, from `Cache-Control` or `Expires`
+    pub max_age: Option<u64>,
+    /// Where the feed is redirected permanently
+    pub moved_to: Option<String>,
+}
+
+#[derive(Debug)]
+struct Response {
+    body: Vec<u8>,
+    source: String,
+    code: u32,
+    content_type: Option<String>,
+    validators: Validators,
+    max_age: Option<u64>,
+}
+
+fn find_header(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+        .map(|&(_, ref v)| v.to_owned())
+}
+
+/// `max-age` of `Cache-Control`, or `Expires` minus `Date`
+fn max_age(headers: &[(String, String)]) -> Option<u64> {
+    let cache_control = find_header(headers, "Cache-Control").unwrap_or_default();
+    for directive in cache_control.split(',') {
+        let directive = directive.trim().to_lowercase();
+        if directive.starts_with("max-age=") {
+            return directive["max-age=".len()..].trim_matches('"').parse().ok();
+        }
+    }
+    let expires = date::parse(&find_header(headers, "Expires")?)?;
+    let date = date::parse(&find_header(headers, "Date")?)?;
+    Some(max(expires.signed_duration_since(date).num_seconds(), 0) as u64)
+}
+
+/// Whether a response code is a permanent redirect, `None` if it's not a redirect
+fn is_permanent_redirect(code: u32) -> Option<bool> {
+    match code {
+        301 | 308 => Some(true),
+        302 | 303 | 307 => Some(false),
+        _ => None,
+    }
+}
+
+/// The `Location` of a redirect from `url`, it may be relative
+fn resolve_location(url: &str, location: &str) -> Option<String> {
+    Url::parse(url)
+        .or_else(|_| Url::parse(&format!("http://{}", url)))
+        .ok()
+        .and_then(|url| join_url(&url, location))
+}
+
+/// A single response, before the redirects are followed
+struct RawResponse {
+    code: u32,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Follows the redirects, `source` of the response is changed only if every one of them
+/// is permanent
 fn make_request(
     session: Session,
-    mut source: String,
+    source: String,
     ua: String,
+    validators: Validators,
+    recur_limit: usize,
+) -> impl Future<Item = Response, Error = Error> {
+    let send = move |url: &str, validators: &Validators| {
+        send_request(session.clone(), url.to_owned(), ua.clone(), validators.clone())
+    };
+    follow_redirects(send, source, validators, recur_limit)
+}
+
+#[async]
+fn send_request(
+    session: Session,
+    url: String,
+    ua: String,
+    validators: Validators,
+) -> Result<RawResponse> {
+    let mut req = Easy::new();
+    let buf = Arc::new(Mutex::new(Vec::new()));
+    let headers_buf = Arc::new(Mutex::new(Vec::new()));
+    {
+        let buf = Arc::clone(&buf);
+        let headers_buf = Arc::clone(&headers_buf);
+        let mut request_headers = List::new();
+        if let Some(ref etag) = validators.etag {
+            request_headers
+                .append(&format!("If-None-Match: {}", etag))
+                .unwrap();
+        }
+        if let Some(ref last_modified) = validators.last_modified {
+            request_headers
+                .append(&format!("If-Modified-Since: {}", last_modified))
+                .unwrap();
+        }
+        req.get(true).unwrap();
+        req.url(&url).unwrap();
+        req.accept_encoding("").unwrap(); // accept all encoding
+        req.useragent(&ua).unwrap();
+        req.http_headers(request_headers).unwrap();
+        req.timeout(Duration::from_secs(10)).unwrap();
+        req.write_function(move |data| {
+            buf.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }).unwrap();
+        req.header_function(move |data| {
+            let header = String::from_utf8_lossy(data);
+            let mut header = header.splitn(2, ':');
+            if let (Some(k), Some(v)) = (header.next(), header.next()) {
+                headers_buf
+                    .lock()
+                    .unwrap()
+                    .push((k.trim().to_owned(), v.trim().to_owned()));
+            }
+            true
+        }).unwrap();
+    }
+    let mut resp = await!(session.perform(req))?;
+    let response_code = resp.response_code().unwrap();
+    ::std::mem::drop(resp); // make `buf` and `headers_buf` strong count to zero
+    Ok(RawResponse {
+        code: response_code,
+        headers: Arc::try_unwrap(headers_buf).unwrap().into_inner().unwrap(),
+        body: Arc::try_unwrap(buf).unwrap().into_inner().unwrap(),
+    })
+}
+
+/// The redirect loop of `make_request`, `send` makes a single request to a url
+#[async]
+fn follow_redirects<F, R>(
+    mut send: F,
+    mut source: String,
+    validators: Validators,
     mut recur_limit: usize,
-) -> Result<(Vec<u8>, String, u32)> {
+) -> Result<Response>
+where
+    F: FnMut(&str, &Validators) -> R + 'static,
+    R: Future<Item = RawResponse, Error = Error> + 'static,
+{
     let mut location: Option<String> = None;
+    let mut moved = false;
+    let no_validators = Validators::default();
     loop {
         if recur_limit == 0 {
             break Err(ErrorKind::TooManyRedirects.into());
         }
-        let mut req = Easy::new();
-        let buf = Arc::new(Mutex::new(Vec::new()));
-        let location_buf = Arc::new(Mutex::new(String::new()));
-        {
-            let buf = Arc::clone(&buf);
-            let location_buf = Arc::clone(&location_buf);
-            req.get(true).unwrap();
-            req.url(location.as_ref().unwrap_or(&source)).unwrap();
-            req.accept_encoding("").unwrap(); // accept all encoding
-            req.useragent(&ua).unwrap();
-            req.timeout(Duration::from_secs(10)).unwrap();
-            req.write_function(move |data| {
-                buf.lock().unwrap().extend_from_slice(data);
-                Ok(data.len())
-            }).unwrap();
-            req.header_function(move |data| {
-                let header = String::from_utf8_lossy(data);
-                let mut header = header.splitn(2, ':');
-                if let (Some(k), Some(v)) = (header.next(), header.next()) {
-                    if k == "Location" || k.to_lowercase() == "location" {
-                        location_buf.lock().unwrap().push_str(v.trim());
-                    }
-                }
-                true
-            }).unwrap();
-        }
-        let mut resp = await!(session.perform(req))?;
-        let response_code = resp.response_code().unwrap();
-        ::std::mem::drop(resp); // make `buf` and `location_buf` strong count to zero
-        if response_code == 301 {
-            source = Arc::try_unwrap(location_buf).unwrap().into_inner().unwrap();
-            location = None;
-            recur_limit -= 1;
-        } else if response_code == 302 {
-            location = Some(Arc::try_unwrap(location_buf).unwrap().into_inner().unwrap());
+        let request = {
+            // they are of the old link, and a 304 would keep the feed from moving
+            let validators = if moved { &no_validators } else { &validators };
+            send(location.as_ref().unwrap_or(&source), validators)
+        };
+        let RawResponse {
+            code: response_code,
+            headers,
+            body,
+        } = await!(request)?;
+        let redirect = is_permanent_redirect(response_code).and_then(|permanent| {
+            let url = location.as_ref().unwrap_or(&source);
+            let target = resolve_location(url, &find_header(&headers, "Location")?)?;
+            Some((permanent, target))
+        });
+        if let Some((permanent, target)) = redirect {
+            // once redirected temporarily, the rest of the way may change too
+            if permanent && location.is_none() {
+                source = target;
+                moved = true;
+            } else {
+                location = Some(target);
+            }
             recur_limit -= 1;
         } else {
-            let body = Arc::try_unwrap(buf).unwrap().into_inner().unwrap();
-            break Ok((body, source, response_code));
+            let validators = Validators {
+                etag: find_header(&headers, "ETag"),
+                last_modified: find_header(&headers, "Last-Modified"),
+            };
+            break Ok(Response {
+                body: body,
+                source: source,
+                code: response_code,
+                content_type: find_header(&headers, "Content-Type"),
+                validators: validators,
+                max_age: max_age(&headers),
+            });
         }
     }
 }
 
+/// Fetches the feed at `source`, or the feed of a web page if the page links to only one
 pub fn fetch_feed<'a>(
     session: Session,
     ua: String,
     source: String,
 ) -> impl Future<Item = RSS, Error = Error> + 'a {
-    fn is_vaild_link(link: &str) -> bool {
-        link.starts_with("http://") || link.starts_with("https://")
-    };
-    make_request(session, source, ua, 10).and_then(move |(body, mut source, response_code)| {
+    let session2 = session.clone();
+    let ua2 = ua.clone();
+    fetch_feed_once(session, ua, source).or_else(move |e| match e {
+        Error(ErrorKind::WebPage(ref feeds), _) if feeds.len() == 1 => {
+            future::Either::A(fetch_feed_once(session2, ua2, feeds[0].link.clone()))
+        }
+        e => future::Either::B(future::err(e)),
+    })
+}
+
+fn fetch_feed_once<'a>(
+    session: Session,
+    ua: String,
+    source: String,
+) -> impl Future<Item = RSS, Error = Error> + 'a {
+    fetch_feed_if_modified(session, ua, source, Validators::default()).and_then(|r| {
+        r.rss.ok_or_else(|| ErrorKind::Http(304).into())
+    })
+}
+
+/// Same as `fetch_feed`, but sends `validators` with the request,
+/// the feed is `None` if it's not modified since then
+pub fn fetch_feed_if_modified<'a>(
+    session: Session,
+    ua: String,
+    source: String,
+    validators: Validators,
+) -> impl Future<Item = Fetched, Error = Error> + 'a {
+    let requested = source.clone();
+    make_request(session, source, ua, validators, 10).and_then(move |resp| {
+        let Response {
+            body,
+            mut source,
+            code: response_code,
+            content_type,
+            validators,
+            max_age,
+        } = resp;
+        let moved_to = if source != requested {
+            Some(source.clone())
+        } else {
+            None
+        };
+        if response_code == 304 {
+            return Ok(Fetched {
+                rss: None,
+                validators: validators,
+                max_age: max_age,
+                moved_to: moved_to,
+            });
+        }
         if response_code != 200 {
             return Err(ErrorKind::Http(response_code).into());
         }
-        let mut rss = parse(body.as_slice())?;
-        if rss == RSS::default() {
-            return Err(ErrorKind::EmptyFeed.into());
-        }
         if !is_vaild_link(&source) {
             source.insert_str(0, "http://");
         }
-        if rss.source.is_none() || !is_vaild_link(rss.source.as_ref().unwrap()) {
-            rss.source = Some(source.clone());
+        let content_type = content_type.as_ref().map(|s| s.as_str());
+        let mut rss = parse_body(content_type, &body, &source)?;
+        // more up to date than the link in the feed
+        if moved_to.is_some() {
+            rss.source = moved_to.clone();
         }
-        Ok(fix_relative_url(rss, &source))
+        Ok(Fetched {
+            rss: Some(rss),
+            validators: validators,
+            max_age: max_age,
+            moved_to: moved_to,
+        })
     })
 }
 
+fn is_vaild_link(link: &str) -> bool {
+    link.starts_with("http://") || link.starts_with("https://")
+}
+
+/// Parses the body of the feed at `source`, either fetched or pushed by a hub
+pub fn parse_body(content_type: Option<&str>, body: &[u8], source: &str) -> Result<RSS> {
+    let text = decode_body(content_type, body);
+    let mut rss = if is_json_feed(content_type, body) {
+        parse_json(text.as_bytes())?
+    } else {
+        match parse_with_base(text.as_bytes(), &Base::new(source)) {
+            // some servers send feeds as `text/html`, so it's only checked on failure
+            Err(_) if is_html(content_type, body) => {
+                let mut feeds = html::find_feed_links(&text);
+                for feed in &mut feeds {
+                    feed.link = resolve_url(&feed.link, source);
+                }
+                return Err(ErrorKind::WebPage(feeds).into());
+            }
+            r => r?,
+        }
+    };
+    if rss == RSS::default() {
+        return Err(ErrorKind::EmptyFeed.into());
+    }
+    if rss.source.is_none() || !is_vaild_link(rss.source.as_ref().unwrap()) {
+        rss.source = Some(source.to_owned());
+    }
+    Ok(fix_relative_url(rss, source))
+}
+
 #[test]
 fn test_atom03() {
     use std::io::Cursor;
@@ -370,16 +1333,30 @@ fn test_atom03() {
             title: "atom_0.3.feed.title".into(),
             link: "atom_0.3.feed.link^href".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints::default(),
             items: vec![
                 Item {
                     title: Some("atom_0.3.feed.entry[0].title".into()),
                     link: Some("atom_0.3.feed.entry[0].link^href".into()),
                     id: Some("atom_0.3.feed.entry[0]^id".into()),
+                    summary: Some(Content::Text("atom_0.3.feed.entry[0].summary".into())),
+                    content: Some(Content::Text("atom_0.3.feed.entry[0].content[0]".into())),
+                    media: vec![],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-01-01T01:00:00Z").ok(),
+                    updated: DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").ok(),
                 },
                 Item {
                     title: Some("atom_0.3.feed.entry[1].title".into()),
                     link: Some("atom_0.3.feed.entry[1].link^href".into()),
                     id: Some("atom_0.3.feed.entry[1]^id".into()),
+                    summary: Some(Content::Text("atom_0.3.feed.entry[1].summary".into())),
+                    content: Some(Content::Text("atom_0.3.feed.entry[1].content[0]".into())),
+                    media: vec![],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-02-01T01:00:00Z").ok(),
+                    updated: DateTime::parse_from_rfc3339("2000-02-01T00:00:00Z").ok(),
                 },
             ],
         }
@@ -397,16 +1374,44 @@ fn test_atom10() {
             title: "atom_1.0.feed.title".into(),
             link: "http://example.com/blog_plain".into(),
             source: Some("http://example.com/blog/atom_1.0.xml".into()),
+            hub: None,
+            hints: UpdateHints::default(),
             items: vec![
                 Item {
                     title: Some("atom_1.0.feed.entry[0].title".into()),
                     link: Some("http://example.com/blog/entry1_plain".into()),
                     id: Some("atom_1.0.feed.entry[0]^id".into()),
+                    summary: Some(Content::Html("atom_1.0.feed.entry[0].summary".into())),
+                    content: Some(Content::Html("atom_1.0.feed.entry[0].content[0]".into())),
+                    media: vec![
+                        Media::new(
+                            "http://example.com/blog/enclosure1.gif".into(),
+                            Some("image/gif".into()),
+                            None,
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-01-01T01:00:00Z").ok(),
+                    updated: DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").ok(),
                 },
                 Item {
                     title: Some("atom_1.0.feed.entry[1].title".into()),
                     link: Some("http://example.com/blog/entry2".into()),
                     id: Some("atom_1.0.feed.entry[1]^id".into()),
+                    summary: Some(Content::Html("atom_1.0.feed.entry[1].summary".into())),
+                    content: Some(Content::Html("atom_1.0.feed.entry[1].content[0]".into())),
+                    media: vec![
+                        Media::new(
+                            "http://example.com/blog/enclosure2.gif".into(),
+                            Some("image/gif".into()),
+                            None,
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-02-01T01:00:00Z").ok(),
+                    updated: DateTime::parse_from_rfc3339("2000-02-01T00:00:00Z").ok(),
                 },
             ],
         }
@@ -424,16 +1429,30 @@ fn test_rss09() {
             title: "rss_0.9.channel.title".into(),
             link: "rss_0.9.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints::default(),
             items: vec![
                 Item {
                     title: Some("rss_0.9.item[0].title".into()),
                     link: Some("rss_0.9.item[0].link".into()),
                     id: None,
+                    summary: None,
+                    content: None,
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_0.9.item[1].title".into()),
                     link: Some("rss_0.9.item[1].link".into()),
                     id: None,
+                    summary: None,
+                    content: None,
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
             ],
         }
@@ -451,16 +1470,43 @@ fn test_rss091() {
             title: "rss_0.91.channel.title".into(),
             link: "rss_0.91.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints {
+                ttl: None,
+                update_period: None,
+                skip_hours: (0..22).chain(Some(23)).collect(),
+                skip_days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            },
             items: vec![
                 Item {
                     title: Some("rss_0.91.channel.item[0].title".into()),
                     link: Some("rss_0.91.channel.item[0].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.91.channel.item[0].description".into())),
+                    content: None,
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_0.91.channel.item[1].title".into()),
                     link: Some("rss_0.91.channel.item[1].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.91.channel.item[1].description".into())),
+                    content: None,
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
             ],
         }
@@ -478,16 +1524,57 @@ fn test_rss092() {
             title: "rss_0.92.channel.title".into(),
             link: "rss_0.92.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints {
+                ttl: None,
+                update_period: None,
+                skip_hours: (1..22).chain(Some(23)).collect(),
+                skip_days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            },
             items: vec![
                 Item {
                     title: Some("rss_0.92.channel.item[0].title".into()),
                     link: Some("rss_0.92.channel.item[0].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.92.channel.item[0].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.92.channel.item[0].enclousure[0]^url".into(),
+                            Some("rss_0.92.channel.item[0].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_0.92.channel.item[1].title".into()),
                     link: Some("rss_0.92.channel.item[1].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.92.channel.item[1].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.92.channel.item[1].enclousure[0]^url".into(),
+                            Some("rss_0.92.channel.item[1].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
             ],
         }
@@ -505,16 +1592,69 @@ fn test_rss093() {
             title: "rss_0.93.channel.title".into(),
             link: "rss_0.93.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints {
+                ttl: None,
+                update_period: None,
+                skip_hours: (1..22).chain(Some(23)).collect(),
+                skip_days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            },
             items: vec![
                 Item {
                     title: Some("rss_0.93.channel.item[0].title".into()),
                     link: Some("rss_0.93.channel.item[0].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.93.channel.item[0].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.93.channel.item[0].enclousure[0]^url".into(),
+                            Some("rss_0.93.channel.item[0].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_0.93.channel.item[0].enclousure[1]^url".into(),
+                            Some("rss_0.93.channel.item[0].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").ok(),
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_0.93.channel.item[1].title".into()),
                     link: Some("rss_0.93.channel.item[1].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_0.93.channel.item[1].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.93.channel.item[1].enclousure[0]^url".into(),
+                            Some("rss_0.93.channel.item[1].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_0.93.channel.item[1].enclousure[1]^url".into(),
+                            Some("rss_0.93.channel.item[1].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-02T00:00:00Z").ok(),
+                    updated: None,
                 },
             ],
         }
@@ -532,16 +1672,69 @@ fn test_rss094() {
             title: "rss_0.94.channel.title".into(),
             link: "rss_0.94.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints {
+                ttl: Some(100),
+                update_period: None,
+                skip_hours: (1..22).chain(Some(23)).collect(),
+                skip_days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            },
             items: vec![
                 Item {
                     title: Some("rss_0.94.channel.item[0].title".into()),
                     link: Some("rss_0.94.channel.item[0].link".into()),
                     id: Some("rss_0.94.channel.item[0].guid".into()),
+                    summary: Some(Content::Html("rss_0.94.channel.item[0].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.94.channel.item[0].enclousure[0]^url".into(),
+                            Some("rss_0.94.channel.item[0].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_0.94.channel.item[0].enclousure[1]^url".into(),
+                            Some("rss_0.94.channel.item[0].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").ok(),
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_0.94.channel.item[1].title".into()),
                     link: Some("rss_0.94.channel.item[1].link".into()),
                     id: Some("rss_0.94.channel.item[1].guid".into()),
+                    summary: Some(Content::Html("rss_0.94.channel.item[1].description".into())),
+                    content: None,
+                    media: vec![
+                        Media::new(
+                            "rss_0.94.channel.item[1].enclousure[0]^url".into(),
+                            Some("rss_0.94.channel.item[1].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_0.94.channel.item[1].enclousure[1]^url".into(),
+                            Some("rss_0.94.channel.item[1].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-02T00:00:00Z").ok(),
+                    updated: None,
                 },
             ],
         }
@@ -559,16 +1752,30 @@ fn test_rss10() {
             title: "rss_1.0.channel.title".into(),
             link: "rss_1.0.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints::default(),
             items: vec![
                 Item {
                     title: Some("rss_1.0.item[0].title".into()),
                     link: Some("rss_1.0.item[0].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_1.0.item[0].description".into())),
+                    content: Some(Content::Html("rss_1.0.item[0].content".into())),
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_1.0.item[1].title".into()),
                     link: Some("rss_1.0.item[1].link".into()),
                     id: None,
+                    summary: Some(Content::Html("rss_1.0.item[1].description".into())),
+                    content: Some(Content::Html("rss_1.0.item[1].content".into())),
+                    media: vec![],
+                    thumbnail: None,
+                    published: None,
+                    updated: None,
                 },
             ],
         }
@@ -586,22 +1793,191 @@ fn test_rss20() {
             title: "rss_2.0.channel.title".into(),
             link: "rss_2.0.channel.link".into(),
             source: None,
+            hub: None,
+            hints: UpdateHints {
+                ttl: Some(100),
+                update_period: None,
+                skip_hours: (0..22).chain(Some(23)).collect(),
+                skip_days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            },
             items: vec![
                 Item {
                     title: Some("rss_2.0.channel.item[0].title".into()),
                     link: Some("rss_2.0.channel.item[0].link".into()),
                     id: Some("rss_2.0.channel.item[0].guid".into()),
+                    summary: Some(Content::Html("rss_2.0.channel.item[0].description".into())),
+                    content: Some(Content::Html("rss_2.0.channel.item[0].content".into())),
+                    media: vec![
+                        Media::new(
+                            "rss_2.0.channel.item[0].enclousure[0]^url".into(),
+                            Some("rss_2.0.channel.item[0].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_2.0.channel.item[0].enclousure[1]^url".into(),
+                            Some("rss_2.0.channel.item[0].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-01T00:00:00Z").ok(),
+                    updated: None,
                 },
                 Item {
                     title: Some("rss_2.0.channel.item[1].title".into()),
                     link: Some("rss_2.0.channel.item[1].link".into()),
                     id: Some("rss_2.0.channel.item[1].guid".into()),
+                    summary: Some(Content::Html("rss_2.0.channel.item[1].description".into())),
+                    content: Some(Content::Html("rss_2.0.channel.item[1].content".into())),
+                    media: vec![
+                        Media::new(
+                            "rss_2.0.channel.item[1].enclousure[0]^url".into(),
+                            Some("rss_2.0.channel.item[1].enclousure[0]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                        Media::new(
+                            "rss_2.0.channel.item[1].enclousure[1]^url".into(),
+                            Some("rss_2.0.channel.item[1].enclousure[1]^type".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2001-01-02T00:00:00Z").ok(),
+                    updated: None,
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn test_json_feed10() {
+    let s = include_str!("../tests/data/json_feed_1.0.json");
+    let r = parse_json(s.as_bytes()).unwrap();
+    assert_eq!(
+        r,
+        RSS {
+            title: "json_feed_1.0.title".into(),
+            link: "http://example.com/blog".into(),
+            source: Some("http://example.com/blog/json_feed_1.0.json".into()),
+            hub: None,
+            hints: UpdateHints::default(),
+            items: vec![
+                Item {
+                    title: Some("json_feed_1.0.items[0].title".into()),
+                    link: Some("http://example.com/blog/entry1".into()),
+                    id: Some("json_feed_1.0.items[0].id".into()),
+                    summary: Some(Content::Text("json_feed_1.0.items[0].summary".into())),
+                    content: Some(Content::Html(
+                        "<p>json_feed_1.0.items[0].content_html</p>".into(),
+                    )),
+                    media: vec![
+                        Media::new(
+                            "http://example.com/blog/entry1.png".into(),
+                            None,
+                            None,
+                            Some("image"),
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-01-01T01:00:00Z").ok(),
+                    updated: DateTime::parse_from_rfc3339("2000-01-01T02:00:00Z").ok(),
+                },
+                Item {
+                    title: Some("json_feed_1.0.items[1].title".into()),
+                    link: Some("http://example.com/blog/entry2_external".into()),
+                    id: Some("2".into()),
+                    summary: None,
+                    content: Some(Content::Text("json_feed_1.0.items[1].content_text".into())),
+                    media: vec![
+                        Media::new(
+                            "http://example.com/blog/entry2.mp3".into(),
+                            Some("audio/mpeg".into()),
+                            Some(100),
+                            None,
+                        ),
+                    ],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-02-01T01:00:00Z").ok(),
+                    updated: None,
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn test_json_feed11() {
+    let s = include_str!("../tests/data/json_feed_1.1.json");
+    let r = parse_json(s.as_bytes()).unwrap();
+    assert_eq!(
+        r,
+        RSS {
+            title: "json_feed_1.1.title".into(),
+            link: "http://example.com/blog".into(),
+            source: Some("http://example.com/blog/json_feed_1.1.json".into()),
+            hub: Some("http://example.com/hub".into()),
+            hints: UpdateHints::default(),
+            items: vec![
+                Item {
+                    title: Some("json_feed_1.1.items[0].title".into()),
+                    link: Some("http://example.com/blog/entry1".into()),
+                    id: Some("json_feed_1.1.items[0].id".into()),
+                    summary: None,
+                    content: Some(Content::Html(
+                        "<p>json_feed_1.1.items[0].content_html</p>".into(),
+                    )),
+                    media: vec![],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-01-01T01:00:00Z").ok(),
+                    updated: None,
+                },
+                Item {
+                    title: None,
+                    link: Some("http://example.com/blog/entry2".into()),
+                    id: Some("json_feed_1.1.items[1].id".into()),
+                    summary: None,
+                    content: Some(Content::Html(
+                        "<p>json_feed_1.1.items[1].content_html</p>".into(),
+                    )),
+                    media: vec![],
+                    thumbnail: None,
+                    published: DateTime::parse_from_rfc3339("2000-02-01T01:00:00Z").ok(),
+                    updated: None,
                 },
             ],
         }
     );
 }
 
+#[test]
+fn test_json_feed_unknown_version() {
+    let s = r#"{"version": "https://example.com/version/1", "title": "title", "items": []}"#;
+    assert!(parse_json(s.as_bytes()).is_err());
+}
+
+#[test]
+fn test_is_json_feed() {
+    assert!(is_json_feed(Some("application/feed+json"), b"<"));
+    assert!(is_json_feed(Some("application/json; charset=utf-8"), b""));
+    assert!(is_json_feed(None, b"\xEF\xBB\xBF \n{\"version\": \"\"}"));
+    assert!(is_json_feed(Some("text/plain"), b"{}"));
+    assert!(!is_json_feed(Some("application/rss+xml"), b"<?xml"));
+    assert!(!is_json_feed(None, b""));
+}
+
 #[test]
 fn test_rss_with_atom_ns() {
     use std::io::Cursor;
@@ -615,6 +1991,106 @@ fn test_rss_with_atom_ns() {
     assert_eq!(r.source, Some("self link".into()));
 }
 
+#[test]
+fn test_decode_body() {
+    let data: Vec<(&[u8], &str, &str)> = vec![
+        (
+            include_bytes!("../tests/data/encoding_gbk.xml"),
+            "中文博客",
+            "第一篇文章：你好，世界",
+        ),
+        (include_bytes!("../tests/data/encoding_gb2312.xml"), "简体中文", "欢迎订阅"),
+        (
+            include_bytes!("../tests/data/encoding_big5.xml"),
+            "繁體中文部落格",
+            "第一篇文章：你好，世界",
+        ),
+        (
+            include_bytes!("../tests/data/encoding_shift_jis.xml"),
+            "日本語のブログ",
+            "最初の記事：こんにちは",
+        ),
+        (
+            include_bytes!("../tests/data/encoding_iso-8859-1.xml"),
+            "Café français",
+            "Crème brûlée à la française",
+        ),
+    ];
+    for (body, title, item_title) in data {
+        // a wrong header is ignored if the body is not valid in it
+        for content_type in &[None, Some("text/xml; charset=UTF-8")] {
+            let rss = parse(decode_body(*content_type, body).as_bytes()).unwrap();
+            assert_eq!(rss.title, title);
+            assert_eq!(rss.items[0].title, Some(item_title.into()));
+        }
+    }
+
+    // UTF-8 behind a Latin-1 header, and real Latin-1
+    let latin1 = Some("text/xml; charset=ISO-8859-1");
+    for body in &[
+        &include_bytes!("../tests/data/encoding_utf-8.xml")[..],
+        &include_bytes!("../tests/data/encoding_iso-8859-1.xml")[..],
+    ] {
+        let rss = parse(decode_body(latin1, body).as_bytes()).unwrap();
+        assert_eq!(rss.title, "Café français");
+        assert_eq!(rss.items[0].title, Some("Crème brûlée à la française".into()));
+    }
+    assert!(is_single_byte(::encoding_rs::WINDOWS_1252));
+    assert!(!is_single_byte(GBK));
+    assert!(!is_single_byte(::encoding_rs::UTF_16LE));
+
+    let text = decode_body(None, include_bytes!("../tests/data/encoding_gbk.xml"));
+    assert!(text.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+
+    let (body, _, _) = ::encoding_rs::SHIFT_JIS.encode("<rss>日本語</rss>");
+    assert_eq!(decode_body(Some("text/xml; charset=\"Shift_JIS\""), &body), "<rss>日本語</rss>");
+    assert_eq!(decode_body(None, b"\xFF\xFEa\x00b\x00"), "ab");
+    assert_eq!(decode_body(None, b"\xEF\xBB\xBFab"), "ab");
+    assert_eq!(decode_body(None, b"a\xFFb"), "a\u{FFFD}b");
+}
+
+#[test]
+fn test_is_html() {
+    assert!(is_html(Some("text/html; charset=utf-8"), b""));
+    assert!(is_html(None, b"\n<!DOCTYPE html>"));
+    assert!(is_html(None, b"<html lang=\"en\">"));
+    assert!(!is_html(Some("application/xml"), b"<?xml version=\"1.0\"?>"));
+}
+
+#[test]
+fn test_resolve_url() {
+    let base = "https://example.com/blog/index.html?a=/b/";
+    assert_eq!(resolve_url("http://a.com/feed", base), "http://a.com/feed");
+    assert_eq!(resolve_url("/feed.xml", base), "https://example.com/feed.xml");
+    assert_eq!(resolve_url("feed.xml", base), "https://example.com/blog/feed.xml");
+    assert_eq!(resolve_url("feed.xml", "https://example.com"), "https://example.com/feed.xml");
+    assert_eq!(resolve_url("//a.com/feed", base), "https://a.com/feed");
+    assert_eq!(resolve_url("../feed.xml", base), "https://example.com/feed.xml");
+}
+
+#[test]
+fn test_resolve_location() {
+    let url = "https://example.com/blog/feed?a=1";
+    let data = vec![
+        ("http://a.com/feed", "http://a.com/feed"),
+        ("/feed.xml", "https://example.com/feed.xml"),
+        ("atom.xml", "https://example.com/blog/atom.xml"),
+        ("//a.com/feed", "https://a.com/feed"),
+        ("?a=2", "https://example.com/blog/feed?a=2"),
+    ];
+    for (location, result) in data {
+        assert_eq!(resolve_location(url, location), Some(result.into()), "{}", location);
+    }
+    assert_eq!(
+        resolve_location("example.com/feed", "/atom.xml"),
+        Some("http://example.com/atom.xml".into())
+    );
+    assert_eq!(resolve_location(url, "http://[::1"), None);
+    assert_eq!(is_permanent_redirect(308), Some(true));
+    assert_eq!(is_permanent_redirect(303), Some(false));
+    assert_eq!(is_permanent_redirect(304), None);
+}
+
 #[test]
 fn test_parse_atom_link() {
     use std::io::Cursor;
@@ -624,6 +2100,7 @@ fn test_parse_atom_link() {
         r#"<link href="self href" rel="self" />"#,
         r#"<link href="hub href" rel="hub" />"#,
         r#"<link href="other href" rel="other" />"#,
+        r#"<link href="a.mp3" rel="enclosure" type="audio/mpeg" length="100" />"#,
         r#"<link />"#,
     ];
     let results = vec![
@@ -635,14 +2112,457 @@ fn test_parse_atom_link() {
             "other href".into(),
             Cow::Owned("other".into()),
         )),
+        Some(AtomLink::Enclosure(Media {
+            url: "a.mp3".into(),
+            mime_type: Some("audio/mpeg".into()),
+            length: Some(100),
+            kind: MediaKind::Audio,
+        })),
         None,
     ];
     for (data, result) in data.iter().zip(results) {
         let mut reader = XmlReader::from_reader(Cursor::new(data));
         let mut buf = Vec::new();
         if let XmlEvent::Empty(e) = reader.read_event(&mut buf).unwrap() {
-            let r = parse_atom_link(&mut reader, e.attributes()).unwrap();
+            let r = parse_atom_link(&mut reader, e.attributes(), &Base::default()).unwrap();
             assert_eq!(r, result);
         }
     }
 }
+
+#[test]
+fn test_xml_base() {
+    let s = r##"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xml:base="/blog/">
+  <link href="./"/>
+  <link rel="self" href="atom.xml"/>
+  <entry xml:base="2006/">
+    <link href="post.html"/>
+    <link rel="enclosure" href="//cdn.example.org/a.mp3"/>
+  </entry>
+  <entry><link xml:base="/other/" href="x"/></entry>
+  <entry><link href="../x.html"/></entry>
+  <entry xml:base="2006/"><link href="?page=2"/></entry>
+  <entry xml:base="2006/"><link href="#c"/></entry>
+</feed>"##;
+    let r = parse_body(None, s.as_bytes(), "https://example.com/feeds/atom.xml").unwrap();
+    assert_eq!(r.link, "https://example.com/blog/");
+    assert_eq!(r.source, Some("https://example.com/blog/atom.xml".into()));
+    let links: Vec<&str> = r.items
+        .iter()
+        .map(|item| item.link.as_ref().unwrap().as_str())
+        .collect();
+    assert_eq!(
+        links,
+        vec![
+            "https://example.com/blog/2006/post.html",
+            "https://example.com/other/x",
+            "https://example.com/x.html",
+            "https://example.com/blog/2006/?page=2",
+            "https://example.com/blog/2006/#c",
+        ]
+    );
+    assert_eq!(r.items[0].media[0].url, "https://cdn.example.org/a.mp3");
+}
+
+#[test]
+fn test_fix_relative_url() {
+    let s = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+  <channel>
+    <title>title</title>
+    <link>site/</link>
+    <item><link>article/1.html</link></item>
+    <item><link>../../x</link></item>
+    <item><link>./a/./b/../c</link></item>
+    <item>
+      <link>http://example.org/y</link>
+      <enclosure url="/a.mp3" type="audio/mpeg"/>
+    </item>
+  </channel>
+</rss>"#;
+    let r = parse_body(None, s.as_bytes(), "http://example.com/rss").unwrap();
+    assert_eq!(r.link, "http://example.com/site/");
+    let links: Vec<&str> = r.items
+        .iter()
+        .map(|item| item.link.as_ref().unwrap().as_str())
+        .collect();
+    assert_eq!(
+        links,
+        vec![
+            "http://example.com/site/article/1.html",
+            "http://example.com/x",
+            "http://example.com/site/a/c",
+            "http://example.org/y",
+        ]
+    );
+    assert_eq!(r.items[3].media[0].url, "http://example.com/a.mp3");
+
+    // the home page
+    let s = r#"<rss><channel><title>title</title><link></link></channel></rss>"#;
+    let r = parse_body(None, s.as_bytes(), "http://example.com/rss").unwrap();
+    assert_eq!(r.link, "http://example.com/");
+}
+
+#[test]
+fn test_item_content() {
+    use std::io::Cursor;
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<entry>
+<summary>a &lt; b</summary>
+<content type="xhtml"><div xmlns="http://www.w3.org/1999/xhtml"><p>a &amp; b<br/></p></div></content>
+</entry>
+<entry>
+<summary type="html">&lt;p&gt;escaped&lt;/p&gt;</summary>
+<content src="http://example.com/content" />
+<content type="text/html" src="http://example.com/content"></content>
+</entry>
+</feed>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].summary, Some(Content::Text("a < b".into())));
+    assert_eq!(
+        r.items[0].content,
+        Some(Content::Html(
+            r#"<div xmlns="http://www.w3.org/1999/xhtml"><p>a &amp; b<br/></p></div>"#.into(),
+        ))
+    );
+    assert_eq!(r.items[1].summary, Some(Content::Html("<p>escaped</p>".into())));
+    assert_eq!(r.items[1].content, None);
+
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+<item>
+<description><![CDATA[<p>cdata</p>]]></description>
+<content:encoded><p>not escaped</p></content:encoded>
+</item>
+</channel>
+</rss>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].summary, Some(Content::Html("<p>cdata</p>".into())));
+    assert_eq!(r.items[0].content, Some(Content::Html("<p>not escaped</p>".into())));
+}
+
+#[test]
+fn test_item_namespace_prefix() {
+    use std::io::Cursor;
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:c="http://purl.org/rss/1.0/modules/content/"
+    xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+<channel>
+<item>
+<title>title</title>
+<itunes:title>itunes title</itunes:title>
+<c:encoded><p>content</p></c:encoded>
+</item>
+</channel>
+</rss>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].title, Some("title".into()));
+    assert_eq!(r.items[0].content, Some(Content::Html("<p>content</p>".into())));
+
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<a:feed xmlns:a="http://www.w3.org/2005/Atom">
+<a:entry>
+<a:title>title</a:title>
+<a:link href="http://example.com/"/>
+<a:content type="html">&lt;p&gt;content&lt;/p&gt;</a:content>
+</a:entry>
+</a:feed>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].title, Some("title".into()));
+    assert_eq!(r.items[0].link, Some("http://example.com/".into()));
+    assert_eq!(r.items[0].content, Some(Content::Html("<p>content</p>".into())));
+}
+
+#[test]
+fn test_item_media() {
+    use std::io::Cursor;
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">
+<channel>
+<item>
+<enclosure url="http://example.com/a.mp3" length="1024" type="audio/mpeg" />
+<media:content url="http://example.com/a.mp3" fileSize="1024" type="audio/mpeg" />
+<media:group>
+<media:content url="http://example.com/b.jpg?size=large" medium="image">
+<media:title>b</media:title>
+</media:content>
+<media:thumbnail url="http://example.com/b_thumb.jpg" />
+</media:group>
+<media:thumbnail url="http://example.com/thumb.jpg" />
+</item>
+</channel>
+</rss>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(
+        r.items[0].media,
+        vec![
+            Media {
+                url: "http://example.com/a.mp3".into(),
+                mime_type: Some("audio/mpeg".into()),
+                length: Some(1024),
+                kind: MediaKind::Audio,
+            },
+            Media {
+                url: "http://example.com/b.jpg?size=large".into(),
+                mime_type: None,
+                length: None,
+                kind: MediaKind::Image,
+            },
+        ]
+    );
+    assert_eq!(r.items[0].thumbnail, Some("http://example.com/b_thumb.jpg".into()));
+}
+
+#[test]
+fn test_item_media_namespace_prefix() {
+    use std::io::Cursor;
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:m="http://search.yahoo.com/mrss/">
+<entry>
+<m:group>
+<m:content url="http://example.com/a.jpg" medium="image"></m:content>
+<m:thumbnail url="http://example.com/thumb.jpg"/>
+</m:group>
+<content type="html">&lt;p&gt;content&lt;/p&gt;</content>
+</entry>
+</feed>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].content, Some(Content::Html("<p>content</p>".into())));
+    assert_eq!(r.items[0].media.len(), 1);
+    assert_eq!(r.items[0].media[0].url, "http://example.com/a.jpg");
+    assert_eq!(r.items[0].thumbnail, Some("http://example.com/thumb.jpg".into()));
+}
+
+#[test]
+fn test_item_date() {
+    use std::io::Cursor;
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:d="http://purl.org/dc/elements/1.1/">
+<channel>
+<item><pubDate>Sat, 01 Jan 2000 00:00:00 GMT</pubDate></item>
+<item><d:date>2000-01-01T00:00:00Z</d:date></item>
+</channel>
+</rss>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    for item in &r.items {
+        assert_eq!(item.date().map(|d| d.timestamp()), Some(946684800));
+    }
+
+    let s = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<entry><updated>2000-01-01T00:00:00Z</updated></entry>
+</feed>"#;
+    let r = parse(Cursor::new(s)).unwrap();
+    assert_eq!(r.items[0].published, None);
+    assert_eq!(r.items[0].date().map(|d| d.timestamp()), Some(946684800));
+}
+
+#[test]
+fn test_media_kind() {
+    let data = vec![
+        (Some("video"), Some("image/png"), "a.png", MediaKind::Video),
+        (None, Some("Image/PNG"), "a", MediaKind::Image),
+        (None, Some("application/octet-stream"), "a.MP3", MediaKind::Audio),
+        (None, None, "http://example.com/a.mp4?a.jpg", MediaKind::Video),
+        (None, None, "http://example.com/a.b/c", MediaKind::Other),
+    ];
+    for (medium, mime_type, url, result) in data {
+        assert_eq!(MediaKind::guess(medium, mime_type, url), result);
+    }
+}
+
+#[test]
+fn test_excerpt() {
+    let mut item = Item::default();
+    assert_eq!(item.excerpt(100), None);
+
+    item.content = Some(Content::Html(
+        "<style>p { color: red; }</style><p>Hello,&nbsp;<b>world</b>&#33;</p>\n\
+         <!-- comment --><p>&lt;tag&gt; &#x4f60;&#x597d; &unknown;</p>"
+            .into(),
+    ));
+    assert_eq!(
+        item.excerpt(100),
+        Some("Hello,\u{a0}<b>world</b>!\n\n&lt;tag&gt; 你好 &amp;unknown;".into())
+    );
+    assert_eq!(item.excerpt(10), Some("Hello,\u{a0}...".into()));
+
+    item.title = Some("title".into());
+    item.summary = Some(Content::Text(" title\n".into()));
+    assert_eq!(item.excerpt(100), None);
+    item.summary = Some(Content::Text(" a  <b>\n c".into()));
+    assert_eq!(item.excerpt(100), Some("a  &lt;b&gt;\n c".into()));
+}
+
+#[test]
+fn test_update_hints() {
+    let s = r#"<rdf:RDF xmlns:sy="http://purl.org/rss/1.0/modules/syndication/">
+        <channel>
+            <title>title</title>
+            <sy:updatePeriod>hourly</sy:updatePeriod>
+            <sy:updateFrequency>2</sy:updateFrequency>
+        </channel>
+    </rdf:RDF>"#;
+    assert_eq!(parse(s.as_bytes()).unwrap().hints.update_period, Some(30 * 60));
+
+    let s = r#"<rss><channel>
+        <title>title</title>
+        <ttl> 60 </ttl>
+        <sy:updateFrequency>4</sy:updateFrequency>
+        <skipHours><hour>0</hour><hour>24</hour><hour>x</hour></skipHours>
+        <skipDays><day>Saturday</day><day>sun</day><day>x</day></skipDays>
+    </channel></rss>"#;
+    assert_eq!(
+        parse(s.as_bytes()).unwrap().hints,
+        UpdateHints {
+            ttl: Some(60),
+            update_period: Some(6 * 60 * 60),
+            skip_hours: vec![0],
+            skip_days: vec![Weekday::Sat, Weekday::Sun],
+        }
+    );
+}
+
+#[test]
+fn test_max_age() {
+    fn headers(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+    let date = ("Date", "Mon, 02 Jan 2006 15:04:05 GMT");
+    let data = vec![
+        (vec![("Cache-Control", "public, max-age=600")], Some(600)),
+        (vec![("cache-control", "Max-Age=\"60\"")], Some(60)),
+        (vec![("Cache-Control", "no-cache")], None),
+        (vec![date, ("Expires", "Mon, 02 Jan 2006 16:04:05 GMT")], Some(3600)),
+        (vec![date, ("Expires", "Mon, 02 Jan 2006 14:04:05 GMT")], Some(0)),
+        (vec![date, ("Expires", "0")], None),
+        (vec![("Expires", "Mon, 02 Jan 2006 16:04:05 GMT")], None),
+        (
+            vec![("Cache-Control", "max-age=60"), date, ("Expires", "Mon, 02 Jan 2006 16:04:05 GMT")],
+            Some(60),
+        ),
+        (vec![], None),
+    ];
+    for (h, result) in data {
+        assert_eq!(max_age(&headers(&h)), result, "{:?}", h);
+    }
+}
+
+/// Follows the redirects from `http://example.com/feed` through canned responses,
+/// each a code and a `Location`, returns the result and the url and validators of each request
+#[cfg(test)]
+fn follow_canned(
+    responses: Vec<(u32, Option<&'static str>)>,
+    validators: Validators,
+    recur_limit: usize,
+) -> (Result<Response>, Vec<(String, Validators)>) {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let requests = Rc::new(RefCell::new(Vec::new()));
+    let send = {
+        let requests = Rc::clone(&requests);
+        let mut responses = responses.into_iter();
+        move |url: &str, validators: &Validators| {
+            requests
+                .borrow_mut()
+                .push((url.to_owned(), validators.clone()));
+            let (code, location) = responses.next().expect("more requests than responses");
+            let mut headers = vec![("ETag".to_owned(), format!("\"{}\"", code))];
+            if let Some(location) = location {
+                headers.push(("Location".to_owned(), location.to_owned()));
+            }
+            future::ok(RawResponse {
+                code: code,
+                headers: headers,
+                body: Vec::new(),
+            })
+        }
+    };
+    let source = "http://example.com/feed".to_owned();
+    let result = follow_redirects(send, source, validators, recur_limit).wait();
+    let requests = requests.borrow().clone();
+    (result, requests)
+}
+
+#[test]
+fn test_follow_redirects() {
+    let validators = Validators {
+        etag: Some("\"old\"".into()),
+        last_modified: None,
+    };
+    let urls = |requests: &[(String, Validators)]| -> Vec<String> {
+        requests.iter().map(|&(ref url, _)| url.clone()).collect()
+    };
+
+    // a permanent redirect moves the feed, and the old validators are dropped
+    let responses = vec![(308, Some("/new")), (200, None)];
+    let (resp, requests) = follow_canned(responses, validators.clone(), 10);
+    let resp = resp.unwrap();
+    assert_eq!(resp.code, 200);
+    assert_eq!(resp.source, "http://example.com/new");
+    assert_eq!(resp.validators.etag, Some("\"200\"".into()));
+    assert_eq!(
+        urls(&requests),
+        vec!["http://example.com/feed", "http://example.com/new"]
+    );
+    assert_eq!(requests[0].1, validators);
+    assert_eq!(requests[1].1, Validators::default());
+
+    // a temporary one doesn't, and the validators are kept
+    let responses = vec![(307, Some("http://example.org/feed")), (304, None)];
+    let (resp, requests) = follow_canned(responses, validators.clone(), 10);
+    let resp = resp.unwrap();
+    assert_eq!(resp.code, 304);
+    assert_eq!(resp.source, "http://example.com/feed");
+    assert_eq!(
+        urls(&requests),
+        vec!["http://example.com/feed", "http://example.org/feed"]
+    );
+    assert_eq!(requests[1].1, validators);
+
+    // only the permanent redirects before any temporary one move the feed
+    let responses = vec![
+        (301, Some("/a")),
+        (302, Some("/b")),
+        (308, Some("/c")),
+        (200, None),
+    ];
+    let (resp, requests) = follow_canned(responses, validators.clone(), 10);
+    assert_eq!(resp.unwrap().source, "http://example.com/a");
+    assert_eq!(
+        urls(&requests),
+        vec![
+            "http://example.com/feed",
+            "http://example.com/a",
+            "http://example.com/b",
+            "http://example.com/c",
+        ]
+    );
+    assert!(requests[1..].iter().all(|&(_, ref v)| *v == Validators::default()));
+
+    // a redirect without a `Location` is the response itself
+    let (resp, _) = follow_canned(vec![(301, None)], validators.clone(), 10);
+    assert_eq!(resp.unwrap().code, 301);
+}
+
+#[test]
+fn test_too_many_redirects() {
+    let responses = vec![(301, Some("/a")), (301, Some("/b")), (200, None)];
+    let (resp, requests) = follow_canned(responses.clone(), Validators::default(), 3);
+    assert_eq!(resp.unwrap().source, "http://example.com/b");
+    assert_eq!(requests.len(), 3);
+
+    let (resp, requests) = follow_canned(responses, Validators::default(), 2);
+    match resp {
+        Err(Error(ErrorKind::TooManyRedirects, _)) => (),
+        r => panic!("{:?}", r),
+    }
+    assert_eq!(requests.len(), 2);
+}
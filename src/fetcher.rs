@@ -1,47 +1,110 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
 use std::time::Duration;
 
+use chrono::Utc;
 use futures::prelude::*;
 use regex::Regex;
 use telebot;
-use telebot::functions::*;
-use telebot::objects::ResponseParameters;
 use tokio_core::reactor::{Interval, Timeout};
 use tokio_curl::Session;
 
+use canonical::canonicalize;
 use data;
 use feed;
-use utlis::{chat_is_unavailable, format_and_split_msgs, gen_ua, send_multiple_messages,
-            to_chinese_error_msg, truncate_message, Escape, EscapeUrl, TELEGRAM_MAX_MSG_LEN};
+use html;
+use i18n::{fill, Lang};
+use scheduler::{backoff, next_interval, Intervals, Observation, Scheduler};
+use utlis::{format_and_split_msgs, gen_ua, log_error, to_error_msg, truncate_message, Escape,
+            EscapeUrl, TELEGRAM_MAX_MSG_LEN};
+use websub;
 
 lazy_static!{
     // it's different from `feed::HOST`, so maybe need a better name?
     static ref HOST: Regex = Regex::new(r"^(?:https?://)?([^/]+)").unwrap();
 }
 
-pub fn spawn_fetcher(bot: telebot::RcBot, db: data::Database, period: u64) {
+/// Seconds between the checks for feeds due
+const TICK: u64 = 10;
+/// Max chars of the excerpt under each title
+const EXCERPT_LEN: usize = 200;
+/// Max chars of the caption of a photo or audio
+const CAPTION_MAX_LEN: usize = 1024;
+/// Max photos in an album
+const MEDIA_GROUP_MAX: usize = 10;
+/// Seconds of failing in a row after which the subscribers are told the feed may be gone,
+/// and again after each as many more
+const DEAD_AFTER: i64 = 5 * 24 * 60 * 60;
+
+pub fn spawn_fetcher(
+    bot: telebot::RcBot,
+    db: data::Database,
+    intervals: Intervals,
+    websub: Option<websub::WebSub>,
+) {
     let handle = bot.inner.handle.clone();
     let handle2 = handle.clone();
+    let scheduler = Rc::new(RefCell::new(Scheduler::new()));
     let lop = async_block! {
         #[async]
-        for _ in Interval::new(Duration::from_secs(period), &handle)
+        for _ in Interval::new(Duration::from_secs(TICK), &handle)
             .expect("failed to start feed loop")
             .map_err(|e| error!("feed loop error: {}", e))
         {
-            let feeds = db.get_all_feeds();
+            let now = Utc::now().timestamp();
+            let due = {
+                let mut scheduler = scheduler.borrow_mut();
+                scheduler.sync(&db.get_fetch_times(), now);
+                scheduler.pop_due(now)
+            };
+            let feeds = due.into_iter()
+                .filter_map(|feed_id| db.get_feed(feed_id))
+                .collect();
             let grouped_feeds = grouping_by_host(feeds);
             let handle2 = handle.clone();
             let bot = bot.clone();
             let db = db.clone();
+            let websub = websub.clone();
+            let scheduler = scheduler.clone();
             let fetcher = async_block! {
                 for group in grouped_feeds {
                     let session = Session::new(handle2.clone());
                     let bot = bot.clone();
                     let db = db.clone();
+                    let websub = websub.clone();
+                    let scheduler = scheduler.clone();
                     let group_fetcher = async_block! {
                         for feed in group {
-                            await!(fetch_feed_updates(bot.clone(), db.clone(),
-                                                      session.clone(), feed))?;
+                            let link = feed.link.clone();
+                            let feed_id = data::get_hash(&link);
+                            let previous = scheduler.borrow().interval(feed_id);
+                            let observation = await!(fetch_feed_updates(
+                                bot.clone(), db.clone(), session.clone(), websub.clone(), feed
+                            ))?;
+                            let now = Utc::now();
+                            match observation {
+                                Some(observation) => {
+                                    let interval =
+                                        next_interval(&observation, previous, &intervals, now);
+                                    db.record_fetch(&link, now.timestamp(), interval);
+                                    scheduler.borrow_mut()
+                                        .schedule(feed_id, now.timestamp(), interval);
+                                }
+                                None => {
+                                    let failures = db.get_feed(feed_id)
+                                        .map_or(1, |feed| feed.error_count);
+                                    let delay = backoff(
+                                        previous.unwrap_or(intervals.default),
+                                        failures,
+                                        &intervals,
+                                        random(),
+                                    );
+                                    scheduler.borrow_mut().retry(feed_id, now.timestamp(), delay);
+                                }
+                            }
                         }
                         Ok(())
                     };
@@ -59,6 +122,17 @@ pub fn spawn_fetcher(bot: telebot::RcBot, db: data::Database, period: u64) {
     handle2.spawn(lop)
 }
 
+/// Whether a feed failing in a row `since` has been failing for another `DEAD_AFTER`
+/// from its `previous` failure to `now`
+fn looks_dead(since: i64, previous: i64, now: i64) -> bool {
+    (now - since) / DEAD_AFTER > (previous - since) / DEAD_AFTER
+}
+
+/// Good enough for jitter, without a dependency on `rand`
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
 fn grouping_by_host(feeds: Vec<data::Feed>) -> Vec<Vec<data::Feed>> {
     let mut result = HashMap::new();
     for feed in feeds {
@@ -74,72 +148,117 @@ fn get_host(url: &str) -> &str {
         .map_or(url, |r| r.get(0).unwrap().as_str())
 }
 
+/// Returns what the fetch tells about the next update of the feed, `None` if it failed
 #[async]
 fn fetch_feed_updates(
     bot: telebot::RcBot,
     db: data::Database,
     session: Session,
+    websub: Option<websub::WebSub>,
     feed: data::Feed,
-) -> Result<(), ()> {
-    let handle = bot.inner.handle.clone();
-    let rss = match await!(feed::fetch_feed(
+) -> Result<Option<Observation>, ()> {
+    let validators = feed::Validators {
+        etag: feed.etag.clone(),
+        last_modified: feed.last_modified.clone(),
+    };
+    let fetched = match await!(feed::fetch_feed_if_modified(
         session,
         gen_ua(&bot),
         feed.link.to_owned(),
+        validators,
     )) {
-        Ok(rss) => rss,
+        Ok(fetched) => fetched,
         Err(e) => {
-            // 1440 * 5 minute = 5 days
-            if db.inc_error_count(&feed.link) > 1440 {
-                db.reset_error_count(&feed.link);
-                let err_msg = to_chinese_error_msg(e);
-                let msg = format!(
-                    "《<a href=\"{}\">{}</a>》\
-                     已经连续 5 天拉取出错 ({}),\
-                     可能已经关闭, 请取消订阅",
-                    EscapeUrl(&feed.link),
-                    Escape(&feed.title),
-                    Escape(&err_msg)
-                );
-                for subscriber in feed.subscribers {
-                    let m = bot.message(subscriber, msg.clone())
-                        .parse_mode("HTML")
-                        .disable_web_page_preview(true)
-                        .send();
-                    match await!(m) {
-                        Err(telebot::Error::Telegram(_, ref s, None)) if chat_is_unavailable(s) => {
-                            db.delete_subscriber(subscriber);
-                        }
-                        Err(telebot::Error::Telegram(
-                            _,
-                            _,
-                            Some(ResponseParameters {
-                                migrate_to_chat_id: Some(new_id),
-                                ..
-                            }),
-                        )) => {
-                            db.update_subscriber(subscriber, new_id);
-                            handle.spawn(
-                                bot.message(new_id, msg.clone())
-                                    .parse_mode("HTML")
-                                    .disable_web_page_preview(true)
-                                    .send()
-                                    .then(|_| Ok(())),
-                            );
-                        }
-                        Err(e) => warn!("failed to send error to {}, {:?}", subscriber, e),
-                        _ => (),
-                    }
+            let now = Utc::now().timestamp();
+            let times = db.record_error(&feed.link, data::FeedError::new(&e, now));
+            if times.map_or(false, |(since, previous)| looks_dead(since, previous, now)) {
+                let messages = feed.subscribers
+                    .iter()
+                    .map(|&subscriber| {
+                        let lang = db.get_lang(subscriber, Lang::default());
+                        let msg = fill(
+                            lang.msgs().feed_failing,
+                            &[
+                                &EscapeUrl(&feed.link).to_string(),
+                                &Escape(&feed.title).to_string(),
+                                &Escape(&to_error_msg(&e, lang)).to_string(),
+                            ],
+                        );
+                        (subscriber, msg)
+                    })
+                    .collect();
+                if let Err(e) = db.enqueue(messages) {
+                    log_error(&e);
                 }
             }
-            return Ok(());
+            return Ok(None);
         }
     };
-    let moved = if rss.source.as_ref().unwrap() != &feed.link {
-        Some(rss.clone())
-    } else {
-        None
+    let feed::Fetched {
+        rss,
+        validators,
+        max_age,
+        moved_to,
+    } = fetched;
+    let rss = match rss {
+        Some(rss) => {
+            db.update_validators(&feed.link, validators);
+            rss
+        }
+        None => {
+            // 304 Not Modified
+            db.reset_error_count(&feed.link);
+            return Ok(Some(Observation {
+                max_age: max_age,
+                ..Default::default()
+            }));
+        }
+    };
+    let mut observation = Observation {
+        dates: rss.items.iter().filter_map(|item| item.date()).collect(),
+        hints: rss.hints.clone(),
+        max_age: max_age,
+        changed: false,
     };
+    if let Some(ref websub) = websub {
+        websub.maintain(&db, &feed, rss.hub.as_ref().map(|s| s.as_str()));
+    }
+    // only a permanent redirect moves the feed, the link in it may point back to the old one
+    let moved_to = moved_to.filter(|link| canonicalize(link) != feed.link);
+    observation.changed = push_updates(&db, &feed, rss);
+    if let Some(new_link) = moved_to {
+        move_feed(&db, &feed, &new_link);
+    }
+    Ok(Some(observation))
+}
+
+/// Moves `feed` to `new_link` and lets the subscribers know
+fn move_feed(db: &data::Database, feed: &data::Feed, new_link: &str) {
+    if let Err(e) = db.move_feed(&feed.link, new_link) {
+        log_error(&e);
+        return;
+    }
+    let mut messages = Vec::new();
+    for &subscriber in &feed.subscribers {
+        let lang = db.get_lang(subscriber, Lang::default());
+        let msg = fill(
+            lang.msgs().feed_moved,
+            &[
+                &EscapeUrl(&feed.link).to_string(),
+                &Escape(&feed.title).to_string(),
+                &Escape(new_link).to_string(),
+            ],
+        );
+        messages.push((subscriber, msg));
+    }
+    if let Err(e) = db.enqueue(messages) {
+        log_error(&e);
+    }
+}
+
+/// Queues the new items of `rss` for the subscribers of `feed`,
+/// whether it was fetched or pushed by a hub. Returns `false` if nothing is new
+pub fn push_updates(db: &data::Database, feed: &data::Feed, rss: feed::RSS) -> bool {
     if rss.title != feed.title {
         db.update_title(&feed.link, &rss.title);
     }
@@ -149,56 +268,150 @@ fn fetch_feed_updates(
         items: rss_items,
         ..
     } = rss;
-    let updates = db.update(&feed.link, rss_items);
+    let mut updates = db.update(&feed.link, rss_items);
     if updates.is_empty() {
-        return Ok(());
+        return false;
+    }
+    // oldest first, so the latest one ends up at the bottom of the chat,
+    // the order of the feed is kept if some items have no date
+    if updates.iter().all(|item| item.date().is_some()) {
+        updates.sort_by_key(|item| item.date());
     }
 
-    let msgs = format_and_split_msgs(
-        format!("<b>{}</b>", Escape(&rss_title)),
-        &updates,
-        move |item| {
-            let title = item.title
-                .as_ref()
-                .map(|s| s.as_str())
-                .unwrap_or_else(|| &rss_title);
-            let link = item.link
-                .as_ref()
-                .map(|s| s.as_str())
-                .unwrap_or_else(|| &rss_link);
-            format!(
-                "<a href=\"{}\">{}</a>",
-                EscapeUrl(link),
-                Escape(&truncate_message(title, TELEGRAM_MAX_MSG_LEN - 500))
-            )
-        },
-    );
+    let msgs = format_updates(&rss_title, &rss_link, &updates, false);
 
-    for subscriber in feed.subscribers {
-        let r = send_multiple_messages(&bot, subscriber, msgs.clone());
-        match await!(r) {
-            Err(telebot::Error::Telegram(_, ref s, None)) if chat_is_unavailable(s) => {
-                db.delete_subscriber(subscriber);
+    let mut messages = Vec::new();
+    let mut digests = Vec::new();
+    for &subscriber in &feed.subscribers {
+        if db.get_chat_settings(subscriber).digest.is_some() {
+            // as text, a digest has no room for media
+            let options = feed.options.get(&subscriber).cloned().unwrap_or_default();
+            for item in options.filter.apply(&updates) {
+                let item = data::DigestItem {
+                    feed: feed.link.clone(),
+                    title: rss_title.clone(),
+                    line: format_item(&rss_title, &rss_link, &item, options.excerpt),
+                };
+                digests.push((subscriber, item));
+            }
+            continue;
+        }
+        let options = match feed.options.get(&subscriber) {
+            Some(options) if !options.filter.is_empty() || options.excerpt || options.media => {
+                options
+            }
+            _ => {
+                messages.extend(msgs.iter().map(|msg| (subscriber, msg.clone().into())));
+                continue;
             }
-            Err(telebot::Error::Telegram(
-                _,
-                _,
-                Some(ResponseParameters {
-                    migrate_to_chat_id: Some(new_id),
-                    ..
-                }),
-            )) => {
-                db.update_subscriber(subscriber, new_id);
-                handle.spawn(send_multiple_messages(&bot, new_id, msgs.clone()).then(|_| Ok(())));
+        };
+        let mut texts = Vec::new();
+        let mut media = Vec::new();
+        for item in options.filter.apply(&updates) {
+            let message = if options.media {
+                to_media_message(&rss_title, &rss_link, &item, options.excerpt)
+            } else {
+                None
+            };
+            match message {
+                Some(message) => media.push((subscriber, message)),
+                None => texts.push(item),
             }
-            Err(e) => warn!("failed to send updates to {}, {:?}", subscriber, e),
-            _ => (),
         }
-        if let Some(ref rss) = moved {
-            // ignore error
-            let _ = db.unsubscribe(subscriber, &feed.link);
-            let _ = db.subscribe(subscriber, rss.source.as_ref().unwrap(), rss);
+        if !texts.is_empty() {
+            let msgs = format_updates(&rss_title, &rss_link, &texts, options.excerpt);
+            messages.extend(msgs.into_iter().map(|msg| (subscriber, msg.into())));
         }
+        messages.extend(media);
+    }
+    // delivered by `sender`, digests by `digest`
+    if let Err(e) = db.commit_updates(&feed.link, messages, digests) {
+        log_error(&e);
     }
-    Ok(())
+    true
 }
+
+/// The title linked to the item, and an excerpt under it if `excerpt` is set
+fn format_item(rss_title: &str, rss_link: &str, item: &feed::Item, excerpt: bool) -> String {
+    let title = item.title
+        .as_ref()
+        .map(|s| s.as_str())
+        .unwrap_or_else(|| rss_title);
+    let link = item.link
+        .as_ref()
+        .map(|s| s.as_str())
+        .unwrap_or_else(|| rss_link);
+    let title = format!(
+        "<a href=\"{}\">{}</a>",
+        EscapeUrl(link),
+        Escape(&truncate_message(title, TELEGRAM_MAX_MSG_LEN - 500))
+    );
+    if excerpt {
+        if let Some(text) = item.excerpt(EXCERPT_LEN) {
+            // a blank line between the items, or they run together
+            return format!("\n{}\n{}", title, text);
+        }
+    }
+    title
+}
+
+fn format_updates(
+    rss_title: &str,
+    rss_link: &str,
+    updates: &[feed::Item],
+    excerpt: bool,
+) -> Vec<String> {
+    format_and_split_msgs(
+        format!("<b>{}</b>", Escape(rss_title)),
+        updates,
+        |item| format_item(rss_title, rss_link, item, excerpt),
+    )
+}
+
+/// An item with photos or audio as Telegram media, the formatted item is the caption.
+/// `None` if it has neither, or the caption is too long
+fn to_media_message(
+    rss_title: &str,
+    rss_link: &str,
+    item: &feed::Item,
+    excerpt: bool,
+) -> Option<data::OutboxMessage> {
+    let mut photos: Vec<String> = item.media
+        .iter()
+        .filter(|media| media.kind == feed::MediaKind::Image)
+        .take(MEDIA_GROUP_MAX)
+        .map(|media| media.url.clone())
+        .collect();
+    let attachment = match photos.len() {
+        0 => item.media
+            .iter()
+            .find(|media| media.kind == feed::MediaKind::Audio)
+            .map(|media| data::Attachment::Audio(media.url.clone()))?,
+        1 => data::Attachment::Photo(photos.pop().unwrap()),
+        _ => data::Attachment::Photos(photos),
+    };
+    let caption = format!(
+        "<b>{}</b>\n{}",
+        Escape(rss_title),
+        format_item(rss_title, rss_link, item, excerpt)
+    );
+    if html::text_len(&caption) > CAPTION_MAX_LEN {
+        return None;
+    }
+    Some(data::OutboxMessage {
+        text: caption,
+        attachment: Some(attachment),
+    })
+}
+
+#[test]
+fn test_looks_dead() {
+    let day = 24 * 60 * 60;
+    assert!(!looks_dead(0, 0, 0));
+    assert!(!looks_dead(0, 0, 5 * day - 1));
+    assert!(looks_dead(0, 5 * day - 1, 5 * day));
+    assert!(!looks_dead(0, 5 * day, 6 * day));
+    assert!(looks_dead(0, day, 12 * day));
+    assert!(looks_dead(day, 9 * day, 11 * day));
+}
+
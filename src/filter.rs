@@ -0,0 +1,132 @@
+use regex::{Regex, RegexBuilder};
+
+use errors::*;
+use feed::Item;
+
+/// Include and exclude rules of a single subscription
+///
+/// A rule is a plain keyword (matched case-insensitively),
+/// or a regex when it's wrapped in slashes, e.g. `/rust(lang)?/`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Filter {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+enum Rule {
+    Keyword(String),
+    Regex(Regex),
+}
+
+impl Rule {
+    fn parse(rule: &str) -> Result<Rule> {
+        if rule.len() > 2 && rule.starts_with('/') && rule.ends_with('/') {
+            let re = RegexBuilder::new(&rule[1..rule.len() - 1])
+                .case_insensitive(true)
+                .build()?;
+            Ok(Rule::Regex(re))
+        } else {
+            Ok(Rule::Keyword(rule.to_lowercase()))
+        }
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match *self {
+            Rule::Keyword(ref keyword) => text.to_lowercase().contains(keyword.as_str()),
+            Rule::Regex(ref re) => re.is_match(text),
+        }
+    }
+}
+
+fn compile(rules: &[String]) -> Vec<Rule> {
+    // rules are validated before saving
+    rules.iter().filter_map(|r| Rule::parse(r).ok()).collect()
+}
+
+fn item_text(item: &Item) -> String {
+    let title = item.title.as_ref().map(|s| s.as_str()).unwrap_or_default();
+    let link = item.link.as_ref().map(|s| s.as_str()).unwrap_or_default();
+    format!("{}\n{}", title, link)
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn add_include(&mut self, rule: &str) -> Result<()> {
+        Rule::parse(rule)?;
+        if !self.include.iter().any(|r| r == rule) {
+            self.include.push(rule.to_owned());
+        }
+        Ok(())
+    }
+
+    pub fn add_exclude(&mut self, rule: &str) -> Result<()> {
+        Rule::parse(rule)?;
+        if !self.exclude.iter().any(|r| r == rule) {
+            self.exclude.push(rule.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Keep the items that match any include rule (if there is one)
+    /// and don't match any exclude rule
+    pub fn apply(&self, items: &[Item]) -> Vec<Item> {
+        let include = compile(&self.include);
+        let exclude = compile(&self.exclude);
+        items
+            .iter()
+            .filter(|item| {
+                let text = item_text(item);
+                (include.is_empty() || include.iter().any(|r| r.is_match(&text)))
+                    && !exclude.iter().any(|r| r.is_match(&text))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn test_filter() {
+    fn item(title: &str) -> Item {
+        Item {
+            title: Some(title.into()),
+            link: Some("http://example.com/".into()),
+            id: None,
+            summary: None,
+            content: None,
+            media: vec![],
+            thumbnail: None,
+            published: None,
+            updated: None,
+        }
+    }
+    let items = vec![
+        item("Rust 1.0 released"),
+        item("Go 1.10 released"),
+        item("rustfmt is stable"),
+        item("Weekly news"),
+    ];
+
+    let mut filter = Filter::default();
+    assert!(filter.is_empty());
+    assert_eq!(filter.apply(&items), items);
+
+    filter.add_include("RUST").unwrap();
+    assert_eq!(filter.apply(&items), vec![items[0].clone(), items[2].clone()]);
+
+    filter.add_exclude("/^rust\\w+/").unwrap();
+    assert_eq!(filter.apply(&items), vec![items[0].clone()]);
+
+    let mut filter = Filter::default();
+    filter.add_exclude("released").unwrap();
+    filter.add_exclude("released").unwrap();
+    assert_eq!(filter.exclude.len(), 1);
+    assert_eq!(filter.apply(&items), vec![items[2].clone(), items[3].clone()]);
+
+    assert!(filter.add_include("/(/").is_err());
+    assert!(filter.include.is_empty());
+}
@@ -0,0 +1,883 @@
+//! Converts the HTML of feeds into the markup Telegram accepts
+//!
+//! Telegram only knows a few tags and rejects the whole message if the markup is invalid,
+//! so every other tag is either mapped to one of them or dropped with its text kept,
+//! https://core.telegram.org/bots/api#html-style
+//!
+//! It also finds the feeds a web page links to, for subscribing to a page instead of a feed
+
+use std::borrow::Cow;
+use std::char;
+use std::cmp::{max, min};
+
+use utlis::{Escape, EscapeUrl};
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    /// Entities are decoded
+    Text(Cow<'a, str>),
+    /// Lowercased name, attributes and whether it's self-closing
+    Start(String, Vec<(String, String)>, bool),
+    End(String),
+}
+
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            rest = rest[4..].find("-->").map_or("", |i| &rest[4 + i + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |i| &rest[i + 1..]);
+        } else if let Some((token, len)) = parse_tag(rest) {
+            rest = &rest[len..];
+            if let Token::Start(ref name, _, false) = token {
+                // the content of these is not HTML
+                if name == "script" || name == "style" {
+                    let end = rest.to_ascii_lowercase()
+                        .find(&format!("</{}", name))
+                        .unwrap_or_else(|| rest.len());
+                    rest = &rest[end..];
+                }
+            }
+            tokens.push(token);
+        } else {
+            // a `<` which doesn't start a tag is just text
+            let start = if rest.starts_with('<') { 1 } else { 0 };
+            let end = rest[start..].find('<').map_or(rest.len(), |i| i + start);
+            tokens.push(Token::Text(decode_entities(&rest[..end])));
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// Parses the tag at the start of `s`, returns it and its length
+fn parse_tag(s: &str) -> Option<(Token<'static>, usize)> {
+    let bytes = s.as_bytes();
+    let is_ws = |i: usize| i < bytes.len() && bytes[i].is_ascii_whitespace();
+    let (is_end, mut i) = if s.starts_with("</") {
+        (true, 2)
+    } else if s.starts_with('<') {
+        (false, 1)
+    } else {
+        return None;
+    };
+    if i >= bytes.len() || !bytes[i].is_ascii_alphabetic() {
+        return None;
+    }
+    let name_len = s[i..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == ':'))
+        .unwrap_or_else(|| s.len() - i);
+    let name = s[i..i + name_len].to_ascii_lowercase();
+    i += name_len;
+
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while is_ws(i) {
+            i += 1;
+        }
+        if i >= bytes.len() {
+            // never closed, so it's text
+            return None;
+        }
+        match bytes[i] {
+            b'>' => {
+                i += 1;
+                break;
+            }
+            b'/' => {
+                self_closing = true;
+                i += 1;
+                continue;
+            }
+            _ => self_closing = false,
+        }
+        let key_len = s[i..]
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or_else(|| s.len() - i);
+        let key_len = max(key_len, 1);
+        let key = s[i..i + key_len].to_ascii_lowercase();
+        i += key_len;
+        while is_ws(i) {
+            i += 1;
+        }
+        let mut value = String::new();
+        if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            while is_ws(i) {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                let quote = bytes[i] as char;
+                let len = s[i + 1..].find(quote)?;
+                value = decode_entities(&s[i + 1..i + 1 + len]).into_owned();
+                i += len + 2;
+            } else {
+                let len = s[i..]
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or_else(|| s.len() - i);
+                value = decode_entities(&s[i..i + len]).into_owned();
+                i += len;
+            }
+        }
+        attributes.push((key, value));
+    }
+
+    let token = if is_end {
+        Token::End(name)
+    } else {
+        Token::Start(name, attributes, self_closing)
+    };
+    Some((token, i))
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if entity.starts_with("#x") || entity.starts_with("#X") {
+        return u32::from_str_radix(&entity[2..], 16)
+            .ok()
+            .and_then(char::from_u32);
+    } else if entity.starts_with('#') {
+        return entity[1..].parse().ok().and_then(char::from_u32);
+    }
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ensp" => '\u{2002}',
+        "emsp" => '\u{2003}',
+        "thinsp" => '\u{2009}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "hellip" => '…',
+        "middot" => '·',
+        "bull" => '•',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "times" => '×',
+        "deg" => '°',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Decodes the character references in `s`, the unknown ones are kept as is
+pub fn decode_entities(s: &str) -> Cow<str> {
+    if !s.contains('&') {
+        return Cow::Borrowed(s);
+    }
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        // the longest entity we know is `thinsp`
+        let decoded = rest.char_indices()
+            .take(12)
+            .find(|&(_, c)| c == ';')
+            .and_then(|(end, _)| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    Cow::Owned(result)
+}
+
+/// Elements dropped with their content
+fn is_dropped(name: &str) -> bool {
+    match name {
+        "script" | "style" | "head" | "title" | "noscript" | "template" | "iframe" | "object"
+        | "svg" | "select" | "button" => true,
+        _ => false,
+    }
+}
+
+/// Line breaks around the element
+fn block_breaks(name: &str) -> Option<usize> {
+    match name {
+        "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Some(2),
+        "div" | "section" | "article" | "header" | "footer" | "main" | "aside" | "nav"
+        | "address" | "figure" | "figcaption" | "blockquote" | "pre" | "ul" | "ol" | "li"
+        | "dl" | "dt" | "dd" | "table" | "tr" | "hr" => Some(1),
+        _ => None,
+    }
+}
+
+/// The Telegram tag an element is written as
+fn tag_for(name: &str) -> Option<&'static str> {
+    let tag = match name {
+        "b" | "strong" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => "b",
+        "i" | "em" | "cite" | "dfn" | "var" => "i",
+        "u" | "ins" => "u",
+        "s" | "strike" | "del" => "s",
+        "code" | "kbd" | "samp" | "tt" => "code",
+        "pre" => "pre",
+        "blockquote" => "blockquote",
+        "a" => "a",
+        _ => return None,
+    };
+    Some(tag)
+}
+
+/// Telegram doesn't know where relative links are relative to
+fn is_absolute_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    ["http://", "https://", "mailto:", "tg://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+#[derive(Default)]
+struct Converter {
+    output: String,
+    /// Elements opened in the source, with the tag written for each if any
+    stack: Vec<(String, Option<&'static str>)>,
+    /// Counters of ordered lists, `None` for unordered ones
+    lists: Vec<Option<u32>>,
+    /// Line breaks to write before the next text
+    breaks: usize,
+    /// A space to write before the next text
+    space: bool,
+    /// Whether the last char written is a space or a line break
+    last_space: bool,
+    /// Whether any text is written, there is no break before the first line
+    has_text: bool,
+    /// Depth in the elements dropped with their content
+    skip: usize,
+}
+
+impl Converter {
+    fn in_tag(&self, tag: &str) -> bool {
+        self.stack.iter().any(|&(_, open)| open == Some(tag))
+    }
+
+    fn block(&mut self, breaks: usize) {
+        if self.has_text {
+            self.breaks = max(self.breaks, breaks);
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.has_text {
+            return;
+        }
+        if self.breaks > 0 {
+            for _ in 0..self.breaks {
+                self.output.push('\n');
+            }
+            self.breaks = 0;
+            self.last_space = true;
+        } else if self.space && !self.last_space {
+            self.output.push(' ');
+            self.last_space = true;
+        }
+        self.space = false;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        if !s.is_empty() {
+            self.flush();
+            self.output.push_str(&Escape(s).to_string());
+            self.has_text = true;
+            self.last_space = s.ends_with(char::is_whitespace);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.in_tag("pre") {
+            self.push_str(text);
+            return;
+        }
+        for (i, word) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+            self.push_str(word);
+        }
+    }
+
+    fn open(&mut self, name: &str, attributes: &[(String, String)]) {
+        let in_code = self.in_tag("code") || self.in_tag("pre");
+        let tag = match tag_for(name) {
+            Some(tag) if !in_code && !self.in_tag(tag) => Some(tag),
+            _ => None,
+        };
+        let markup = match tag {
+            Some("a") => attributes
+                .iter()
+                .find(|&&(ref key, _)| key == "href")
+                .map(|&(_, ref href)| href.trim())
+                .and_then(|href| {
+                    if is_absolute_url(href) {
+                        Some(format!("<a href=\"{}\">", EscapeUrl(href)))
+                    } else {
+                        None
+                    }
+                }),
+            Some(tag) => Some(format!("<{}>", tag)),
+            None => None,
+        };
+        let tag = match markup {
+            Some(markup) => {
+                self.flush();
+                self.output.push_str(&markup);
+                tag
+            }
+            None => None,
+        };
+        self.stack.push((name.to_owned(), tag));
+    }
+
+    fn close(&mut self, name: &str) {
+        // misnested tags are closed together
+        if let Some(pos) = self.stack.iter().rposition(|&(ref open, _)| open == name) {
+            let closed: Vec<_> = self.stack.drain(pos..).collect();
+            for (_, tag) in closed.into_iter().rev() {
+                if let Some(tag) = tag {
+                    self.output.push_str("</");
+                    self.output.push_str(tag);
+                    self.output.push('>');
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, name: &str, attributes: &[(String, String)], self_closing: bool) {
+        if is_dropped(name) {
+            if !self_closing {
+                self.skip += 1;
+            }
+            return;
+        }
+        if self.skip > 0 {
+            return;
+        }
+        match name {
+            "br" => if self.in_tag("pre") {
+                self.output.push('\n');
+            } else if self.has_text {
+                self.breaks = min(self.breaks + 1, 2);
+            },
+            "td" | "th" => self.space = true,
+            "ul" => {
+                self.block(1);
+                self.lists.push(None);
+            }
+            "ol" => {
+                self.block(1);
+                let start = attributes
+                    .iter()
+                    .find(|&&(ref key, _)| key == "start")
+                    .and_then(|&(_, ref start)| start.trim().parse::<u32>().ok())
+                    .unwrap_or(1);
+                self.lists.push(Some(start.saturating_sub(1)));
+            }
+            "li" => {
+                self.block(1);
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let bullet = match self.lists.last_mut() {
+                    Some(&mut Some(ref mut n)) => {
+                        *n += 1;
+                        format!("{}{}. ", indent, n)
+                    }
+                    _ => format!("{}• ", indent),
+                };
+                self.push_str(&bullet);
+            }
+            _ => {
+                if let Some(breaks) = block_breaks(name) {
+                    self.block(breaks);
+                }
+                if tag_for(name).is_some() && !self_closing {
+                    self.open(name, attributes);
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if is_dropped(name) {
+            self.skip = self.skip.saturating_sub(1);
+            return;
+        }
+        if self.skip > 0 {
+            return;
+        }
+        if name == "ul" || name == "ol" {
+            self.lists.pop();
+        }
+        if tag_for(name).is_some() {
+            self.close(name);
+        }
+        if let Some(breaks) = block_breaks(name) {
+            self.block(breaks);
+        }
+    }
+
+    fn finish(mut self) -> String {
+        for (_, tag) in self.stack.drain(..).rev() {
+            if let Some(tag) = tag {
+                self.output.push_str("</");
+                self.output.push_str(tag);
+                self.output.push('>');
+            }
+        }
+        self.output
+    }
+}
+
+/// Converts HTML into the markup of Telegram's HTML parse mode
+///
+/// Headings become bold lines, list items become bullets, links with a relative URL
+/// lose the link, and anything that can't be shown as text is dropped.
+pub fn to_telegram_html(html: &str) -> String {
+    let mut converter = Converter::default();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(ref text) if converter.skip == 0 => converter.text(text),
+            Token::Text(_) => (),
+            Token::Start(ref name, ref attributes, self_closing) => {
+                converter.start(name, attributes, self_closing)
+            }
+            Token::End(ref name) => converter.end(name),
+        }
+    }
+    converter.finish()
+}
+
+/// The text of HTML without any markup, blocks are put on separated lines
+pub fn to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut skip = 0;
+    for token in tokenize(html) {
+        match token {
+            Token::Start(ref name, _, false) if is_dropped(name) => skip += 1,
+            Token::End(ref name) if is_dropped(name) => skip = max(skip, 1) - 1,
+            _ if skip > 0 => (),
+            Token::Text(ref s) => text.push_str(s),
+            Token::Start(ref name, _, _) | Token::End(ref name)
+                if name == "br" || block_breaks(name).is_some() =>
+            {
+                text.push('\n')
+            }
+            _ => (),
+        }
+    }
+    text
+}
+
+/// The length of a char or an entity as Telegram counts it, in UTF-16 code units
+fn unit_len(s: &str) -> (usize, usize) {
+    if s.starts_with('&') {
+        if let Some(end) = s.find(';') {
+            return (end + 1, 1);
+        }
+    }
+    let c = s.chars().next().unwrap();
+    (c.len_utf8(), c.len_utf16())
+}
+
+/// The length of the text in Telegram HTML
+pub fn text_len(html: &str) -> usize {
+    let mut len = 0;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            rest = rest.find('>').map_or("", |i| &rest[i + 1..]);
+            continue;
+        }
+        let (bytes, units) = unit_len(rest);
+        len += units;
+        rest = &rest[bytes..];
+    }
+    len
+}
+
+/// How many bytes of `text` fit into `room`, and their length
+fn fit(text: &str, room: usize) -> (usize, usize) {
+    let mut end = 0;
+    let mut len = 0;
+    while end < text.len() {
+        let (bytes, units) = unit_len(&text[end..]);
+        if len + units > room {
+            break;
+        }
+        end += bytes;
+        len += units;
+    }
+    (end, len)
+}
+
+fn closing_tag(open: &str) -> String {
+    let name = open[1..]
+        .split(|c: char| c.is_ascii_whitespace() || c == '>')
+        .next()
+        .unwrap_or_default();
+    format!("</{}>", name)
+}
+
+/// Splits Telegram HTML into parts with at most `max` chars of text
+///
+/// Parts are split at a line break or a space if possible, and never inside a tag or
+/// an entity. The tags open at a split are closed and opened again in the next part.
+pub fn split(html: &str, max: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut len = 0;
+    let mut open: Vec<&str> = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            if tag.starts_with("</") {
+                open.pop();
+            } else {
+                open.push(tag);
+            }
+            part.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or_else(|| rest.len());
+        let mut text = &rest[..end];
+        rest = &rest[end..];
+        loop {
+            let (mut cut, cut_len) = fit(text, max - len);
+            if cut == text.len() {
+                part.push_str(text);
+                len += cut_len;
+                break;
+            }
+            let prefix = &text[..cut];
+            let space = match prefix.rfind('\n') {
+                Some(i) if i >= prefix.len() / 2 => Some(i),
+                _ => prefix.rfind(|c: char| c.is_whitespace()),
+            };
+            if let Some(i) = space {
+                cut = i + prefix[i..].chars().next().unwrap().len_utf8();
+            } else if len > 0 {
+                // try to keep the word whole in the next part
+                cut = 0;
+            } else if cut == 0 {
+                // `max` is too small for anything, take a char anyway
+                cut = unit_len(text).0;
+            }
+            part.push_str(&text[..cut]);
+            text = &text[cut..];
+
+            let mut closed = false;
+            for tag in open.iter().rev() {
+                // don't leave an empty tag behind
+                if !closed && part.ends_with(tag) {
+                    let len = part.len() - tag.len();
+                    part.truncate(len);
+                } else {
+                    part.push_str(&closing_tag(tag));
+                    closed = true;
+                }
+            }
+            parts.push(part);
+            part = open.concat();
+            len = 0;
+        }
+    }
+    if len > 0 || parts.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+/// Cuts Telegram HTML to at most `max` chars of text, `...` is added if it's cut
+pub fn truncate(html: &str, max: usize) -> String {
+    if text_len(html) <= max {
+        return html.to_owned();
+    }
+    let mut part = split(html, max.saturating_sub(3)).swap_remove(0);
+    part.push_str("...");
+    part
+}
+
+/// A feed announced by a web page with `<link rel="alternate">`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedLink {
+    pub title: Option<String>,
+    /// As written in the page, may be relative
+    pub link: String,
+}
+
+/// The feeds linked from the `<head>` of a web page
+pub fn find_feed_links(html: &str) -> Vec<FeedLink> {
+    // the body can be huge, and feeds are not linked there
+    let head = match html.to_ascii_lowercase().find("<body") {
+        Some(end) => &html[..end],
+        None => html,
+    };
+    let mut links: Vec<FeedLink> = Vec::new();
+    for token in tokenize(head) {
+        let attributes = match token {
+            Token::Start(ref name, ref attributes, _) if name == "link" => attributes,
+            _ => continue,
+        };
+        let get = |key: &str| {
+            attributes
+                .iter()
+                .find(|&&(ref k, _)| k == key)
+                .map(|&(_, ref v)| v.trim())
+        };
+        let is_alternate = get("rel").map_or(false, |rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let is_feed = match get("type").map(|t| t.to_ascii_lowercase()) {
+            Some(ref t) => {
+                t == "application/rss+xml" || t == "application/atom+xml"
+                    || t == "application/feed+json"
+            }
+            None => false,
+        };
+        let link = match get("href") {
+            Some(link) if is_alternate && is_feed && !link.is_empty() => link.to_owned(),
+            _ => continue,
+        };
+        if links.iter().all(|l| l.link != link) {
+            links.push(FeedLink {
+                title: get("title").filter(|t| !t.is_empty()).map(|t| t.to_owned()),
+                link: link,
+            });
+        }
+    }
+    links
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(
+        tokenize(r#"a<A HREF='x&amp;y' id=z data-x>b<br/>&lt;&unknown;</a><!-- c -->< d"#),
+        vec![
+            Token::Text("a".into()),
+            Token::Start(
+                "a".into(),
+                vec![
+                    ("href".into(), "x&y".into()),
+                    ("id".into(), "z".into()),
+                    ("data-x".into(), "".into()),
+                ],
+                false,
+            ),
+            Token::Text("b".into()),
+            Token::Start("br".into(), vec![], true),
+            Token::Text("<&unknown;".into()),
+            Token::End("a".into()),
+            Token::Text("< d".into()),
+        ]
+    );
+    assert_eq!(
+        tokenize("<script>if (a < b) { '</p>' }</SCRIPT>x<p"),
+        vec![
+            Token::Start("script".into(), vec![], false),
+            Token::End("script".into()),
+            Token::Text("x".into()),
+            Token::Text("<p".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_decode_entities() {
+    assert_eq!(decode_entities("a &amp; b"), "a & b");
+    assert_eq!(decode_entities("&#60;&#x3E;&#X4f60;"), "<>你");
+    assert_eq!(decode_entities("&hellip;&nbsp;&mdash;"), "…\u{a0}—");
+    assert_eq!(decode_entities("AT&T &amp"), "AT&T &amp");
+    assert_eq!(decode_entities("&#xD800; &#99999999; &;"), "&#xD800; &#99999999; &;");
+    assert_eq!(decode_entities("&amp;lt;"), "&lt;");
+}
+
+#[test]
+fn test_to_telegram_html() {
+    let cases = vec![
+        ("plain text", "plain text"),
+        ("  a \n\t b  ", "a b"),
+        ("<p>a &lt; b &amp;&amp; c &gt; d</p>", "a &lt; b &amp;&amp; c &gt; d"),
+        ("a \"quoted\" text", "a &quot;quoted&quot; text"),
+        (
+            "<h1>Title</h1><p>Hello, <strong>world</strong>!</p><p>Bye</p>",
+            "<b>Title</b>\n\nHello, <b>world</b>!\n\nBye",
+        ),
+        (
+            "<em>i</em> <u>u</u> <ins>u</ins> <del>s</del> <strike>s</strike> <kbd>c</kbd>",
+            "<i>i</i> <u>u</u> <u>u</u> <s>s</s> <s>s</s> <code>c</code>",
+        ),
+        ("<ul><li>a</li><li> b </li></ul>", "• a\n• b"),
+        ("<ol start=\"3\"><li>a<li>b</ol>", "3. a\n4. b"),
+        (
+            "<ul><li>a<ol><li>b</li></ol></li><li>c</li></ul>",
+            "• a\n  1. b\n• c",
+        ),
+        (
+            r#"<a href="https://example.com/?a=1&amp;b=2">link</a>"#,
+            r#"<a href="https://example.com/?a=1&b=2">link</a>"#,
+        ),
+        (r#"<a href="/relative">link</a>"#, "link"),
+        (r#"<a href="javascript:alert(1)">link</a>"#, "link"),
+        (r#"<a name="anchor">text</a>"#, "text"),
+        (
+            r#"<a href='http://a.com/"><b>'>x</a>"#,
+            r#"<a href="http://a.com/%22%3E%3Cb%3E">x</a>"#,
+        ),
+        (
+            "<script>alert('<b>')</script><style>p { }</style>text<noscript>no</noscript>",
+            "text",
+        ),
+        ("<head><title>t</title></head><body>body</body>", "body"),
+        ("<img src=\"a.png\" alt=\"a\"><p>text</p>", "text"),
+        ("<!-- <b>comment</b> -->text", "text"),
+        ("<![CDATA[x]]><?php echo ?>text", "text"),
+        (
+            "<pre>  fn main() {\n    <b>x</b> &lt; y\n}</pre>",
+            "<pre>  fn main() {\n    x &lt; y\n}</pre>",
+        ),
+        ("<pre><code>a<br>b</code></pre>", "<pre>a\nb</pre>"),
+        ("<code><a href=\"http://a.com\">x</a></code>", "<code>x</code>"),
+        (
+            "<blockquote>a<blockquote>b</blockquote></blockquote>c",
+            "<blockquote>a\nb</blockquote>\nc",
+        ),
+        ("<b>a<i>b</b>c</i>d", "<b>a<i>b</i></b>cd"),
+        ("<b>a<b>b</b>c</b>", "<b>abc</b>"),
+        ("</i>a<b>b", "a<b>b</b>"),
+        (
+            "<a href=\"http://a.com\">a<a href=\"http://b.com\">b</a>",
+            "<a href=\"http://a.com\">ab</a>",
+        ),
+        ("a<br>b<br><br><br>c", "a\nb\n\nc"),
+        ("<br><p></p>a<hr>b<p>", "a\nb"),
+        ("<table><tr><td>a</td><td>b</td></tr><tr><td>c</td></tr></table>", "a b\nc"),
+        ("<p>a<b> b </b>c</p>", "a<b> b</b> c"),
+        ("<p>a <b> b</b></p>", "a <b>b</b>"),
+        ("a&nbsp;&nbsp;b", "a\u{a0}\u{a0}b"),
+        ("<b></b>", "<b></b>"),
+        ("1 < 2 <3", "1 &lt; 2 &lt;3"),
+        ("", ""),
+    ];
+    for (html, expected) in cases {
+        assert_eq!(to_telegram_html(html), expected, "{:?}", html);
+    }
+}
+
+#[test]
+fn test_to_text() {
+    assert_eq!(
+        to_text("<style>p {}</style><p>Hello,&nbsp;<b>world</b>!</p><p>a<br>b</p>"),
+        "\nHello,\u{a0}world!\n\na\nb\n"
+    );
+}
+
+#[test]
+fn test_text_len() {
+    assert_eq!(text_len("<b>a &amp; b</b>"), 5);
+    assert_eq!(text_len("<a href=\"http://a.com/\">你好</a>"), 2);
+    assert_eq!(text_len("😀"), 2);
+    assert_eq!(text_len(""), 0);
+}
+
+#[test]
+fn test_split() {
+    assert_eq!(split("", 10), vec![""]);
+    assert_eq!(split("<b>short</b>", 10), vec!["<b>short</b>"]);
+    assert_eq!(
+        split("aaa bbb ccc", 5),
+        vec!["aaa ", "bbb ", "ccc"]
+    );
+    // tags are reopened
+    assert_eq!(
+        split("<b>aaa <i>bbb ccc</i></b> ddd", 5),
+        vec!["<b>aaa </b>", "<b><i>bbb </i></b>", "<b><i>ccc</i></b> ", "ddd"]
+    );
+    assert_eq!(
+        split("<a href=\"http://a.com/\">aaaa bbbb</a>", 6),
+        vec![
+            "<a href=\"http://a.com/\">aaaa </a>",
+            "<a href=\"http://a.com/\">bbbb</a>",
+        ]
+    );
+    // entities are not cut
+    assert_eq!(split("&amp;&amp;&amp;", 2), vec!["&amp;&amp;", "&amp;"]);
+    // long words are cut, short ones are moved to the next part
+    assert_eq!(split("abcdefg", 3), vec!["abc", "def", "g"]);
+    assert_eq!(split("<b>ab</b>cdef", 4), vec!["<b>ab</b>", "cdef"]);
+    // a line break is preferred
+    assert_eq!(
+        split("aaaaaa\nbb cc dd", 10),
+        vec!["aaaaaa\n", "bb cc dd"]
+    );
+    // surrogate pairs count as 2
+    assert_eq!(split("😀😀😀", 4), vec!["😀😀", "😀"]);
+    assert_eq!(split("😀", 1), vec!["😀"]);
+
+    let html = to_telegram_html(&"<p>Lorem <b>ipsum</b> dolor sit amet</p>".repeat(500));
+    let parts = split(&html, 4096);
+    assert!(parts.len() > 1);
+    for part in &parts {
+        assert!(text_len(part) <= 4096);
+        assert_eq!(part.matches("<b>").count(), part.matches("</b>").count());
+    }
+    assert_eq!(
+        parts.iter().map(|part| text_len(part)).sum::<usize>(),
+        text_len(&html)
+    );
+}
+
+#[test]
+fn test_truncate() {
+    assert_eq!(truncate("<b>short</b>", 5), "<b>short</b>");
+    assert_eq!(truncate("<b>aaa bbb</b> ccc", 8), "<b>aaa </b>...");
+    assert_eq!(truncate("abcdefgh", 6), "abc...");
+}
+
+#[test]
+fn test_find_feed_links() {
+    let html = r#"<!DOCTYPE html>
+<html><head>
+<link rel="stylesheet" type="text/css" href="/style.css">
+<LINK REL="alternate" TYPE="application/rss+xml" TITLE="Posts" HREF="/feed.xml">
+<link rel="alternate feed" type="application/atom+xml" href="atom.xml" title="">
+<link rel="alternate" type="application/rss+xml" href="/feed.xml" title="Again">
+<link rel="alternate" type="text/html" hreflang="en" href="/en/">
+</head>
+<body><link rel="alternate" type="application/feed+json" href="/feed.json"></body>
+</html>"#;
+    assert_eq!(
+        find_feed_links(html),
+        vec![
+            FeedLink {
+                title: Some("Posts".into()),
+                link: "/feed.xml".into(),
+            },
+            FeedLink {
+                title: None,
+                link: "atom.xml".into(),
+            },
+        ]
+    );
+}
@@ -0,0 +1,327 @@
+//! Message catalogs of the bot
+//!
+//! Every locale is a `Messages` with the same fields, `{}` in a message is
+//! replaced by `fill` in order. To add a locale, add a variant to `Lang`
+//! and a catalog for it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lang {
+    #[serde(rename = "zh")]
+    Zh,
+    #[serde(rename = "en")]
+    En,
+}
+
+pub const LANGS: &[Lang] = &[Lang::Zh, Lang::En];
+
+impl Lang {
+    /// Parses an IETF language tag like `en-US`, only the primary language is used
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let primary = code.split(|c: char| c == '-' || c == '_')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        LANGS.iter().cloned().find(|lang| lang.code() == primary)
+    }
+
+    /// The language for a Telegram user, Chinese if unknown since it's where the bot started
+    pub fn from_language_code(code: Option<&str>) -> Lang {
+        match code {
+            Some(code) => Lang::from_code(code).unwrap_or(Lang::En),
+            None => Lang::Zh,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Zh => "zh",
+            Lang::En => "en",
+        }
+    }
+
+    pub fn msgs(self) -> &'static Messages {
+        match self {
+            Lang::Zh => &ZH,
+            Lang::En => &EN,
+        }
+    }
+}
+
+impl Default for Lang {
+    fn default() -> Lang {
+        Lang::Zh
+    }
+}
+
+/// Replaces each `{}` in `template` with the next one of `args`
+pub fn fill(template: &str, args: &[&str]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    result.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        result.push_str(args.next().cloned().unwrap_or_default());
+        result.push_str(part);
+    }
+    result
+}
+
+pub struct Messages {
+    /// Name of this language in itself
+    pub name: &'static str,
+    pub rss_usage: &'static str,
+    pub sub_usage: &'static str,
+    pub unsub_usage: &'static str,
+    pub unsubthis_usage: &'static str,
+    pub export_usage: &'static str,
+    pub filter_usage: &'static str,
+    pub lang_usage: &'static str,
+    pub import_usage: &'static str,
+    pub excerpt_usage: &'static str,
+    pub media_usage: &'static str,
+    pub status_usage: &'static str,
+    pub digest_usage: &'static str,
+    pub timezone_usage: &'static str,
+    pub empty_list: &'static str,
+    pub subscription_list: &'static str,
+    pub processing: &'static str,
+    pub already_subscribed: &'static str,
+    pub not_subscribed: &'static str,
+    /// {error}
+    pub subscribe_failed: &'static str,
+    /// {link} {title}, HTML
+    pub subscribed: &'static str,
+    /// {link} {title}, HTML
+    pub unsubscribed: &'static str,
+    pub unrecognized_message: &'static str,
+    pub subscription_not_found: &'static str,
+    pub verifying_channel: &'static str,
+    /// {error}
+    pub channel_not_found: &'static str,
+    pub not_a_channel: &'static str,
+    /// {error}
+    pub bot_not_in_channel: &'static str,
+    pub bot_not_admin: &'static str,
+    pub user_not_admin: &'static str,
+    /// {link}, HTML
+    pub no_filter: &'static str,
+    /// {link} {include} {exclude}, HTML
+    pub filter_rules: &'static str,
+    /// {error}
+    pub invalid_regex: &'static str,
+    /// {name} {code} {available}
+    pub current_lang: &'static str,
+    /// {name}
+    pub lang_set: &'static str,
+    /// {error}
+    pub import_invalid: &'static str,
+    pub import_empty: &'static str,
+    /// {count}
+    pub importing: &'static str,
+    pub import_result: &'static str,
+    /// {link}, HTML
+    pub excerpt_on: &'static str,
+    /// {link}, HTML
+    pub excerpt_off: &'static str,
+    /// {link}, HTML
+    pub media_on: &'static str,
+    /// {link}, HTML
+    pub media_off: &'static str,
+    /// {link} {title} {error}, HTML
+    pub feed_failing: &'static str,
+    /// {link} {title} {new link}, HTML
+    pub feed_moved: &'static str,
+    /// {error}
+    pub network_error: &'static str,
+    /// {error}
+    pub encoding_error: &'static str,
+    /// {error}
+    pub parse_error: &'static str,
+    /// {status}, like `404 Not Found`
+    pub http_error: &'static str,
+    pub too_many_redirects: &'static str,
+    pub empty_feed: &'static str,
+    pub no_feed_found: &'static str,
+    /// followed by the feeds, one per line
+    pub feeds_found: &'static str,
+    /// followed by the feeds
+    pub status_list: &'static str,
+    pub status_never: &'static str,
+    /// {time}
+    pub status_last_fetch: &'static str,
+    /// {time} {error}
+    pub status_last_error: &'static str,
+    /// {count}
+    pub status_failures: &'static str,
+    /// {time}
+    pub status_last_item: &'static str,
+    /// {interval}
+    pub status_interval: &'static str,
+    pub digest_off: &'static str,
+    pub digest_hourly: &'static str,
+    /// {time} {time zone}
+    pub digest_daily: &'static str,
+    /// {time zone}
+    pub current_timezone: &'static str,
+}
+
+pub static ZH: Messages = Messages {
+    name: "中文",
+    rss_usage: "使用方法: /rss <Channel ID> <raw>",
+    sub_usage: "使用方法: /sub [Channel ID] <RSS URL>",
+    unsub_usage: "使用方法: /unsub [Channel ID] <RSS URL>",
+    unsubthis_usage: "使用方法: 使用此命令回复想要退订的 RSS 消息即可退订,不支持 Channel",
+    export_usage: "使用方法: /export <Channel ID>",
+    filter_usage: "使用方法: /filter [Channel ID] <RSS URL> \
+                   [include <关键词或 /正则/>|exclude <关键词或 /正则/>|clear]",
+    lang_usage: "使用方法: /lang [Channel ID] [zh|en]",
+    import_usage: "使用方法: 发送 OPML 文件时附上 /import [Channel ID], \
+                   或者使用此命令回复 OPML 文件",
+    excerpt_usage: "使用方法: /excerpt [Channel ID] <RSS URL> [on|off]",
+    media_usage: "使用方法: /media [Channel ID] <RSS URL> [on|off]",
+    status_usage: "使用方法: /status [Channel ID]",
+    digest_usage: "使用方法: /digest [Channel ID] [daily HH:MM|hourly|off]",
+    timezone_usage: "使用方法: /timezone [Channel ID] [+08:00]",
+    empty_list: "订阅列表为空",
+    subscription_list: "订阅列表:",
+    processing: "处理中, 请稍候",
+    already_subscribed: "已订阅过的 RSS",
+    not_subscribed: "未订阅过的 RSS",
+    subscribe_failed: "订阅失败: {}",
+    subscribed: "《<a href=\"{}\">{}</a>》订阅成功",
+    unsubscribed: "《<a href=\"{}\">{}</a>》退订成功",
+    unrecognized_message: "无法识别的消息",
+    subscription_not_found: "无法找到此订阅",
+    verifying_channel: "正在验证 Channel",
+    channel_not_found: "无法找到目标 Channel: {}",
+    not_a_channel: "目标需为 Channel",
+    bot_not_in_channel: "请先将本 Bot 加入目标 Channel并设为管理员: {}",
+    bot_not_admin: "请将本 Bot 设为管理员",
+    user_not_admin: "该命令只能由 Channel 管理员使用",
+    no_filter: "{} 没有过滤规则",
+    filter_rules: "{} 的过滤规则:\n包含: {}\n排除: {}",
+    invalid_regex: "无效的正则表达式: {}",
+    current_lang: "当前语言: {} ({}), 可用: {}",
+    lang_set: "语言已设置为{}",
+    import_invalid: "无法读取 OPML 文件: {}",
+    import_empty: "OPML 文件中没有 RSS",
+    importing: "正在导入 {} 个 RSS, 请稍候",
+    import_result: "导入结果:",
+    excerpt_on: "{} 的更新会附带摘要",
+    excerpt_off: "{} 的更新不附带摘要",
+    media_on: "{} 中带图片或音频的更新会以图片或音频发送",
+    media_off: "{} 的更新只以文字发送",
+    feed_failing: "《<a href=\"{}\">{}</a>》已经连续 5 天拉取出错 ({}),\
+                   可能已经关闭, 请取消订阅",
+    feed_moved: "《<a href=\"{}\">{}</a>》已经永久移动到 {}, 订阅已随之更新",
+    network_error: "网络错误 ({})",
+    encoding_error: "编码错误 ({})",
+    parse_error: "解析错误 ({})",
+    http_error: "HTTP 错误 ({})",
+    too_many_redirects: "重定向次数过多",
+    empty_feed: "RSS 为空或无效",
+    no_feed_found: "这是一个网页, 其中没有找到 RSS",
+    feeds_found: "这是一个网页, 其中有多个 RSS, 请选择一个订阅:",
+    status_list: "订阅状态:",
+    status_never: "无",
+    status_last_fetch: "上次拉取成功: {}",
+    status_last_error: "上次出错: {} {}",
+    status_failures: "连续出错 {} 次",
+    status_last_item: "上次有更新: {}",
+    status_interval: "拉取间隔: {}",
+    digest_off: "更新会逐条发送",
+    digest_hourly: "更新会在每小时整点汇总发送",
+    digest_daily: "更新会在每天 {} ({}) 汇总发送",
+    current_timezone: "当前时区: {}",
+};
+
+pub static EN: Messages = Messages {
+    name: "English",
+    rss_usage: "Usage: /rss <Channel ID> <raw>",
+    sub_usage: "Usage: /sub [Channel ID] <RSS URL>",
+    unsub_usage: "Usage: /unsub [Channel ID] <RSS URL>",
+    unsubthis_usage: "Usage: reply to an update of the RSS you want to unsubscribe \
+                      with this command, channels are not supported",
+    export_usage: "Usage: /export <Channel ID>",
+    filter_usage: "Usage: /filter [Channel ID] <RSS URL> \
+                   [include <keyword or /regex/>|exclude <keyword or /regex/>|clear]",
+    lang_usage: "Usage: /lang [Channel ID] [zh|en]",
+    import_usage: "Usage: send an OPML file with /import [Channel ID] as the caption, \
+                   or reply to an OPML file with this command",
+    excerpt_usage: "Usage: /excerpt [Channel ID] <RSS URL> [on|off]",
+    media_usage: "Usage: /media [Channel ID] <RSS URL> [on|off]",
+    status_usage: "Usage: /status [Channel ID]",
+    digest_usage: "Usage: /digest [Channel ID] [daily HH:MM|hourly|off]",
+    timezone_usage: "Usage: /timezone [Channel ID] [+08:00]",
+    empty_list: "No subscriptions",
+    subscription_list: "Subscriptions:",
+    processing: "Processing, please wait",
+    already_subscribed: "Already subscribed to this RSS",
+    not_subscribed: "Not subscribed to this RSS",
+    subscribe_failed: "Failed to subscribe: {}",
+    subscribed: "Subscribed to <a href=\"{}\">{}</a>",
+    unsubscribed: "Unsubscribed from <a href=\"{}\">{}</a>",
+    unrecognized_message: "Unrecognized message",
+    subscription_not_found: "Subscription not found",
+    verifying_channel: "Verifying the channel",
+    channel_not_found: "Channel not found: {}",
+    not_a_channel: "The target must be a channel",
+    bot_not_in_channel: "Please add this bot to the channel as an administrator first: {}",
+    bot_not_admin: "Please make this bot an administrator of the channel",
+    user_not_admin: "Only channel administrators can use this command",
+    no_filter: "{} has no filter rules",
+    filter_rules: "Filter rules of {}:\nInclude: {}\nExclude: {}",
+    invalid_regex: "Invalid regular expression: {}",
+    current_lang: "Current language: {} ({}), available: {}",
+    lang_set: "Language set to {}",
+    import_invalid: "Failed to read the OPML file: {}",
+    import_empty: "No RSS found in the OPML file",
+    importing: "Importing {} RSS, please wait",
+    import_result: "Import results:",
+    excerpt_on: "Updates of {} come with an excerpt",
+    excerpt_off: "Updates of {} come without an excerpt",
+    media_on: "Updates of {} with photos or audio are sent as Telegram media",
+    media_off: "Updates of {} are sent as text only",
+    feed_failing: "<a href=\"{}\">{}</a> has failed to update for 5 days ({}), \
+                   it may be gone, please unsubscribe",
+    feed_moved: "<a href=\"{}\">{}</a> has moved permanently to {}, \
+                 the subscription is moved with it",
+    network_error: "Network error ({})",
+    encoding_error: "Encoding error ({})",
+    parse_error: "Parse error ({})",
+    http_error: "HTTP error ({})",
+    too_many_redirects: "Too many redirects",
+    empty_feed: "The feed is empty or not valid",
+    no_feed_found: "This is a web page, and no feed is found in it",
+    feeds_found: "This is a web page linking to several feeds, please subscribe to one of them:",
+    status_list: "Subscription status:",
+    status_never: "never",
+    status_last_fetch: "Last fetched: {}",
+    status_last_error: "Last error: {} {}",
+    status_failures: "Failed {} times in a row",
+    status_last_item: "Last new item: {}",
+    status_interval: "Fetched every {}",
+    digest_off: "Updates are sent as they come",
+    digest_hourly: "Updates are sent as a digest at the start of every hour",
+    digest_daily: "Updates are sent as a digest every day at {} ({})",
+    current_timezone: "Current time zone: {}",
+};
+
+#[test]
+fn test_fill() {
+    assert_eq!(fill("a{}b{}c", &["1", "2"]), "a1b2c");
+    assert_eq!(fill("{}", &["{}"]), "{}");
+    assert_eq!(fill("{}{}", &["1"]), "1");
+    assert_eq!(fill("none", &["1"]), "none");
+}
+
+#[test]
+fn test_lang_from_code() {
+    assert_eq!(Lang::from_code("en"), Some(Lang::En));
+    assert_eq!(Lang::from_code("en-US"), Some(Lang::En));
+    assert_eq!(Lang::from_code("zh-hans"), Some(Lang::Zh));
+    assert_eq!(Lang::from_code("ZH_TW"), Some(Lang::Zh));
+    assert_eq!(Lang::from_code("de"), None);
+    assert_eq!(Lang::from_language_code(Some("de")), Lang::En);
+    assert_eq!(Lang::from_language_code(None), Lang::Zh);
+}
@@ -1,4 +1,5 @@
 #![feature(proc_macro, generators, proc_macro_non_items)]
+#![recursion_limit = "128"]
 
 
 #[macro_use]
@@ -6,11 +7,15 @@ extern crate log;
 extern crate env_logger;
 #[macro_use]
 extern crate error_chain;
+extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 extern crate curl;
+extern crate encoding_rs;
 extern crate futures_await as futures;
+extern crate hmac;
+extern crate hyper;
 extern crate quick_xml;
 extern crate telebot;
 extern crate tokio_core;
@@ -20,48 +25,161 @@ extern crate lazy_static;
 extern crate chrono;
 extern crate pinyin_order;
 extern crate regex;
+extern crate rusqlite;
+extern crate sha1;
+extern crate sha2;
+extern crate siphasher;
+extern crate url;
+
+use std::net::SocketAddr;
 
 use futures::Stream;
+use telebot::functions::*;
 use tokio_core::reactor::Core;
 
+mod canonical;
 mod checker;
 mod cmdhandles;
 mod data;
+mod date;
+mod digest;
 mod errors;
 mod feed;
 mod fetcher;
+mod filter;
+mod html;
+mod i18n;
 mod opml;
+mod ratelimit;
+mod scheduler;
+mod sender;
+mod storage;
 mod utlis;
+mod websub;
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: {} DATAFILE TELEGRAM-BOT-TOKEN", args[0]);
+fn print_error_and_exit(e: errors::Error) -> ! {
+    eprintln!("error: {}", e);
+    for e in e.iter().skip(1) {
+        eprintln!("caused by: {}", e);
+    }
+    if let Some(backtrace) = e.backtrace() {
+        eprintln!("backtrace: {:?}", backtrace);
+    }
+    std::process::exit(1);
+}
+
+/// Removes `name` from `args`, returns whether it exists
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes `name` and the value after it from `args`, returns the value
+fn take_flag(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    if i + 1 >= args.len() {
+        eprintln!("{} requires a value", name);
         std::process::exit(1);
     }
-    let datafile = &args[1];
-    let token = &args[2];
-    let period = args.get(3)
+    args.remove(i);
+    Some(args.remove(i))
+}
+
+/// Same as `take_flag`, but the value is an address, or `default` if it doesn't exist
+fn take_addr(args: &mut Vec<String>, name: &str, default: SocketAddr) -> SocketAddr {
+    take_flag(args, name)
         .map(|s| {
             s.parse().unwrap_or_else(|_| {
-                eprintln!("period must be unsigned");
+                eprintln!("invalid listen address: {}", s);
                 std::process::exit(1);
             })
         })
-        .unwrap_or(300);
+        .unwrap_or(default)
+}
 
-    let db = data::Database::open(datafile)
-        .map_err(|e| {
-            eprintln!("error: {}", e);
-            for e in e.iter().skip(1) {
-                eprintln!("caused by: {}", e);
-            }
-            if let Some(backtrace) = e.backtrace() {
-                eprintln!("backtrace: {:?}", backtrace);
-            }
-            std::process::exit(1);
+/// Parses the seconds given as `name`
+fn parse_seconds(s: &str, name: &str) -> u64 {
+    s.parse().unwrap_or_else(|_| {
+        eprintln!("{} must be unsigned", name);
+        std::process::exit(1);
+    })
+}
+
+fn run_update_stream<S>(lp: &mut Core, stream: S)
+where
+    S: Stream<Item = (telebot::RcBot, telebot::objects::Update), Error = telebot::Error>,
+{
+    let s = stream
+        .map(|_| ())
+        .or_else(|e| {
+            error!("telebot: {:?}", e);
+            Ok::<(), ()>(())
         })
-        .unwrap();
+        .for_each(|_| Ok(()));
+    lp.run(s).unwrap();
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let force_sqlite = take_switch(&mut args, "--sqlite");
+    let import_json = take_flag(&mut args, "--import-json");
+    let webhook = take_flag(&mut args, "--webhook");
+    let webhook_secret = take_flag(&mut args, "--webhook-secret");
+    let listen = take_addr(&mut args, "--listen", ([127, 0, 0, 1], 8080).into());
+    let websub_url = take_flag(&mut args, "--websub");
+    let websub_listen = take_addr(&mut args, "--websub-listen", ([127, 0, 0, 1], 8081).into());
+    let min_interval = take_flag(&mut args, "--min-interval")
+        .map(|s| parse_seconds(&s, "--min-interval"))
+        .unwrap_or(60);
+    let max_interval = take_flag(&mut args, "--max-interval")
+        .map(|s| parse_seconds(&s, "--max-interval"))
+        .unwrap_or(24 * 60 * 60);
+    // without a secret anyone who finds the webhook could forge updates
+    let missing_secret = webhook.is_some() && webhook_secret.is_none();
+    if missing_secret || args.len() < 3 && !(import_json.is_some() && args.len() == 2) {
+        eprintln!(
+            "Usage: {0} [--sqlite] [--webhook URL --webhook-secret SECRET [--listen ADDR]] \
+             [--websub URL [--websub-listen ADDR]] [--min-interval SECS] [--max-interval SECS] \
+             DATAFILE TELEGRAM-BOT-TOKEN [PERIOD]\n       \
+             {0} [--sqlite] --import-json JSON-DATAFILE DATAFILE",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let datafile = &args[1];
+    let backend = if force_sqlite {
+        storage::Backend::Sqlite
+    } else {
+        storage::Backend::from_path(datafile)
+    };
+
+    let db = data::Database::open(datafile, backend).unwrap_or_else(|e| print_error_and_exit(e));
+
+    if let Some(json_path) = import_json {
+        let n = db.import_json(&json_path)
+            .unwrap_or_else(|e| print_error_and_exit(e));
+        println!("imported {} feeds from '{}'", n, json_path);
+        return;
+    }
+
+    let token = &args[2];
+    let period = args.get(3)
+        .map(|s| parse_seconds(s, "period"))
+        .unwrap_or(300);
+    if min_interval > max_interval {
+        eprintln!("--min-interval must not be greater than --max-interval");
+        std::process::exit(1);
+    }
+    let intervals = scheduler::Intervals {
+        min: min_interval,
+        max: max_interval,
+        default: period,
+    };
 
     env_logger::init().unwrap();
 
@@ -71,18 +189,36 @@ fn main() {
         .expect("failed to initialize bot")
         .update_interval(200);
 
-    cmdhandles::register_commands(&bot, &db, lp.handle());
+    let limiter = ratelimit::RateLimiter::new();
+
+    cmdhandles::register_commands(&bot, &db, &limiter, lp.handle());
+
+    let websub = websub_url.map(|url| {
+        websub::serve(&lp.handle(), &websub_listen, db.clone())
+            .expect("failed to start WebSub server");
+        websub::WebSub::new(url, lp.handle())
+    });
+
+    fetcher::spawn_fetcher(bot.clone(), db.clone(), intervals, websub);
 
-    fetcher::spawn_fetcher(bot.clone(), db.clone(), period);
+    digest::spawn_digests(db.clone(), lp.handle());
+
+    sender::spawn_sender(bot.clone(), db.clone(), limiter);
 
     checker::spawn_subscriber_alive_checker(bot.clone(), db, lp.handle());
 
-    let s = bot.get_stream()
-        .map(|_| ())
-        .or_else(|e| {
-            error!("telebot: {:?}", e);
-            Ok::<(), ()>(())
-        })
-        .for_each(|_| Ok(()));
-    lp.run(s).unwrap();
+    if let (Some(url), Some(secret)) = (webhook, webhook_secret) {
+        let set_webhook = bot.set_webhook(url).secret_token(secret.clone());
+        lp.run(set_webhook.send()).expect("failed to set webhook");
+        let s = bot.get_webhook_stream(&listen, secret)
+            .expect("failed to start webhook server");
+        run_update_stream(&mut lp, s);
+    } else {
+        // getUpdates doesn't work while a webhook is set,
+        // but there may be none, or Telegram may be briefly unreachable
+        if let Err(e) = lp.run(bot.delete_webhook().send()) {
+            warn!("failed to delete webhook: {:?}", e);
+        }
+        run_update_stream(&mut lp, bot.get_stream());
+    }
 }
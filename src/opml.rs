@@ -1,15 +1,28 @@
 use std::io::Cursor;
-use std::io::Write;
+use std::io::{BufRead, Write};
 
 use chrono::Local;
 use quick_xml::events::attributes::Attribute;
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::reader::Reader;
 use quick_xml::writer::Writer;
 
-use data::Feed;
+use serde_json;
+
+use data::{Feed, SubscriberID};
 use errors::*;
+use filter::Filter;
+
+/// A feed in an OPML file
+#[derive(Debug, PartialEq)]
+pub struct Outline {
+    pub title: Option<String>,
+    pub link: String,
+    /// Exported by `to_opml`
+    pub filter: Option<Filter>,
+}
 
-pub fn to_opml(feeds: Vec<Feed>) -> String {
+pub fn to_opml(feeds: Vec<Feed>, subscriber: SubscriberID) -> String {
     let mut writer = Writer::new(Cursor::new(Vec::new()));
     let decl = BytesDecl::new(b"1.0", Some(b"UTF-8"), None);
     writer.write_event(Event::Decl(decl)).unwrap();
@@ -40,10 +53,20 @@ pub fn to_opml(feeds: Vec<Feed>) -> String {
             })?;
             with_tag(writer, b"body", &mut [], move |writer| {
                 for feed in feeds {
+                    let title = escape(&feed.title);
+                    let link = escape(&feed.link);
+                    let filter = feed.options
+                        .get(&subscriber)
+                        .map(|options| &options.filter)
+                        .filter(|filter| !filter.is_empty())
+                        .map(|filter| escape(&serde_json::to_string(filter).unwrap()));
                     let mut outline = BytesStart::borrowed(b"outline", 7);
                     outline.push_attribute(Attribute::from(("type", "rss")));
-                    outline.push_attribute(Attribute::from(("text", feed.title.as_str())));
-                    outline.push_attribute(Attribute::from(("xmlUrl", feed.link.as_str())));
+                    outline.push_attribute(Attribute::from(("text", title.as_str())));
+                    outline.push_attribute(Attribute::from(("xmlUrl", link.as_str())));
+                    if let Some(ref filter) = filter {
+                        outline.push_attribute(Attribute::from(("rssbotFilter", filter.as_str())));
+                    }
                     writer.write_event(Event::Empty(outline))?;
                 }
                 Ok(())
@@ -54,6 +77,75 @@ pub fn to_opml(feeds: Vec<Feed>) -> String {
     unsafe { String::from_utf8_unchecked(writer.into_inner().into_inner()) }
 }
 
+/// Collects every `<outline>` with a `xmlUrl`, however deep it's nested in folders
+pub fn from_opml<B: BufRead>(reader: B) -> Result<Vec<Outline>> {
+    let mut reader = Reader::from_reader(reader);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut outlines: Vec<Outline> = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+                if reader.decode(e.name()) == "outline" {
+                    if let Some(outline) = parse_outline(&reader, e)? {
+                        if !outlines.iter().any(|o| o.link == outline.link) {
+                            outlines.push(outline);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(err) => return Err(err.into()),
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(outlines)
+}
+
+fn parse_outline<B: BufRead>(reader: &Reader<B>, e: &BytesStart) -> Result<Option<Outline>> {
+    let mut text = None;
+    let mut title = None;
+    let mut link = None;
+    let mut filter = None;
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        match reader.decode(attribute.key).as_ref() {
+            "text" => text = Some(attribute.unescape_and_decode_value(reader)?),
+            "title" => title = Some(attribute.unescape_and_decode_value(reader)?),
+            "xmlUrl" => link = Some(attribute.unescape_and_decode_value(reader)?),
+            "rssbotFilter" => {
+                let value = attribute.unescape_and_decode_value(reader)?;
+                // a broken filter shouldn't stop the import
+                filter = serde_json::from_str(&value).ok();
+            }
+            _ => (),
+        }
+    }
+    Ok(link.filter(|link| !link.trim().is_empty())
+        .map(|link| Outline {
+            title: title.or(text).filter(|s| !s.is_empty()),
+            link: link.trim().to_owned(),
+            filter: filter,
+        }))
+}
+
+// `Attribute` takes raw bytes, so values must be escaped by hand
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
 // type of `attrs` is for zero allocation
 fn with_tag<'a, W, F>(
     writer: &mut Writer<W>,
@@ -82,9 +174,15 @@ fn test_to_opml() {
     feed1.title = "title1".into();
     feed1.link = "link1".into();
     let mut feed2 = Feed::default();
-    feed2.title = "title2".into();
-    feed2.link = "link2".into();
-    let feeds = vec![feed1, feed2];
+    feed2.title = "title2 & <title3>".into();
+    feed2.link = "link2?a=1&b=2".into();
+    let mut feed3 = Feed::default();
+    feed3.title = "title3".into();
+    feed3.link = "link3".into();
+    let mut options = ::data::Options::default();
+    options.filter.add_include("rust").unwrap();
+    feed3.options.insert(1, options);
+    let feeds = vec![feed1, feed2, feed3];
     let r = format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
          <opml version=\"2.0\">\
@@ -95,10 +193,70 @@ fn test_to_opml() {
          </head>\
          <body>\
          <outline type=\"rss\" text=\"title1\" xmlUrl=\"link1\"/>\
-         <outline type=\"rss\" text=\"title2\" xmlUrl=\"link2\"/>\
+         <outline type=\"rss\" text=\"title2 &amp; &lt;title3&gt;\" \
+         xmlUrl=\"link2?a=1&amp;b=2\"/>\
+         <outline type=\"rss\" text=\"title3\" xmlUrl=\"link3\" \
+         rssbotFilter=\"{{&quot;include&quot;:[&quot;rust&quot;],&quot;exclude&quot;:[]}}\"/>\
          </body>\
          </opml>",
         Local::now().format("%a, %d %b %Y %T %Z")
     );
-    assert_eq!(to_opml(feeds), r);
+    assert_eq!(to_opml(feeds, 1), r);
+}
+
+#[test]
+fn test_from_opml() {
+    let opml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="1.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Rust" title="Rust">
+      <outline type="rss" text="Rust Blog" xmlUrl="https://blog.rust-lang.org/feed.xml"/>
+      <outline text="Nested">
+        <outline type="rss" title="This Week" xmlUrl=" https://this-week-in-rust.org/rss.xml "/>
+      </outline>
+    </outline>
+    <outline type="rss" xmlUrl="http://example.com/?a=1&amp;b=2"></outline>
+    <outline type="rss" text="Duplicated" xmlUrl="https://blog.rust-lang.org/feed.xml"/>
+    <outline text="No link"/>
+  </body>
+</opml>"#;
+    let outlines = from_opml(Cursor::new(opml)).unwrap();
+    assert_eq!(
+        outlines,
+        vec![
+            Outline {
+                title: Some("Rust Blog".into()),
+                link: "https://blog.rust-lang.org/feed.xml".into(),
+                filter: None,
+            },
+            Outline {
+                title: Some("This Week".into()),
+                link: "https://this-week-in-rust.org/rss.xml".into(),
+                filter: None,
+            },
+            Outline {
+                title: None,
+                link: "http://example.com/?a=1&b=2".into(),
+                filter: None,
+            },
+        ]
+    );
+
+    // what's exported can be imported
+    let mut feed = Feed::default();
+    feed.title = "a & b".into();
+    feed.link = "link?a=1&b=2".into();
+    let mut options = ::data::Options::default();
+    options.filter.add_exclude("/^ad:/").unwrap();
+    feed.options.insert(1, options.clone());
+    let outlines = from_opml(Cursor::new(to_opml(vec![feed], 1))).unwrap();
+    assert_eq!(
+        outlines,
+        vec![Outline {
+            title: Some("a & b".into()),
+            link: "link?a=1&b=2".into(),
+            filter: Some(options.filter),
+        }]
+    );
 }
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// https://core.telegram.org/bots/faq#my-bot-is-hitting-limits-how-do-i-avoid-this
+/// 30 messages per second in total
+const GLOBAL_INTERVAL: Duration = Duration::from_millis(34);
+/// 1 message per second in a private chat
+const PRIVATE_INTERVAL: Duration = Duration::from_secs(1);
+/// 20 messages per minute in a group or channel
+const GROUP_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug)]
+struct Schedule {
+    /// Instants reserved for messages, to keep them `GLOBAL_INTERVAL` apart
+    slots: BTreeSet<Instant>,
+    next_chat: HashMap<i64, Instant>,
+}
+
+impl Schedule {
+    fn new() -> Schedule {
+        Schedule {
+            slots: BTreeSet::new(),
+            next_chat: HashMap::new(),
+        }
+    }
+
+    fn reserve(&mut self, chat: i64, now: Instant) -> Instant {
+        if self.next_chat.len() > 1000 {
+            self.next_chat.retain(|_, next| *next > now);
+        }
+        // older slots can't be too close to any new one
+        self.slots = self.slots.split_off(&(now - GLOBAL_INTERVAL));
+        let chat_at = self.next_chat.get(&chat).cloned().unwrap_or(now);
+        // the first free slot after the chat is ready, so a chat held back
+        // by its own limit doesn't hold back the others
+        let mut at = ::std::cmp::max(now, chat_at);
+        for &slot in &self.slots {
+            if slot >= at + GLOBAL_INTERVAL {
+                break;
+            }
+            if slot + GLOBAL_INTERVAL > at {
+                at = slot + GLOBAL_INTERVAL;
+            }
+        }
+        self.slots.insert(at);
+        let chat_interval = if chat < 0 {
+            GROUP_INTERVAL
+        } else {
+            PRIVATE_INTERVAL
+        };
+        self.next_chat.insert(chat, at + chat_interval);
+        at
+    }
+
+    fn retry_after(&mut self, chat: i64, now: Instant, secs: u64) {
+        let at = now + Duration::from_secs(secs);
+        let next = self.next_chat.entry(chat).or_insert(at);
+        if *next < at {
+            *next = at;
+        }
+    }
+}
+
+/// Spaces out the messages sent by the bot to stay under Telegram's flood limits
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    inner: Rc<RefCell<Schedule>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            inner: Rc::new(RefCell::new(Schedule::new())),
+        }
+    }
+
+    /// Takes a slot for a message to `chat`, returns when it can be sent
+    pub fn reserve(&self, chat: i64) -> Instant {
+        self.inner.borrow_mut().reserve(chat, Instant::now())
+    }
+
+    /// Holds back the messages to `chat` for `secs` seconds,
+    /// used when Telegram answers with 429 Too Many Requests
+    pub fn retry_after(&self, chat: i64, secs: u64) {
+        self.inner
+            .borrow_mut()
+            .retry_after(chat, Instant::now(), secs)
+    }
+}
+
+#[test]
+fn test_schedule() {
+    let now = Instant::now();
+    let mut schedule = Schedule::new();
+
+    assert_eq!(schedule.reserve(1, now), now);
+    assert_eq!(schedule.reserve(2, now), now + GLOBAL_INTERVAL);
+    assert_eq!(schedule.reserve(-1, now), now + GLOBAL_INTERVAL * 2);
+    assert_eq!(schedule.reserve(1, now), now + PRIVATE_INTERVAL);
+    assert_eq!(
+        schedule.reserve(-1, now),
+        now + GLOBAL_INTERVAL * 2 + GROUP_INTERVAL
+    );
+
+    let later = now + Duration::from_secs(10);
+    assert_eq!(schedule.reserve(1, later), later);
+
+    schedule.retry_after(2, later, 30);
+    assert_eq!(
+        schedule.reserve(2, later),
+        later + Duration::from_secs(30)
+    );
+    // other chats are not affected
+    assert_eq!(schedule.reserve(3, later), later + GLOBAL_INTERVAL);
+    // a shorter retry_after doesn't bring the chat forward,
+    // and chat 2 has taken the slot at that time
+    schedule.retry_after(1, later, 30);
+    schedule.retry_after(1, later, 5);
+    assert_eq!(
+        schedule.reserve(1, later),
+        later + Duration::from_secs(30) + GLOBAL_INTERVAL
+    );
+}
+
+#[test]
+fn test_schedule_global_interval() {
+    let now = Instant::now();
+    let mut schedule = Schedule::new();
+    let mut slots = Vec::new();
+    for chat in 1..50 {
+        slots.push(schedule.reserve(chat, now));
+    }
+    // all of them are ready again at the same time
+    for chat in 1..50 {
+        schedule.retry_after(chat, now, 1);
+    }
+    for chat in 1..50 {
+        slots.push(schedule.reserve(chat, now));
+    }
+    slots.sort();
+    for pair in slots.windows(2) {
+        assert!(pair[1] - pair[0] >= GLOBAL_INTERVAL);
+    }
+}
@@ -0,0 +1,331 @@
+//! When to fetch each feed
+//!
+//! Every feed gets its own interval, from how often its items come out and what the feed
+//! and its server say about it, kept between the bounds of `Intervals`.
+
+use std::cmp::{max, min, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Timelike, Utc};
+
+use data::FeedID;
+use feed::UpdateHints;
+
+/// Number of the latest items used to tell how often a feed updates
+const RECENT_ITEMS: usize = 10;
+
+/// Bounds of the intervals, in seconds
+#[derive(Debug, Clone, Copy)]
+pub struct Intervals {
+    pub min: u64,
+    pub max: u64,
+    /// For feeds nothing is known about yet
+    pub default: u64,
+}
+
+/// What a fetch of a feed tells about its next update
+#[derive(Debug, Default)]
+pub struct Observation {
+    /// Dates of the items, in any order
+    pub dates: Vec<DateTime<FixedOffset>>,
+    pub hints: UpdateHints,
+    /// Seconds the response is fresh for
+    pub max_age: Option<u64>,
+    /// Whether any item was new
+    pub changed: bool,
+}
+
+/// The average gap between the latest items, or the time since the last one if longer
+fn estimate_period(dates: &[DateTime<FixedOffset>], now: DateTime<Utc>) -> Option<u64> {
+    let mut dates: Vec<i64> = dates.iter().map(|date| date.timestamp()).collect();
+    dates.sort_by(|a, b| b.cmp(a));
+    dates.dedup();
+    dates.truncate(RECENT_ITEMS);
+    if dates.len() < 2 {
+        return None;
+    }
+    let average = (dates[0] - dates[dates.len() - 1]) / (dates.len() as i64 - 1);
+    let quiet = now.timestamp() - dates[0];
+    Some(max(max(average, quiet), 0) as u64)
+}
+
+/// Seconds from `now` to the next fetch of a feed, `previous` is its last interval
+pub fn next_interval(
+    observation: &Observation,
+    previous: Option<u64>,
+    intervals: &Intervals,
+    now: DateTime<Utc>,
+) -> u64 {
+    let interval = match estimate_period(&observation.dates, now) {
+        // an update waits a quarter of the period on average
+        Some(period) => period / 2,
+        // without dates, back off while nothing changes and come closer when it does
+        None => match previous {
+            Some(previous) if observation.changed => previous * 2 / 3,
+            Some(previous) => previous * 3 / 2,
+            None => intervals.default,
+        },
+    };
+    // what the feed and its server ask for are the least
+    let hints = &observation.hints;
+    let least = [
+        hints.ttl.map(|ttl| ttl * 60),
+        hints.update_period,
+        observation.max_age,
+    ].iter()
+        .filter_map(|&seconds| seconds)
+        .max()
+        .unwrap_or(0);
+    let interval = min(max(max(interval, least), intervals.min), intervals.max);
+    skip(hints, now, interval, intervals.max)
+}
+
+/// Seconds to wait after the `failures`th failure in a row of a feed fetched every `interval`
+/// seconds, doubled for each one up to `intervals.max`, with `random` taking off up to half
+/// so the failing feeds of a host don't come back all at once
+pub fn backoff(interval: u64, failures: u32, intervals: &Intervals, random: u64) -> u64 {
+    let exponent = min(failures.saturating_sub(1), 16);
+    let delay = min(interval.saturating_mul(1 << exponent), intervals.max);
+    let delay = delay / 2 + random % (delay - delay / 2 + 1);
+    max(delay, intervals.min)
+}
+
+/// Moves the fetch `interval` seconds after `now` out of the skipped hours and days,
+/// but not further than `limit` seconds
+fn skip(hints: &UpdateHints, now: DateTime<Utc>, interval: u64, limit: u64) -> u64 {
+    let skipped = |time: DateTime<Utc>| {
+        hints.skip_hours.contains(&time.hour()) || hints.skip_days.contains(&time.weekday())
+    };
+    let mut time = now + Duration::seconds(interval as i64);
+    // a week at most, in case every hour is skipped
+    for _ in 0..7 * 24 {
+        if !skipped(time) {
+            break;
+        }
+        let hour = time.with_minute(0)
+            .and_then(|time| time.with_second(0))
+            .and_then(|time| time.with_nanosecond(0))
+            .unwrap();
+        time = hour + Duration::hours(1);
+    }
+    if skipped(time) {
+        return interval;
+    }
+    min(time.signed_duration_since(now).num_seconds() as u64, limit)
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Unix time of the next fetch, `None` while it's being fetched
+    at: Option<i64>,
+    interval: Option<u64>,
+}
+
+/// A priority queue of feeds by the time of their next fetch
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(i64, FeedID)>>,
+    entries: HashMap<FeedID, Entry>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Adds the new ones of `feeds` and forgets the ones not in `feeds`, which are
+    /// `(feed, last fetch, interval)`. A new one is due an interval after its last fetch,
+    /// so a restart doesn't fetch every feed at once, or at `now` if that's unknown or past
+    pub fn sync(&mut self, feeds: &[(FeedID, Option<i64>, Option<u64>)], now: i64) {
+        let ids: HashSet<FeedID> = feeds.iter().map(|&(feed_id, _, _)| feed_id).collect();
+        self.entries.retain(|feed_id, _| ids.contains(feed_id));
+        for &(feed_id, last_fetch, interval) in feeds {
+            if !self.entries.contains_key(&feed_id) {
+                let at = match (last_fetch, interval) {
+                    (Some(last_fetch), Some(interval)) => max(last_fetch + interval as i64, now),
+                    _ => now,
+                };
+                self.entries.insert(
+                    feed_id,
+                    Entry {
+                        at: Some(at),
+                        interval: interval,
+                    },
+                );
+                self.queue.push(Reverse((at, feed_id)));
+            }
+        }
+    }
+
+    /// Takes out the feeds due at `now`, they are back after `schedule`
+    pub fn pop_due(&mut self, now: i64) -> Vec<FeedID> {
+        let mut due = Vec::new();
+        while self.queue.peek().map_or(false, |&Reverse((at, _))| at <= now) {
+            let Reverse((at, feed_id)) = self.queue.pop().unwrap();
+            // outdated if the feed was forgotten or re-added since
+            if let Some(entry) = self.entries.get_mut(&feed_id) {
+                if entry.at == Some(at) {
+                    entry.at = None;
+                    due.push(feed_id);
+                }
+            }
+        }
+        due
+    }
+
+    /// The last interval of `feed_id`
+    pub fn interval(&self, feed_id: FeedID) -> Option<u64> {
+        self.entries.get(&feed_id).and_then(|entry| entry.interval)
+    }
+
+    /// Puts a fetched feed back, due `interval` seconds after `now`
+    pub fn schedule(&mut self, feed_id: FeedID, now: i64, interval: u64) {
+        self.push(feed_id, now, interval, true);
+    }
+
+    /// Puts a feed that failed back, due `delay` seconds after `now`, keeping its interval
+    pub fn retry(&mut self, feed_id: FeedID, now: i64, delay: u64) {
+        self.push(feed_id, now, delay, false);
+    }
+
+    fn push(&mut self, feed_id: FeedID, now: i64, seconds: u64, is_interval: bool) {
+        if let Some(entry) = self.entries.get_mut(&feed_id) {
+            let at = now + seconds as i64;
+            entry.at = Some(at);
+            if is_interval {
+                entry.interval = Some(seconds);
+            }
+            self.queue.push(Reverse((at, feed_id)));
+        }
+    }
+}
+
+#[test]
+fn test_next_interval() {
+    use chrono::{TimeZone, Weekday};
+
+    let intervals = Intervals {
+        min: 60,
+        max: 24 * 60 * 60,
+        default: 300,
+    };
+    // Monday
+    let now = Utc.ymd(2006, 1, 2).and_hms(12, 0, 0);
+    let hours_ago =
+        |hours: i64| (now - Duration::hours(hours)).with_timezone(&FixedOffset::east(0));
+    let mut observation = Observation::default();
+    let next = |observation: &Observation, previous| {
+        next_interval(observation, previous, &intervals, now)
+    };
+
+    assert_eq!(next(&observation, None), 300);
+    assert_eq!(next(&observation, Some(600)), 900);
+    observation.changed = true;
+    assert_eq!(next(&observation, Some(600)), 400);
+    assert_eq!(next(&observation, Some(60)), 60);
+
+    // every 2 hours
+    observation.dates = vec![hours_ago(4), hours_ago(0), hours_ago(2), hours_ago(2)];
+    assert_eq!(next(&observation, Some(600)), 60 * 60);
+    // quiet for a day
+    observation.dates = vec![hours_ago(26), hours_ago(24)];
+    assert_eq!(next(&observation, None), 12 * 60 * 60);
+    observation.dates = vec![hours_ago(1000), hours_ago(500)];
+    assert_eq!(next(&observation, None), 24 * 60 * 60);
+
+    observation.dates = vec![hours_ago(1), hours_ago(0)];
+    assert_eq!(next(&observation, None), 30 * 60);
+    observation.hints.ttl = Some(60);
+    assert_eq!(next(&observation, None), 60 * 60);
+    observation.hints.update_period = Some(2 * 60 * 60);
+    assert_eq!(next(&observation, None), 2 * 60 * 60);
+    observation.max_age = Some(3 * 60 * 60);
+    assert_eq!(next(&observation, None), 3 * 60 * 60);
+
+    // 15:00 to 17:59, then Tuesday
+    observation.hints.skip_hours = vec![15, 16, 17];
+    assert_eq!(next(&observation, None), 6 * 60 * 60);
+    observation.hints.skip_days = vec![Weekday::Mon];
+    assert_eq!(next(&observation, None), 12 * 60 * 60);
+    observation.hints.skip_hours = (0..24).collect();
+    assert_eq!(next(&observation, None), 3 * 60 * 60);
+}
+
+#[test]
+fn test_backoff() {
+    let intervals = Intervals {
+        min: 60,
+        max: 24 * 60 * 60,
+        default: 300,
+    };
+    let lowest = |failures| backoff(600, failures, &intervals, 0);
+    let highest = |failures| backoff(600, failures, &intervals, u64::max_value());
+    assert_eq!(lowest(0), 300);
+    assert_eq!(lowest(1), 300);
+    assert_eq!(lowest(2), 600);
+    assert_eq!(lowest(3), 1200);
+    assert_eq!(lowest(100), 12 * 60 * 60);
+    for failures in 0..100 {
+        assert!(highest(failures) <= 24 * 60 * 60);
+    }
+    assert_eq!(backoff(600, 2, &intervals, 600), 1200);
+    assert_eq!(backoff(600, 2, &intervals, 601), 600);
+    assert_eq!(backoff(60, 1, &intervals, 0), 60);
+    assert_eq!(backoff(u64::max_value(), 1000, &intervals, 0), 12 * 60 * 60);
+}
+
+#[test]
+fn test_scheduler() {
+    let feeds = |ids: &[FeedID]| -> Vec<(FeedID, Option<i64>, Option<u64>)> {
+        ids.iter().map(|&feed_id| (feed_id, None, None)).collect()
+    };
+    let mut scheduler = Scheduler::new();
+    scheduler.sync(&feeds(&[1, 2]), 100);
+    assert!(scheduler.pop_due(99).is_empty());
+    let mut due = scheduler.pop_due(100);
+    due.sort();
+    assert_eq!(due, vec![1, 2]);
+    // being fetched
+    scheduler.sync(&feeds(&[1, 2]), 100);
+    assert!(scheduler.pop_due(1000).is_empty());
+
+    scheduler.schedule(1, 100, 60);
+    scheduler.schedule(2, 100, 30);
+    assert_eq!(scheduler.interval(1), Some(60));
+    assert_eq!(scheduler.pop_due(1000), vec![2, 1]);
+
+    scheduler.schedule(1, 100, 60);
+    scheduler.schedule(2, 100, 30);
+    scheduler.sync(&feeds(&[2, 3]), 200);
+    assert_eq!(scheduler.interval(1), None);
+    scheduler.schedule(1, 100, 60);
+    assert_eq!(scheduler.pop_due(200), vec![2, 3]);
+    // forgotten and added again, the old time is outdated
+    scheduler.schedule(2, 200, 30);
+    scheduler.sync(&[], 200);
+    scheduler.sync(&feeds(&[2]), 300);
+    assert_eq!(scheduler.pop_due(1000), vec![2]);
+
+    scheduler.schedule(2, 1000, 30);
+    assert_eq!(scheduler.pop_due(1030), vec![2]);
+    scheduler.retry(2, 1030, 120);
+    assert_eq!(scheduler.interval(2), Some(30));
+    assert!(scheduler.pop_due(1149).is_empty());
+    assert_eq!(scheduler.pop_due(1150), vec![2]);
+
+    // known from the database, due after the interval or right away if that's past
+    let mut scheduler = Scheduler::new();
+    let feeds = [
+        (1, Some(100), Some(600)),
+        (2, Some(100), Some(60)),
+        (3, None, Some(60)),
+    ];
+    scheduler.sync(&feeds, 400);
+    assert_eq!(scheduler.interval(1), Some(600));
+    assert_eq!(scheduler.interval(3), Some(60));
+    let mut due = scheduler.pop_due(400);
+    due.sort();
+    assert_eq!(due, vec![2, 3]);
+    assert!(scheduler.pop_due(699).is_empty());
+    assert_eq!(scheduler.pop_due(700), vec![1]);
+}
@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use telebot;
+use telebot::objects::ResponseParameters;
+use tokio_core::reactor::Interval;
+
+use data::{Database, OutboxEntry, SubscriberID};
+use ratelimit::RateLimiter;
+use utlis::{chat_is_unavailable, send_attachment, send_message};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default)]
+struct SenderState {
+    /// Chats being delivered to, each chat is handled by only one task to keep the order
+    sending: HashSet<SubscriberID>,
+    /// Failed deliveries in a row, and when to try again
+    failures: HashMap<SubscriberID, (u32, Instant)>,
+}
+
+fn backoff(failures: u32) -> Duration {
+    min(Duration::from_secs(1 << min(failures, 12)), MAX_BACKOFF)
+}
+
+/// Drains the outbox of the database, messages are removed only after Telegram accepts them
+pub fn spawn_sender(bot: telebot::RcBot, db: Database, limiter: RateLimiter) {
+    let handle = bot.inner.handle.clone();
+    let handle2 = handle.clone();
+    let state = Rc::new(RefCell::new(SenderState::default()));
+    let lop = Interval::new(Duration::from_secs(1), &handle)
+        .expect("failed to start sender loop")
+        .for_each(move |_| {
+            let now = Instant::now();
+            for chat in db.get_outbox_chats() {
+                {
+                    let mut state = state.borrow_mut();
+                    let waiting = state
+                        .failures
+                        .get(&chat)
+                        .map(|&(_, retry_at)| retry_at > now)
+                        .unwrap_or(false);
+                    if waiting || !state.sending.insert(chat) {
+                        continue;
+                    }
+                }
+                handle.spawn(send_outbox(
+                    bot.clone(),
+                    db.clone(),
+                    limiter.clone(),
+                    state.clone(),
+                    chat,
+                ));
+            }
+            Ok(())
+        })
+        .map_err(|e| error!("sender loop: {}", e));
+    handle2.spawn(lop);
+}
+
+/// Sends an entry, as text if Telegram refuses its media
+#[async]
+fn deliver(
+    bot: telebot::RcBot,
+    limiter: RateLimiter,
+    entry: OutboxEntry,
+) -> Result<(), telebot::Error> {
+    if let Some(attachment) = entry.attachment.clone() {
+        let r = send_attachment(&bot, &limiter, entry.chat, entry.text.clone(), attachment);
+        match await!(r) {
+            Err(telebot::Error::Telegram(400, ref s, _)) => {
+                // too big, or the URL can't be fetched by Telegram
+                warn!("failed to send media to {}: {}", entry.chat, s);
+            }
+            r => return r,
+        }
+    }
+    await!(send_message(&bot, &limiter, entry.chat, entry.text))
+}
+
+#[async]
+fn send_outbox(
+    bot: telebot::RcBot,
+    db: Database,
+    limiter: RateLimiter,
+    state: Rc<RefCell<SenderState>>,
+    chat: SubscriberID,
+) -> Result<(), ()> {
+    // messages queued meanwhile are picked up in the next round
+    let mut failed = false;
+    // removed together after the pass, each removal is a save of the database,
+    // a crash in between only sends them again
+    let mut done = Vec::new();
+    for entry in db.get_outbox(chat) {
+        match await!(deliver(bot.clone(), limiter.clone(), entry.clone())) {
+            Ok(()) => done.push(entry.id),
+            Err(telebot::Error::Telegram(_, ref s, None)) if chat_is_unavailable(s) => {
+                // also drops its messages
+                db.delete_subscriber(chat);
+                done.clear();
+                break;
+            }
+            Err(telebot::Error::Telegram(
+                _,
+                _,
+                Some(ResponseParameters {
+                    migrate_to_chat_id: Some(new_id),
+                    ..
+                }),
+            )) => {
+                // the rest of the messages are moved to the new chat
+                db.remove_outbox(&done);
+                db.update_subscriber(chat, new_id);
+                done.clear();
+                break;
+            }
+            Err(telebot::Error::Telegram(400, ref s, _)) => {
+                // it will never be accepted
+                warn!("dropped message to {}: {}, {:?}", chat, s, entry.text);
+                done.push(entry.id);
+            }
+            Err(e) => {
+                warn!("failed to send message to {}, {:?}", chat, e);
+                failed = true;
+                break;
+            }
+        }
+    }
+    db.remove_outbox(&done);
+
+    let mut state = state.borrow_mut();
+    state.sending.remove(&chat);
+    if failed {
+        let failures = state.failures.get(&chat).map(|&(n, _)| n).unwrap_or(0) + 1;
+        let retry_at = Instant::now() + backoff(failures);
+        state.failures.insert(chat, (failures, retry_at));
+    } else {
+        state.failures.remove(&chat);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(1), Duration::from_secs(2));
+    assert_eq!(backoff(5), Duration::from_secs(32));
+    assert_eq!(backoff(12), MAX_BACKOFF);
+    assert_eq!(backoff(100), MAX_BACKOFF);
+}
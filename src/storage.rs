@@ -0,0 +1,547 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use rusqlite::Connection;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use data::{get_hash, ChatSettings, Feed, FeedID, OutboxEntry, SubscriberID};
+use errors::*;
+
+/// Everything that is persisted
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub feeds: Vec<Feed>,
+    pub outbox: Vec<OutboxEntry>,
+    pub chats: HashMap<SubscriberID, ChatSettings>,
+}
+
+/// The whole database after a change
+#[derive(Debug, Clone, Copy)]
+pub struct State<'a> {
+    pub feeds: &'a HashMap<FeedID, Feed>,
+    pub outbox: &'a BTreeMap<u64, OutboxEntry>,
+    pub chats: &'a HashMap<SubscriberID, ChatSettings>,
+}
+
+/// Something that was updated or removed, by feed link, outbox entry id or chat id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change<'a> {
+    Feed(&'a str),
+    Outbox(u64),
+    Chat(SubscriberID),
+}
+
+/// Where the database is persisted
+pub trait Storage: Debug {
+    fn load(&mut self) -> Result<Snapshot>;
+
+    /// All changes are saved at once, or none of them
+    fn save(&mut self, state: State, changes: &[Change]) -> Result<()>;
+
+    /// Whether a save writes only the changed rows,
+    /// so that it's cheap enough to save after every fetch
+    fn is_incremental(&self) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+impl Backend {
+    pub fn from_path(path: &str) -> Backend {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("sqlite") | Some("sqlite3") | Some("db") => Backend::Sqlite,
+            _ => Backend::Json,
+        }
+    }
+
+    pub fn open(self, path: &str) -> Result<Box<Storage>> {
+        Ok(match self {
+            Backend::Json => Box::new(JsonStorage::new(path)),
+            Backend::Sqlite => Box::new(SqliteStorage::open(path)?),
+        })
+    }
+}
+
+const BACKUP_COUNT: usize = 5;
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The whole database in a single JSON file, rewritten on every save
+///
+/// Saves go to a temporary file first, which is then renamed over the original,
+/// so a crash never leaves a truncated database behind.
+/// Up to `BACKUP_COUNT` timestamped copies are kept next to it,
+/// at most one every `BACKUP_INTERVAL`.
+#[derive(Debug)]
+pub struct JsonStorage {
+    path: String,
+    last_backup: Option<Instant>,
+}
+
+impl JsonStorage {
+    pub fn new(path: &str) -> JsonStorage {
+        JsonStorage {
+            path: path.to_owned(),
+            last_backup: None,
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        match Path::new(&self.path).parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    fn backup_prefix(&self) -> String {
+        let file_name = Path::new(&self.path)
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        format!("{}.", file_name)
+    }
+
+    /// Backups of this database, newest first
+    fn backups(&self) -> Vec<PathBuf> {
+        let prefix = self.backup_prefix();
+        let mut backups: Vec<PathBuf> = fs::read_dir(self.dir())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|s| s.to_str())
+                            .map(|s| s.starts_with(&prefix) && s.ends_with(".bak"))
+                            .unwrap_or(false)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // timestamps are zero-padded, so it's ordered by time
+        backups.sort();
+        backups.reverse();
+        backups
+    }
+
+    fn backup(&mut self) -> Result<()> {
+        if self.last_backup
+            .map(|t| t.elapsed() < BACKUP_INTERVAL)
+            .unwrap_or(false) || !Path::new(&self.path).exists()
+        {
+            return Ok(());
+        }
+        let backup = format!("{}.{}.bak", self.path, Local::now().format("%Y%m%d%H%M%S"));
+        fs::copy(&self.path, &backup).chain_err(|| ErrorKind::DatabaseSave(backup.clone()))?;
+        self.last_backup = Some(Instant::now());
+        for old in self.backups().into_iter().skip(BACKUP_COUNT) {
+            if let Err(e) = fs::remove_file(&old) {
+                warn!("failed to remove old backup {:?}: {}", old, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonDatabase {
+    /// The format before the outbox, just a list of feeds
+    Feeds(Vec<Feed>),
+    Full {
+        feeds: Vec<Feed>,
+        #[serde(default)]
+        outbox: Vec<OutboxEntry>,
+        #[serde(default)]
+        chats: HashMap<SubscriberID, ChatSettings>,
+    },
+}
+
+#[derive(Serialize)]
+struct JsonDatabaseRef<'a> {
+    feeds: Vec<&'a Feed>,
+    outbox: Vec<&'a OutboxEntry>,
+    chats: &'a HashMap<SubscriberID, ChatSettings>,
+}
+
+fn read_json_database<P: AsRef<Path>>(path: P) -> Result<Snapshot> {
+    let path = path.as_ref();
+    let f =
+        File::open(path).chain_err(|| ErrorKind::DatabaseOpen(path.to_string_lossy().into()))?;
+    let database = serde_json::from_reader(&f).chain_err(|| ErrorKind::DatabaseFormat)?;
+    Ok(match database {
+        JsonDatabase::Feeds(feeds) => Snapshot {
+            feeds: feeds,
+            ..Snapshot::default()
+        },
+        JsonDatabase::Full {
+            feeds,
+            outbox,
+            chats,
+        } => Snapshot {
+            feeds: feeds,
+            outbox: outbox,
+            chats: chats,
+        },
+    })
+}
+
+impl Storage for JsonStorage {
+    fn load(&mut self) -> Result<Snapshot> {
+        if !Path::new(&self.path).exists() {
+            return Ok(Snapshot::default());
+        }
+        match read_json_database(&self.path) {
+            Err(Error(ErrorKind::DatabaseFormat, state)) => {
+                for backup in self.backups() {
+                    if let Ok(snapshot) = read_json_database(&backup) {
+                        warn!("'{}' is broken, loaded backup {:?}", self.path, backup);
+                        return Ok(snapshot);
+                    }
+                }
+                Err(Error(ErrorKind::DatabaseFormat, state))
+            }
+            r => r,
+        }
+    }
+
+    fn save(&mut self, state: State, _changes: &[Change]) -> Result<()> {
+        let database = JsonDatabaseRef {
+            feeds: state.feeds.iter().map(|(_id, feed)| feed).collect(),
+            outbox: state.outbox.iter().map(|(_id, entry)| entry).collect(),
+            chats: state.chats,
+        };
+        let tmp_path = format!("{}.tmp", self.path);
+        {
+            let file =
+                File::create(&tmp_path).chain_err(|| ErrorKind::DatabaseSave(tmp_path.clone()))?;
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &database)
+                .chain_err(|| ErrorKind::DatabaseSave(tmp_path.clone()))?;
+            writer
+                .flush()
+                .chain_err(|| ErrorKind::DatabaseSave(tmp_path.clone()))?;
+            writer
+                .get_ref()
+                .sync_all()
+                .chain_err(|| ErrorKind::DatabaseSave(tmp_path.clone()))?;
+        }
+        if let Err(e) = self.backup() {
+            // not fatal, the database itself is still fine
+            ::utlis::log_error(&e);
+        }
+        fs::rename(&tmp_path, &self.path)
+            .chain_err(|| ErrorKind::DatabaseSave(self.path.to_owned()))?;
+        // persist the rename itself
+        if let Ok(dir) = File::open(self.dir()) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+
+    fn is_incremental(&self) -> bool {
+        false
+    }
+}
+
+/// One row per feed, outbox entry and chat, only changed rows are written
+///
+/// Rows are stored in the same JSON representation as `JsonStorage`,
+/// so new fields don't need a schema migration
+#[derive(Debug)]
+pub struct SqliteStorage {
+    path: String,
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<SqliteStorage> {
+        let conn = Connection::open(path).chain_err(|| ErrorKind::DatabaseOpen(path.to_owned()))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS feeds (
+                 link TEXT PRIMARY KEY NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS outbox (
+                 id INTEGER PRIMARY KEY NOT NULL,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS chats (
+                 id INTEGER PRIMARY KEY NOT NULL,
+                 data TEXT NOT NULL
+             );",
+        ).chain_err(|| ErrorKind::DatabaseOpen(path.to_owned()))?;
+        Ok(SqliteStorage {
+            path: path.to_owned(),
+            conn: conn,
+        })
+    }
+}
+
+impl SqliteStorage {
+    /// `sql` selects the id and the data of rows
+    fn load_table<T: DeserializeOwned>(&self, sql: &str) -> Result<Vec<(i64, T)>> {
+        let mut stmt = self.conn
+            .prepare(sql)
+            .chain_err(|| ErrorKind::DatabaseOpen(self.path.to_owned()))?;
+        let rows = stmt.query_map(&[], |row| (row.get::<_, i64>(0), row.get::<_, String>(1)))
+            .chain_err(|| ErrorKind::DatabaseOpen(self.path.to_owned()))?;
+        let mut result = Vec::new();
+        for row in rows {
+            let (id, data) = row.chain_err(|| ErrorKind::DatabaseOpen(self.path.to_owned()))?;
+            let value = serde_json::from_str(&data).chain_err(|| ErrorKind::DatabaseFormat)?;
+            result.push((id, value));
+        }
+        Ok(result)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Snapshot> {
+        let feeds = self.load_table("SELECT rowid, data FROM feeds")?;
+        let outbox = self.load_table("SELECT id, data FROM outbox ORDER BY id")?;
+        let chats = self.load_table("SELECT id, data FROM chats")?;
+        Ok(Snapshot {
+            feeds: feeds.into_iter().map(|(_, feed)| feed).collect(),
+            outbox: outbox.into_iter().map(|(_, entry)| entry).collect(),
+            chats: chats.into_iter().collect(),
+        })
+    }
+
+    fn save(&mut self, state: State, changes: &[Change]) -> Result<()> {
+        let path = self.path.to_owned();
+        let tx = self.conn
+            .transaction()
+            .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+        for change in changes {
+            match *change {
+                Change::Feed(link) => match state.feeds.get(&get_hash(link)) {
+                    Some(feed) => {
+                        let data = serde_json::to_string(feed)
+                            .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                        tx.execute(
+                            "INSERT OR REPLACE INTO feeds (link, data) VALUES (?1, ?2)",
+                            &[&link, &data],
+                        ).chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                    }
+                    None => {
+                        tx.execute("DELETE FROM feeds WHERE link = ?1", &[&link])
+                            .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                    }
+                },
+                Change::Outbox(id) => {
+                    // SQLite integers are signed
+                    let row_id = id as i64;
+                    match state.outbox.get(&id) {
+                        Some(entry) => {
+                            let data = serde_json::to_string(entry)
+                                .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                            tx.execute(
+                                "INSERT OR REPLACE INTO outbox (id, data) VALUES (?1, ?2)",
+                                &[&row_id, &data],
+                            ).chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                        }
+                        None => {
+                            tx.execute("DELETE FROM outbox WHERE id = ?1", &[&row_id])
+                                .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                        }
+                    }
+                }
+                Change::Chat(id) => match state.chats.get(&id) {
+                    Some(settings) => {
+                        let data = serde_json::to_string(settings)
+                            .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                        tx.execute(
+                            "INSERT OR REPLACE INTO chats (id, data) VALUES (?1, ?2)",
+                            &[&id, &data],
+                        ).chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                    }
+                    None => {
+                        tx.execute("DELETE FROM chats WHERE id = ?1", &[&id])
+                            .chain_err(|| ErrorKind::DatabaseSave(path.clone()))?;
+                    }
+                },
+            }
+        }
+        tx.commit().chain_err(|| ErrorKind::DatabaseSave(path))
+    }
+
+    fn is_incremental(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_backend_from_path() {
+    assert_eq!(Backend::from_path("rssbot.json"), Backend::Json);
+    assert_eq!(Backend::from_path("rssbot"), Backend::Json);
+    assert_eq!(Backend::from_path("rssbot.sqlite"), Backend::Sqlite);
+    assert_eq!(Backend::from_path("/var/lib/rssbot.DB"), Backend::Sqlite);
+}
+
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = ::std::env::temp_dir().join(format!(
+        "rssbot-test-{}-{}",
+        name,
+        ::std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_json_storage_save() {
+    let dir = temp_dir("json-save");
+    let path = dir.join("db.json").to_string_lossy().into_owned();
+    let mut storage = JsonStorage::new(&path);
+    assert!(storage.load().unwrap().feeds.is_empty());
+
+    let mut feeds = HashMap::new();
+    let mut feed = Feed::default();
+    feed.link = "link1".into();
+    feeds.insert(get_hash("link1"), feed);
+    let mut outbox = BTreeMap::new();
+    outbox.insert(
+        1,
+        OutboxEntry {
+            id: 1,
+            chat: 1,
+            text: "text".into(),
+            attachment: None,
+        },
+    );
+    let state = State {
+        feeds: &feeds,
+        outbox: &outbox,
+        chats: &HashMap::new(),
+    };
+    storage.save(state, &[Change::Feed("link1")]).unwrap();
+    // first save has nothing to back up
+    assert!(storage.backups().is_empty());
+    storage.save(state, &[Change::Feed("link1")]).unwrap();
+    assert_eq!(storage.backups().len(), 1);
+    // within `BACKUP_INTERVAL`
+    storage.save(state, &[Change::Feed("link1")]).unwrap();
+    assert_eq!(storage.backups().len(), 1);
+
+    assert!(!Path::new(&format!("{}.tmp", path)).exists());
+    let snapshot = JsonStorage::new(&path).load().unwrap();
+    assert_eq!(snapshot.feeds[0].link, "link1");
+    assert_eq!(snapshot.outbox, vec![outbox[&1].clone()]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_json_storage_fallback() {
+    let dir = temp_dir("json-fallback");
+    let path = dir.join("db.json").to_string_lossy().into_owned();
+    fs::write(format!("{}.20000101000000.bak", path), r#"[{"link":"old","title":"",
+        "error_count":0,"subscribers":[],"hash_list":[]}]"#).unwrap();
+    fs::write(format!("{}.20010101000000.bak", path), r#"{"feeds":[{"link":"new","title":"",
+        "error_count":0,"subscribers":[],"hash_list":[]}]}"#).unwrap();
+    fs::write(format!("{}.20020101000000.bak", path), "[{").unwrap();
+    fs::write(&path, "[{\"link\":").unwrap();
+
+    let snapshot = JsonStorage::new(&path).load().unwrap();
+    assert_eq!(snapshot.feeds.len(), 1);
+    assert_eq!(snapshot.feeds[0].link, "new");
+    assert!(snapshot.outbox.is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_sqlite_storage() {
+    use i18n::Lang;
+
+    let mut storage = SqliteStorage::open(":memory:").unwrap();
+    assert!(storage.load().unwrap().feeds.is_empty());
+
+    let mut feeds = HashMap::new();
+    for link in &["link1", "link2"] {
+        let mut feed = Feed::default();
+        feed.link = link.to_string();
+        feed.subscribers.insert(1);
+        feeds.insert(get_hash(link), feed);
+    }
+    let mut outbox = BTreeMap::new();
+    for id in 1..4 {
+        outbox.insert(
+            id,
+            OutboxEntry {
+                id: id,
+                chat: 1,
+                text: format!("text{}", id),
+                attachment: None,
+            },
+        );
+    }
+    let mut chats = HashMap::new();
+    chats.insert(
+        -1,
+        ChatSettings {
+            lang: Some(Lang::En),
+            ..Default::default()
+        },
+    );
+    {
+        let state = State {
+            feeds: &feeds,
+            outbox: &outbox,
+            chats: &chats,
+        };
+        let changes = [
+            Change::Feed("link1"),
+            Change::Feed("link2"),
+            Change::Outbox(1),
+            Change::Outbox(2),
+            Change::Outbox(3),
+            Change::Chat(-1),
+        ];
+        storage.save(state, &changes).unwrap();
+    }
+    let loaded = storage.load().unwrap();
+    assert_eq!(loaded.feeds.len(), 2);
+    assert_eq!(loaded.outbox.len(), 3);
+    assert_eq!(loaded.chats[&-1].lang, Some(Lang::En));
+
+    feeds.remove(&get_hash("link1"));
+    feeds.get_mut(&get_hash("link2")).unwrap().title = "title2".into();
+    outbox.remove(&2);
+    chats.remove(&-1);
+    {
+        let state = State {
+            feeds: &feeds,
+            outbox: &outbox,
+            chats: &chats,
+        };
+        let changes = [
+            Change::Feed("link1"),
+            Change::Feed("link2"),
+            Change::Outbox(2),
+            Change::Chat(-1),
+        ];
+        storage.save(state, &changes).unwrap();
+    }
+    let loaded = storage.load().unwrap();
+    assert_eq!(loaded.feeds.len(), 1);
+    assert_eq!(loaded.feeds[0].link, "link2");
+    assert_eq!(loaded.feeds[0].title, "title2");
+    let ids: Vec<u64> = loaded.outbox.iter().map(|entry| entry.id).collect();
+    assert_eq!(ids, vec![1, 3]);
+    assert!(loaded.chats.is_empty());
+}
@@ -1,8 +1,16 @@
-use futures::{self, Future, Stream};
+use futures;
+use futures::prelude::*;
 use telebot;
 use telebot::functions::*;
+use telebot::objects::ResponseParameters;
+use tokio_core::reactor::Timeout;
 
+use chrono::{TimeZone, Utc};
+
+use data::{Attachment, FeedError, FeedErrorKind};
 use errors;
+use i18n::{fill, Lang};
+use ratelimit::RateLimiter;
 
 pub const TELEGRAM_MAX_MSG_LEN: usize = 4096;
 
@@ -71,21 +79,114 @@ impl<'a> ::std::fmt::Display for EscapeUrl<'a> {
     }
 }
 
-pub fn send_multiple_messages<'a>(
+/// Calls `send` once the rate limiter allows it,
+/// waits and calls it again if Telegram answers with `retry_after`
+fn send_with_limit<'a, F>(
     bot: &telebot::RcBot,
+    limiter: &RateLimiter,
     target: i64,
-    messages: Vec<String>,
-) -> impl Future<Item = (), Error = telebot::Error> + 'a {
+    send: F,
+) -> impl Future<Item = (), Error = telebot::Error> + 'a
+where
+    F: Fn(&telebot::RcBot) -> Box<Future<Item = (), Error = telebot::Error>> + 'a,
+{
     let bot = bot.clone();
-    futures::stream::iter_ok(messages).for_each(move |msg| {
-        bot.message(target, msg)
+    let limiter = limiter.clone();
+    async_block! {
+        loop {
+            let at = limiter.reserve(target);
+            let delay = Timeout::new_at(at, &bot.inner.handle)
+                .map_err(|_| telebot::Error::Unknown)?;
+            await!(delay).map_err(|_| telebot::Error::Unknown)?;
+            match await!(send(&bot)) {
+                Err(telebot::Error::Telegram(
+                    _,
+                    _,
+                    Some(ResponseParameters {
+                        retry_after: Some(secs),
+                        ..
+                    }),
+                )) => {
+                    warn!("flood control: retry sending to {} after {}s", target, secs);
+                    limiter.retry_after(target, secs as u64);
+                }
+                r => return r,
+            }
+        }
+    }
+}
+
+/// Sends a message once the rate limiter allows it,
+/// waits and tries again if Telegram answers with `retry_after`
+pub fn send_message<'a>(
+    bot: &telebot::RcBot,
+    limiter: &RateLimiter,
+    target: i64,
+    msg: String,
+) -> impl Future<Item = (), Error = telebot::Error> + 'a {
+    send_with_limit(bot, limiter, target, move |bot| {
+        let r = bot.message(target, msg.clone())
             .parse_mode("HTML")
             .disable_web_page_preview(true)
             .send()
-            .map(|_| ())
+            .map(|_| ());
+        Box::new(r)
     })
 }
 
+/// Sends media by URL with `caption` in HTML, an album has it on the first photo
+pub fn send_attachment<'a>(
+    bot: &telebot::RcBot,
+    limiter: &RateLimiter,
+    target: i64,
+    caption: String,
+    attachment: Attachment,
+) -> impl Future<Item = (), Error = telebot::Error> + 'a {
+    send_with_limit(bot, limiter, target, move |bot| {
+        let r: Box<Future<Item = (), Error = telebot::Error>> = match attachment {
+            Attachment::Photo(ref url) => Box::new(
+                bot.photo(target, url.clone())
+                    .caption(caption.clone())
+                    .parse_mode("HTML")
+                    .send()
+                    .map(|_| ()),
+            ),
+            Attachment::Audio(ref url) => Box::new(
+                bot.audio(target, url.clone())
+                    .caption(caption.clone())
+                    .parse_mode("HTML")
+                    .send()
+                    .map(|_| ()),
+            ),
+            Attachment::Photos(ref urls) => {
+                let media = urls.iter()
+                    .enumerate()
+                    .map(|(i, url)| InputMedia {
+                        kind: "photo".into(),
+                        media: url.clone(),
+                        caption: if i == 0 { Some(caption.clone()) } else { None },
+                        parse_mode: if i == 0 { Some("HTML".into()) } else { None },
+                    })
+                    .collect::<Vec<_>>();
+                Box::new(bot.media_group(target, media).send().map(|_| ()))
+            }
+        };
+        r
+    })
+}
+
+pub fn send_multiple_messages<'a>(
+    bot: &telebot::RcBot,
+    limiter: &RateLimiter,
+    target: i64,
+    messages: Vec<String>,
+) -> impl Future<Item = (), Error = telebot::Error> + 'a {
+    let bot = bot.clone();
+    let limiter = limiter.clone();
+    futures::stream::iter_ok(messages)
+        .for_each(move |msg| send_message(&bot, &limiter, target, msg))
+}
+
 pub fn truncate_message(s: &str, max: usize) -> String {
     if s.chars().count() > max {
         format!("{:.1$}...", s, max - 3)
@@ -112,21 +213,96 @@ where
     msgs
 }
 
-pub fn to_chinese_error_msg(e: errors::Error) -> String {
-    match e {
-        errors::Error(errors::ErrorKind::Curl(e), _) => {
-            format!("网络错误 ({})", e.into_error())
+pub fn to_error_msg(e: &errors::Error, lang: Lang) -> String {
+    let msgs = lang.msgs();
+    match *e {
+        errors::Error(errors::ErrorKind::Curl(ref e), _) => {
+            fill(msgs.network_error, &[&e.to_string()])
+        }
+        errors::Error(errors::ErrorKind::Utf8(ref e), _) => {
+            fill(msgs.encoding_error, &[&e.to_string()])
         }
-        errors::Error(errors::ErrorKind::Utf8(e), _) => format!("编码错误 ({})", e),
-        errors::Error(errors::ErrorKind::Xml(e), _) => {
+        errors::Error(errors::ErrorKind::Xml(ref e), _) => {
             let s = e.to_string();
             let msg = truncate_message(&s, 500);
-            format!("解析错误 ({})", msg)
+            fill(msgs.parse_error, &[&msg])
+        }
+        errors::Error(errors::ErrorKind::Json(ref e), _) => {
+            fill(msgs.parse_error, &[&e.to_string()])
+        }
+        errors::Error(errors::ErrorKind::EOF, _) => fill(msgs.parse_error, &[&e.to_string()]),
+        errors::Error(errors::ErrorKind::EmptyFeed, _) => msgs.empty_feed.to_owned(),
+        errors::Error(errors::ErrorKind::TooManyRedirects, _) => {
+            msgs.too_many_redirects.to_owned()
+        }
+        errors::Error(errors::ErrorKind::Http(code), _) => {
+            fill(msgs.http_error, &[&http_status(code)])
+        }
+        errors::Error(errors::ErrorKind::WebPage(ref feeds), _) => {
+            if feeds.is_empty() {
+                return msgs.no_feed_found.to_owned();
+            }
+            let mut msg = msgs.feeds_found.to_owned();
+            for feed in feeds {
+                msg.push('\n');
+                if let Some(ref title) = feed.title {
+                    msg.push_str(title);
+                    msg.push_str(": ");
+                }
+                msg.push_str(&feed.link);
+            }
+            msg
         }
         _ => format!("{}", e),
     }
 }
 
+/// Like `to_error_msg`, for an error saved in the database
+pub fn to_feed_error_msg(e: &FeedError, lang: Lang) -> String {
+    let msgs = lang.msgs();
+    match e.kind {
+        FeedErrorKind::Network => fill(msgs.network_error, &[&e.message]),
+        FeedErrorKind::Encoding => fill(msgs.encoding_error, &[&e.message]),
+        FeedErrorKind::Parse => fill(msgs.parse_error, &[&truncate_message(&e.message, 500)]),
+        FeedErrorKind::Http(code) => fill(msgs.http_error, &[&http_status(code)]),
+        FeedErrorKind::Other => e.message.clone(),
+    }
+}
+
+/// Like `404 Not Found`
+fn http_status(code: u32) -> String {
+    match errors::response_code(code) {
+        Some(reason) => format!("{} {}", code, reason),
+        None => code.to_string(),
+    }
+}
+
+/// A Unix time like `2006-01-02 15:04 UTC`
+pub fn format_time(time: i64) -> String {
+    Utc.timestamp(time, 0).format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// Seconds like `1d 2h`, only the two largest units
+pub fn format_duration(seconds: u64) -> String {
+    let units = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m"), (1, "s")];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |rest, &(size, name)| {
+            let count = *rest / size;
+            *rest %= size;
+            Some((count, name))
+        })
+        .skip_while(|&(count, _)| count == 0)
+        .take(2)
+        .filter(|&(count, _)| count != 0)
+        .map(|(count, name)| format!("{}{}", count, name))
+        .collect();
+    if parts.is_empty() {
+        return "0s".to_owned();
+    }
+    parts.join(" ")
+}
+
 pub fn chat_is_unavailable(s: &str) -> bool {
     s.contains("Forbidden") || s.contains("chat not found")
 }
@@ -152,3 +328,26 @@ pub fn gen_ua(bot: &telebot::RcBot) -> String {
         bot.inner.username
     )
 }
+
+#[test]
+fn test_to_error_msg() {
+    let e = errors::ErrorKind::Http(404).into();
+    assert_eq!(to_error_msg(&e, Lang::En), "HTTP error (404 Not Found)");
+    assert_eq!(to_error_msg(&e, Lang::Zh), "HTTP 错误 (404 Not Found)");
+    let e = errors::ErrorKind::EmptyFeed.into();
+    assert_eq!(to_error_msg(&e, Lang::Zh), "RSS 为空或无效");
+    let e = FeedError::new(&errors::ErrorKind::Http(599).into(), 0);
+    assert_eq!(to_feed_error_msg(&e, Lang::En), "HTTP error (599)");
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(0), "0s");
+    assert_eq!(format_duration(59), "59s");
+    assert_eq!(format_duration(300), "5m");
+    assert_eq!(format_duration(3 * 60 * 60 + 5), "3h");
+    assert_eq!(format_duration(90 * 60), "1h 30m");
+    assert_eq!(format_duration(24 * 60 * 60 + 60), "1d");
+    assert_eq!(format_duration(50 * 60 * 60), "2d 2h");
+}
+
@@ -0,0 +1,626 @@
+//! WebSub (formerly PubSubHubbub) subscriptions, https://www.w3.org/TR/websub/
+//!
+//! Feeds which advertise a hub are subscribed with a callback on an embedded HTTP server,
+//! content the hub pushes to it goes the same way as fetched content. Feeds are still
+//! polled, which also renews the leases, so nothing is missed if a hub stops pushing.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use curl::easy::Easy;
+use futures::{future, Future, Stream};
+use hmac::{Hmac, Mac};
+use hyper;
+use hyper::header::ContentLength;
+use hyper::server::{Http, Request, Response, Service};
+use hyper::{Method, StatusCode};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use tokio_core::reactor::Handle;
+use tokio_curl::Session;
+use url::form_urlencoded;
+
+use data::{self, Database, FeedID};
+use errors::*;
+use feed;
+use fetcher;
+
+/// Hubs send the HMAC of the pushed content in this header, as `method=hex`
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature";
+
+/// The lease asked for, hubs may grant another one
+const LEASE_SECONDS: i64 = 10 * 24 * 60 * 60;
+/// Leases are renewed when less than this is left
+const RENEW_BEFORE: i64 = 24 * 60 * 60;
+/// Requests are not repeated within this, in case the hub never verifies them
+const RETRY_INTERVAL: i64 = 60 * 60;
+/// Larger content pushed by a hub is refused, it's read into memory
+const MAX_BODY_LEN: usize = 4 * 1024 * 1024;
+
+/// Subscribes feeds to their hubs, with callbacks under `base`
+#[derive(Clone)]
+pub struct WebSub {
+    base: Rc<String>,
+    session: Session,
+    handle: Handle,
+}
+
+impl WebSub {
+    pub fn new(base: String, handle: Handle) -> WebSub {
+        WebSub {
+            base: Rc::new(base.trim_right_matches('/').to_owned()),
+            session: Session::new(handle.clone()),
+            handle: handle,
+        }
+    }
+
+    /// `base/FEED-ID`, the server only looks at the last part of the path,
+    /// so a reverse proxy doesn't have to strip `base`
+    fn callback(&self, rss_link: &str) -> String {
+        format!("{}/{}", self.base, data::get_hash(rss_link))
+    }
+
+    /// Subscribes `feed` to `hub` if it's not yet, or renews the lease if it's ending.
+    /// The subscription is dropped if the feed has no hub anymore
+    pub fn maintain(&self, db: &Database, feed: &data::Feed, hub: Option<&str>) {
+        let hub_url = match hub {
+            Some(hub_url) => hub_url,
+            None => {
+                if feed.hub.is_some() {
+                    let _ = db.set_hub(&feed.link, None);
+                }
+                return;
+            }
+        };
+        let now = now();
+        let callback = self.callback(&feed.link);
+        let hub = match feed.hub {
+            Some(ref hub) if hub.url == hub_url && hub.callback == callback => {
+                if !needs_renewal(hub, now) {
+                    return;
+                }
+                data::Hub {
+                    requested_at: now,
+                    ..hub.clone()
+                }
+            }
+            _ => {
+                let secret = match gen_secret() {
+                    Ok(secret) => secret,
+                    Err(e) => {
+                        error!("websub: failed to generate secret: {}", e);
+                        return;
+                    }
+                };
+                data::Hub {
+                    url: hub_url.to_owned(),
+                    callback: callback,
+                    secret: secret,
+                    requested_at: now,
+                    expires_at: None,
+                }
+            }
+        };
+        if let Err(e) = db.set_hub(&feed.link, Some(hub.clone())) {
+            warn!("websub: {}: {}", feed.link, e);
+            return;
+        }
+        let topic = feed.link.clone();
+        let request = subscribe(&self.session, &hub, &topic).then(move |r| {
+            // the subscription is only active after the hub verifies it
+            match r {
+                Ok(code) if code >= 200 && code < 300 => {}
+                Ok(code) => warn!("websub: {} refused {}: HTTP {}", hub.url, topic, code),
+                Err(e) => warn!("websub: {} failed for {}: {}", hub.url, topic, e),
+            }
+            Ok(())
+        });
+        self.handle.spawn(request);
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn needs_renewal(hub: &data::Hub, now: i64) -> bool {
+    now - hub.requested_at >= RETRY_INTERVAL
+        && hub.expires_at
+            .map_or(true, |expires_at| expires_at - now < RENEW_BEFORE)
+}
+
+/// 20 random bytes in hex
+fn gen_secret() -> io::Result<String> {
+    let mut bytes = [0; 20];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn subscription_request(hub: &data::Hub, topic: &str) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .append_pair("hub.mode", "subscribe")
+        .append_pair("hub.topic", topic)
+        .append_pair("hub.callback", &hub.callback)
+        .append_pair("hub.secret", &hub.secret)
+        .append_pair("hub.lease_seconds", &LEASE_SECONDS.to_string())
+        .finish()
+}
+
+/// Sends the subscription request, returns the response code
+fn subscribe(
+    session: &Session,
+    hub: &data::Hub,
+    topic: &str,
+) -> impl Future<Item = u32, Error = Error> {
+    let mut req = Easy::new();
+    req.url(&hub.url).unwrap();
+    req.post(true).unwrap();
+    req.post_fields_copy(subscription_request(hub, topic).as_bytes())
+        .unwrap();
+    req.timeout(Duration::from_secs(10)).unwrap();
+    // or it goes to stdout
+    req.write_function(|data| Ok(data.len())).unwrap();
+    session
+        .perform(req)
+        .map(|mut resp| resp.response_code().unwrap())
+        .map_err(Error::from)
+}
+
+fn verify_hmac<M: Mac>(secret: &str, body: &[u8], code: &[u8]) -> bool {
+    match M::new_varkey(secret.as_bytes()) {
+        Ok(mut mac) => {
+            mac.input(body);
+            mac.verify(code).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Checks the `X-Hub-Signature` of `body`
+pub fn check_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let mut signature = signature.unwrap_or_default().splitn(2, '=');
+    let method = signature.next().unwrap_or_default();
+    let code = match signature.next().and_then(from_hex) {
+        Some(code) => code,
+        None => return false,
+    };
+    match method {
+        "sha1" => verify_hmac::<Hmac<Sha1>>(secret, body, &code),
+        "sha256" => verify_hmac::<Hmac<Sha256>>(secret, body, &code),
+        "sha384" => verify_hmac::<Hmac<Sha384>>(secret, body, &code),
+        "sha512" => verify_hmac::<Hmac<Sha512>>(secret, body, &code),
+        _ => false,
+    }
+}
+
+/// Answers the verification of intent of a hub, returns the response and its body
+fn verify_intent(
+    db: &Database,
+    feed_id: Option<FeedID>,
+    query: &str,
+    now: i64,
+) -> (StatusCode, String) {
+    let params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let param = |name: &str| params.get(name).map(|s| s.as_str());
+    let (mode, topic) = match (param("hub.mode"), param("hub.topic")) {
+        (Some(mode), Some(topic)) => (mode, topic),
+        _ => return (StatusCode::BadRequest, String::new()),
+    };
+    let feed = feed_id
+        .and_then(|feed_id| db.get_feed(feed_id))
+        .filter(|feed| feed.link == topic);
+    let challenge = param("hub.challenge").unwrap_or_default().to_owned();
+    match mode {
+        "subscribe" => {
+            let mut hub = match feed.and_then(|feed| feed.hub) {
+                Some(hub) => hub,
+                None => return (StatusCode::NotFound, String::new()),
+            };
+            let lease = param("hub.lease_seconds")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(LEASE_SECONDS);
+            hub.expires_at = Some(now + lease);
+            if db.set_hub(topic, Some(hub)).is_err() {
+                return (StatusCode::NotFound, String::new());
+            }
+            info!("websub: subscribed to {} for {}s", topic, lease);
+            (StatusCode::Ok, challenge)
+        }
+        // confirmed only if the feed is gone or doesn't want the hub anymore
+        "unsubscribe" if feed.as_ref().map_or(true, |feed| feed.hub.is_none()) => {
+            (StatusCode::Ok, challenge)
+        }
+        "unsubscribe" => (StatusCode::NotFound, String::new()),
+        "denied" => {
+            warn!(
+                "websub: subscription to {} denied: {}",
+                topic,
+                param("hub.reason").unwrap_or_default()
+            );
+            // polled only, until the next try
+            if let Some(mut hub) = feed.and_then(|feed| feed.hub) {
+                hub.expires_at = None;
+                let _ = db.set_hub(topic, Some(hub));
+            }
+            (StatusCode::Ok, String::new())
+        }
+        _ => (StatusCode::BadRequest, String::new()),
+    }
+}
+
+/// Takes the content a hub pushed, it's ignored if the signature doesn't match
+fn receive(
+    db: &Database,
+    feed_id: Option<FeedID>,
+    content_type: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+) -> StatusCode {
+    let feed = match feed_id.and_then(|feed_id| db.get_feed(feed_id)) {
+        Some(feed) => feed,
+        // tells the hub to stop
+        None => return StatusCode::Gone,
+    };
+    let valid = match feed.hub {
+        Some(ref hub) => check_signature(&hub.secret, signature, body),
+        None => return StatusCode::Gone,
+    };
+    // the hub must get a success anyway, so it can't probe the secret
+    if !valid {
+        warn!("websub: invalid signature for {}", feed.link);
+        return StatusCode::Ok;
+    }
+    match feed::parse_body(content_type, body, &feed.link) {
+        Ok(rss) => {
+            fetcher::push_updates(db, &feed, rss);
+        }
+        Err(e) => warn!("websub: invalid content for {}: {}", feed.link, e),
+    }
+    StatusCode::Ok
+}
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers()
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+}
+
+struct WebSubService {
+    db: Database,
+}
+
+impl Service for WebSubService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let feed_id = req.path()
+            .rsplit('/')
+            .next()
+            .and_then(|id| id.parse().ok());
+        match *req.method() {
+            Method::Get => {
+                let query = req.query().unwrap_or_default();
+                let (status, body) = verify_intent(&self.db, feed_id, query, now());
+                Box::new(future::ok(
+                    Response::new().with_status(status).with_body(body),
+                ))
+            }
+            Method::Post => {
+                // checked before the body is read, `receive` checks it again
+                let subscribed = feed_id
+                    .and_then(|feed_id| self.db.get_feed(feed_id))
+                    .map_or(false, |feed| feed.hub.is_some());
+                if !subscribed {
+                    return Box::new(future::ok(Response::new().with_status(StatusCode::Gone)));
+                }
+                let too_large = req.headers()
+                    .get::<ContentLength>()
+                    .map_or(false, |len| len.0 > MAX_BODY_LEN as u64);
+                if too_large {
+                    return Box::new(future::ok(
+                        Response::new().with_status(StatusCode::PayloadTooLarge),
+                    ));
+                }
+                let content_type = header(&req, "Content-Type");
+                let signature = header(&req, SIGNATURE_HEADER);
+                let db = self.db.clone();
+                // the length may be missing or wrong, so it's checked as the body is read
+                let body = req.body().map_err(Some).fold(Vec::new(), |mut body, chunk| {
+                    body.extend_from_slice(&chunk);
+                    if body.len() > MAX_BODY_LEN {
+                        Err(None)
+                    } else {
+                        Ok(body)
+                    }
+                });
+                Box::new(body.then(move |body| match body {
+                    Ok(body) => {
+                        let status = receive(
+                            &db,
+                            feed_id,
+                            content_type.as_ref().map(|s| s.as_str()),
+                            signature.as_ref().map(|s| s.as_str()),
+                            &body,
+                        );
+                        Ok(Response::new().with_status(status))
+                    }
+                    Err(None) => Ok(Response::new().with_status(StatusCode::PayloadTooLarge)),
+                    Err(Some(e)) => Err(e),
+                }))
+            }
+            _ => Box::new(future::ok(
+                Response::new().with_status(StatusCode::MethodNotAllowed),
+            )),
+        }
+    }
+}
+
+/// Starts the callback server on `addr`
+pub fn serve(handle: &Handle, addr: &SocketAddr, db: Database) -> io::Result<()> {
+    let server = Http::new().serve_addr_handle(addr, handle, move || {
+        Ok(WebSubService { db: db.clone() })
+    }).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    info!("websub: listening on {}", addr);
+
+    let handle2 = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |conn| {
+                handle2.spawn(
+                    conn.map(|_| ())
+                        .map_err(|e| warn!("websub: connection error: {}", e)),
+                );
+                Ok(())
+            })
+            .map_err(|e| error!("websub: server error: {}", e)),
+    );
+    Ok(())
+}
+
+#[test]
+fn test_check_signature() {
+    // the examples of Wikipedia
+    let body = b"The quick brown fox jumps over the lazy dog";
+    let sha1 = "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+    let sha256 = "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+    assert!(check_signature("key", Some(sha1), body));
+    assert!(check_signature("key", Some(sha256), body));
+    assert!(!check_signature("key", Some(sha1), b"The quick brown fox"));
+    assert!(!check_signature("wrong", Some(sha1), body));
+    assert!(!check_signature("key", Some("md5=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9"), body));
+    assert!(!check_signature("key", Some("sha1=de7c"), body));
+    assert!(!check_signature("key", Some("sha1=xyz"), body));
+    assert!(!check_signature("key", Some("sha1"), body));
+    assert!(!check_signature("key", None, body));
+}
+
+#[test]
+fn test_needs_renewal() {
+    let mut hub = data::Hub::default();
+    hub.requested_at = 1000;
+    // not verified yet
+    assert!(!needs_renewal(&hub, 1000 + RETRY_INTERVAL - 1));
+    assert!(needs_renewal(&hub, 1000 + RETRY_INTERVAL));
+    hub.expires_at = Some(1000 + LEASE_SECONDS);
+    assert!(!needs_renewal(&hub, 1000 + RETRY_INTERVAL));
+    assert!(needs_renewal(&hub, 1000 + LEASE_SECONDS - RENEW_BEFORE + 1));
+}
+
+/// The `X-Hub-Signature` a hub would send
+#[cfg(test)]
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).unwrap();
+    mac.input(body);
+    let code: String = mac.result()
+        .code()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha1={}", code)
+}
+
+#[test]
+fn test_callbacks() {
+    use storage::Backend;
+
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let link = "http://example.com/blog/feed.xml";
+    let rss = feed::parse(include_str!("../tests/data/websub_feed.xml").as_bytes()).unwrap();
+    db.subscribe(1, link, &rss).unwrap();
+    let feed_id = data::get_hash(link);
+    let hub = data::Hub {
+        url: rss.hub.clone().unwrap(),
+        callback: format!("http://127.0.0.1:8081/{}", feed_id),
+        secret: "secret".into(),
+        requested_at: 0,
+        expires_at: None,
+    };
+    assert_eq!(
+        subscription_request(&hub, link),
+        format!(
+            "hub.mode=subscribe&hub.topic=http%3A%2F%2Fexample.com%2Fblog%2Ffeed.xml\
+             &hub.callback=http%3A%2F%2F127.0.0.1%3A8081%2F{}&hub.secret=secret\
+             &hub.lease_seconds=864000",
+            feed_id
+        )
+    );
+    db.set_hub(link, Some(hub)).unwrap();
+
+    // verification of intent
+    let query = |mode: &str, topic: &str| {
+        form_urlencoded::Serializer::new(String::new())
+            .append_pair("hub.mode", mode)
+            .append_pair("hub.topic", topic)
+            .append_pair("hub.challenge", "challenge")
+            .append_pair("hub.lease_seconds", "3600")
+            .finish()
+    };
+    assert_eq!(
+        verify_intent(&db, Some(feed_id), &query("subscribe", link), 100),
+        (StatusCode::Ok, "challenge".to_owned())
+    );
+    assert_eq!(db.get_feed(feed_id).unwrap().hub.unwrap().expires_at, Some(3700));
+    let other = "http://example.com/other.xml";
+    assert_eq!(
+        verify_intent(&db, Some(feed_id), &query("subscribe", other), 100).0,
+        StatusCode::NotFound
+    );
+    assert_eq!(
+        verify_intent(&db, Some(0), &query("subscribe", link), 100).0,
+        StatusCode::NotFound
+    );
+    assert_eq!(
+        verify_intent(&db, Some(feed_id), &query("unsubscribe", link), 100).0,
+        StatusCode::NotFound
+    );
+    assert_eq!(
+        verify_intent(&db, None, &query("unsubscribe", link), 100),
+        (StatusCode::Ok, "challenge".to_owned())
+    );
+    assert_eq!(
+        verify_intent(&db, Some(feed_id), "hub.mode=subscribe", 100).0,
+        StatusCode::BadRequest
+    );
+
+    // content distribution, it has only the new entry
+    let body = include_bytes!("../tests/data/websub_push.xml");
+    let sign = |secret: &str| sign(secret, body);
+    let content_type = Some("application/atom+xml");
+    assert_eq!(
+        receive(&db, Some(feed_id), content_type, Some(&sign("wrong")), body),
+        StatusCode::Ok
+    );
+    assert!(db.get_outbox(1).is_empty());
+    assert_eq!(
+        receive(&db, Some(feed_id), content_type, Some(&sign("secret")), body),
+        StatusCode::Ok
+    );
+    let outbox = db.get_outbox(1);
+    assert_eq!(outbox.len(), 1);
+    assert!(outbox[0].text.contains("websub_push.entry.title"));
+    // neither pushing it again nor polling makes anything new
+    receive(&db, Some(feed_id), content_type, Some(&sign("secret")), body);
+    assert_eq!(db.get_outbox(1).len(), 1);
+    assert!(db.update(link, rss.items).is_empty());
+
+    assert_eq!(
+        receive(&db, Some(0), content_type, Some(&sign("secret")), body),
+        StatusCode::Gone
+    );
+
+    // the body is only read for a subscribed feed, and up to `MAX_BODY_LEN`
+    let service = WebSubService { db: db.clone() };
+    let post = |feed_id: FeedID, body: Vec<u8>| {
+        let uri = format!("http://127.0.0.1:8081/{}", feed_id).parse().unwrap();
+        let mut req = Request::new(Method::Post, uri);
+        req.set_body(body);
+        service.call(req).wait().unwrap().status()
+    };
+    assert_eq!(post(0, body.to_vec()), StatusCode::Gone);
+    assert_eq!(post(feed_id, vec![b' '; MAX_BODY_LEN + 1]), StatusCode::PayloadTooLarge);
+}
+
+#[test]
+fn test_fake_hub() {
+    use futures::sync::mpsc;
+    use storage::Backend;
+    use tokio_core::reactor::Core;
+
+    /// Accepts every request, and passes on its body
+    struct FakeHub(mpsc::UnboundedSender<String>);
+
+    impl Service for FakeHub {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            let sender = self.0.clone();
+            Box::new(req.body().concat2().map(move |body| {
+                let _ = sender.unbounded_send(String::from_utf8_lossy(&body).into_owned());
+                Response::new().with_status(StatusCode::Accepted)
+            }))
+        }
+    }
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (sender, requests) = mpsc::unbounded();
+    let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let server = Http::new()
+        .serve_addr_handle(&addr, &handle, move || Ok(FakeHub(sender.clone())))
+        .unwrap();
+    let hub_url = format!("http://{}/hub", server.incoming_ref().local_addr());
+    let handle2 = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |conn| {
+                handle2.spawn(conn.map(|_| ()).map_err(|_| ()));
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let db = Database::open(":memory:", Backend::Sqlite).unwrap();
+    let link = "http://example.com/blog/feed.xml";
+    let rss = feed::parse(include_str!("../tests/data/websub_feed.xml").as_bytes()).unwrap();
+    db.subscribe(1, link, &rss).unwrap();
+    let feed_id = data::get_hash(link);
+
+    // the subscription request
+    let websub = WebSub::new("http://127.0.0.1:8081/".into(), handle);
+    websub.maintain(&db, &db.get_feed(feed_id).unwrap(), Some(&hub_url));
+    let (request, _) = core.run(requests.into_future()).map_err(|_| ()).unwrap();
+    let hub = db.get_feed(feed_id).unwrap().hub.unwrap();
+    assert_eq!(hub.url, hub_url);
+    assert_eq!(hub.callback, format!("http://127.0.0.1:8081/{}", feed_id));
+    assert_eq!(request, Some(subscription_request(&hub, link)));
+
+    // the hub verifies it at the callback
+    let service = WebSubService { db: db.clone() };
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("hub.mode", "subscribe")
+        .append_pair("hub.topic", link)
+        .append_pair("hub.challenge", "challenge")
+        .finish();
+    let uri = format!("{}?{}", hub.callback, query).parse().unwrap();
+    let resp = service.call(Request::new(Method::Get, uri)).wait().unwrap();
+    assert_eq!(resp.status(), StatusCode::Ok);
+    assert_eq!(&resp.body().concat2().wait().unwrap()[..], b"challenge");
+    assert!(db.get_feed(feed_id).unwrap().hub.unwrap().expires_at.is_some());
+
+    // and pushes the new entry there
+    let body = include_bytes!("../tests/data/websub_push.xml");
+    let mut req = Request::new(Method::Post, hub.callback.parse().unwrap());
+    req.headers_mut().set_raw(SIGNATURE_HEADER, sign(&hub.secret, body));
+    req.headers_mut().set_raw("Content-Type", "application/atom+xml");
+    req.set_body(body.to_vec());
+    assert_eq!(service.call(req).wait().unwrap().status(), StatusCode::Ok);
+    let outbox = db.get_outbox(1);
+    assert_eq!(outbox.len(), 1);
+    assert!(outbox[0].text.contains("websub_push.entry.title"));
+}
@@ -3,9 +3,12 @@
 
 use error::Error;
 use objects;
+use webhook;
 
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
 use std::rc::Rc;
 use std::str;
 use std::sync::{Arc, Mutex};
@@ -93,6 +96,44 @@ impl Bot {
         self.fetch(func, req)
     }
 
+    /// Downloads a file, `file_path` is taken from the answer of `getFile`
+    pub fn download_file<'a>(
+        &self,
+        file_path: &str,
+    ) -> impl Future<Item = Vec<u8>, Error = Error> + 'a {
+        let result = Arc::new(Mutex::new(Vec::new()));
+
+        let mut req = Easy::new();
+        req.url(&format!(
+            "https://api.telegram.org/file/bot{}/{}",
+            self.key, file_path
+        ))
+        .unwrap();
+
+        let r2 = result.clone();
+        req.write_function(move |data| {
+            r2.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        })
+        .unwrap();
+
+        self.session
+            .perform(req)
+            .map_err(|e| e.into())
+            .and_then(move |mut req| {
+                let code = req.response_code().unwrap_or(0);
+                if code != 200 {
+                    return Err(Error::Telegram(
+                        code,
+                        "failed to download file".into(),
+                        None,
+                    ));
+                }
+                let mut response = result.lock().unwrap();
+                Ok(::std::mem::replace(&mut *response, Vec::new()))
+            })
+    }
+
     /// calls cURL and parses the result for an error
     pub fn fetch<'a, T: DeserializeOwned + 'a>(
         &self,
@@ -211,37 +252,55 @@ impl RcBot {
             }
             !fresh_update_id || valid_update_id
         })
-        .filter_map(move |mut val| {
-            debug!("Got an update from Telegram: {:?}", val);
-            let mut forward: Option<String> = None;
-
-            if let Some(ref mut message) = val.message {
-                if let Some(text) = message.text.clone() {
-                    let mut content = text.split_whitespace();
-                    if let Some(cmd) = content.next() {
-                        let s: Vec<&str> = cmd.split("@").take(2).collect();
-                        if s.len() > 0
-                            && (s.len() < 2 || s[1] == self.inner.username)
-                            && self.inner.handlers.borrow().contains_key(s[0])
-                        {
-                            message.text = Some(content.collect::<Vec<&str>>().join(" "));
-
-                            forward = Some(s[0].into());
-                        }
+        .filter_map(move |val| self.dispatch(val))
+    }
+
+    /// Like `get_stream`, but the updates come from the webhook server listening on `addr`
+    /// instead of polling. The webhook itself has to be registered with `set_webhook`.
+    pub fn get_webhook_stream(
+        &self,
+        addr: &SocketAddr,
+        secret: String,
+    ) -> io::Result<impl Stream<Item = (RcBot, objects::Update), Error = Error>> {
+        let bot = self.clone();
+        let updates = webhook::serve(&self.inner.handle, addr, secret)?;
+        Ok(updates
+            .map_err(|_| Error::Unknown)
+            .filter_map(move |val| bot.dispatch(val)))
+    }
+
+    /// Forwards the update to the handler of its command, returns it if there is no such command
+    pub fn dispatch(&self, mut val: objects::Update) -> Option<(RcBot, objects::Update)> {
+        debug!("Got an update from Telegram: {:?}", val);
+        let mut forward: Option<String> = None;
+
+        if let Some(ref mut message) = val.message {
+            // commands can also be sent as the caption of a file
+            if let Some(text) = message.text.clone().or_else(|| message.caption.clone()) {
+                let mut content = text.split_whitespace();
+                if let Some(cmd) = content.next() {
+                    let s: Vec<&str> = cmd.split("@").take(2).collect();
+                    if s.len() > 0
+                        && (s.len() < 2 || s[1] == self.inner.username)
+                        && self.inner.handlers.borrow().contains_key(s[0])
+                    {
+                        message.text = Some(content.collect::<Vec<&str>>().join(" "));
+
+                        forward = Some(s[0].into());
                     }
                 }
             }
+        }
 
-            if let Some(cmd) = forward {
-                if let Some(sender) = self.inner.handlers.borrow_mut().get_mut(&cmd) {
-                    sender
-                        .unbounded_send((self.clone(), val.message.unwrap()))
-                        .unwrap();
-                }
-                return None;
-            } else {
-                return Some((self.clone(), val));
+        if let Some(cmd) = forward {
+            if let Some(sender) = self.inner.handlers.borrow_mut().get_mut(&cmd) {
+                sender
+                    .unbounded_send((self.clone(), val.message.unwrap()))
+                    .unwrap();
             }
-        })
+            return None;
+        } else {
+            return Some((self.clone(), val));
+        }
     }
 }
@@ -146,6 +146,36 @@ pub struct GetUpdates {
     allowed_updates: Option<Vec<String>>,
 }
 
+/// Use this method to specify a url and receive incoming updates via an outgoing webhook.
+/// Whenever there is an update for the bot, Telegram will send an HTTPS POST request to the
+/// specified url, containing a JSON-serialized Update. Returns True on success.
+#[derive(TelegramFunction, Serialize)]
+#[call = "setWebhook"]
+#[answer = "Boolean"]
+#[function = "set_webhook"]
+pub struct SetWebhook {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_connections: Option<Integer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_updates: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drop_pending_updates: Option<Boolean>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret_token: Option<String>,
+}
+
+/// Use this method to remove webhook integration if you decide to switch back to getUpdates.
+/// Returns True on success.
+#[derive(TelegramFunction, Serialize)]
+#[call = "deleteWebhook"]
+#[answer = "Boolean"]
+#[function = "delete_webhook"]
+pub struct DeleteWebhook {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    drop_pending_updates: Option<Boolean>,
+}
+
 /// Use this method to send text messages. On success, the sent Message is returned.
 #[derive(TelegramFunction, Serialize)]
 #[call = "sendMessage"]
@@ -243,6 +273,16 @@ pub struct DeleteMessage {
     message_id: Integer,
 }
 
+/// Use this method to get basic info about a file and prepare it for downloading. For the moment,
+/// bots can download files of up to 20MB in size. On success, a File object is returned.
+#[derive(TelegramFunction, Serialize)]
+#[call = "getFile"]
+#[answer = "File"]
+#[function = "get_file"]
+pub struct GetFile {
+    file_id: String,
+}
+
 /// Use this method to send general files. On success, the sent Message is returned. Bots can
 /// currently send files of any type of up to 50 MB in size, this limit may be changed in the
 /// future.
@@ -263,3 +303,81 @@ pub struct SendDocument {
     #[serde(skip_serializing_if = "Option::is_none")]
     reply_markup: Option<NotImplemented>,
 }
+
+/// Use this method to send photos. On success, the sent Message is returned.
+#[derive(TelegramFunction, Serialize)]
+#[call = "sendPhoto"]
+#[answer = "Message"]
+#[function = "photo"]
+#[file_kind = "photo"]
+pub struct SendPhoto {
+    chat_id: Integer,
+    photo: File,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<Integer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<NotImplemented>,
+}
+
+/// Use this method to send audio files, if you want Telegram clients to display them in the
+/// music player. Your audio must be in the .mp3 format. On success, the sent Message is
+/// returned. Bots can currently send audio files of up to 50 MB in size, this limit may be
+/// changed in the future.
+#[derive(TelegramFunction, Serialize)]
+#[call = "sendAudio"]
+#[answer = "Message"]
+#[function = "audio"]
+#[file_kind = "audio"]
+pub struct SendAudio {
+    chat_id: Integer,
+    audio: File,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<Integer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    performer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<Integer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<NotImplemented>,
+}
+
+/// Use this method to send a group of photos or videos as an album. On success, an array of the
+/// sent Messages is returned.
+#[derive(TelegramFunction, Serialize)]
+#[call = "sendMediaGroup"]
+#[answer = "Vector<objects::Message>"]
+#[function = "media_group"]
+pub struct SendMediaGroup {
+    chat_id: Integer,
+    media: Vec<InputMedia>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_notification: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<Integer>,
+}
+
+/// A photo or video of `sendMediaGroup`, `media` is a file_id or an HTTP URL
+#[derive(Serialize)]
+pub struct InputMedia {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub media: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<String>,
+}
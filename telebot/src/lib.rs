@@ -10,6 +10,7 @@ extern crate serde_derive;
 extern crate log;
 extern crate curl;
 extern crate futures;
+extern crate hyper;
 extern crate serde;
 extern crate serde_json;
 extern crate tokio_core;
@@ -22,3 +23,4 @@ pub mod bot;
 pub mod error;
 pub mod functions;
 pub mod objects;
+pub mod webhook;
@@ -61,7 +61,7 @@ pub struct Message {
     pub text: Option<String>,
     pub entities: Option<Vec<MessageEntity>>,
     pub audio: Option<NotImplemented>,
-    pub document: Option<NotImplemented>,
+    pub document: Option<Document>,
     pub game: Option<NotImplemented>,
     pub photo: Option<Vec<NotImplemented>>,
     pub sticker: Option<NotImplemented>,
@@ -88,6 +88,25 @@ pub struct Message {
     pub successful_payment: Option<NotImplemented>,
 }
 
+/// This object represents a general file (as opposed to photos, voice messages and audio files).
+#[derive(Deserialize, Debug)]
+pub struct Document {
+    pub file_id: String,
+    pub thumb: Option<NotImplemented>,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    pub file_size: Option<Integer>,
+}
+
+/// This object represents a file ready to be downloaded. The file can be downloaded with
+/// `Bot::download_file`. Maximum file size to download is 20 MB.
+#[derive(Deserialize, Debug)]
+pub struct File {
+    pub file_id: String,
+    pub file_size: Option<Integer>,
+    pub file_path: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Updates(pub Vec<Update>);
 
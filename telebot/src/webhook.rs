@@ -0,0 +1,186 @@
+//! An embedded HTTP server which receives the updates Telegram POSTs to a webhook
+//!
+//! It's meant to sit behind a reverse proxy which terminates TLS, so it only speaks plain HTTP.
+
+use std::io;
+use std::net::SocketAddr;
+
+use futures::sync::mpsc;
+use futures::sync::mpsc::UnboundedSender;
+use futures::{future, Future, Stream};
+use hyper;
+use hyper::header::ContentLength;
+use hyper::server::{Http, Request, Response, Service};
+use hyper::{Method, StatusCode};
+use serde_json;
+use tokio_core::reactor::Handle;
+
+use objects::Update;
+
+/// Telegram sends the `secret_token` passed to `setWebhook` in this header
+pub const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+/// Larger requests are refused, an update is far smaller
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// Compares without returning early, so the time taken doesn't tell how much of `a` is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks the secret token of a webhook request and decodes the update in its body
+pub fn parse_update(secret: &str, token: Option<&[u8]>, body: &[u8]) -> Result<Update, StatusCode> {
+    if !token.map_or(false, |token| constant_time_eq(token, secret.as_bytes())) {
+        return Err(StatusCode::Unauthorized);
+    }
+    serde_json::from_slice(body).map_err(|e| {
+        warn!("webhook: invalid update: {}", e);
+        StatusCode::BadRequest
+    })
+}
+
+struct WebhookService {
+    secret: String,
+    sender: UnboundedSender<Update>,
+}
+
+impl Service for WebhookService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if *req.method() != Method::Post {
+            return Box::new(future::ok(
+                Response::new().with_status(StatusCode::MethodNotAllowed),
+            ));
+        }
+        let too_large = req.headers()
+            .get::<ContentLength>()
+            .map_or(false, |len| len.0 > MAX_BODY_LEN as u64);
+        if too_large {
+            return Box::new(future::ok(
+                Response::new().with_status(StatusCode::PayloadTooLarge),
+            ));
+        }
+        let token = req
+            .headers()
+            .get_raw(SECRET_TOKEN_HEADER)
+            .and_then(|raw| raw.one())
+            .map(|v| v.to_vec());
+        let secret = self.secret.clone();
+        let sender = self.sender.clone();
+        // the length may be missing or wrong, so it's checked as the body is read
+        let body = req.body().map_err(Some).fold(Vec::new(), |mut body, chunk| {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_BODY_LEN {
+                Err(None)
+            } else {
+                Ok(body)
+            }
+        });
+        Box::new(body.then(move |body| match body {
+            Ok(body) => match parse_update(&secret, token.as_ref().map(|v| v.as_slice()), &body) {
+                Ok(update) => {
+                    // the receiver is gone only when the bot is shutting down
+                    let _ = sender.unbounded_send(update);
+                    Ok(Response::new())
+                }
+                Err(status) => Ok(Response::new().with_status(status)),
+            },
+            Err(None) => Ok(Response::new().with_status(StatusCode::PayloadTooLarge)),
+            Err(Some(e)) => Err(e),
+        }))
+    }
+}
+
+/// Starts listening on `addr`, returns a stream of the received updates
+///
+/// Requests without the right secret token header are rejected,
+/// otherwise anyone who finds the address could forge updates
+pub fn serve(
+    handle: &Handle,
+    addr: &SocketAddr,
+    secret: String,
+) -> io::Result<impl Stream<Item = Update, Error = ()>> {
+    let (sender, receiver) = mpsc::unbounded();
+    let server = Http::new().serve_addr_handle(addr, handle, move || {
+        Ok(WebhookService {
+            secret: secret.clone(),
+            sender: sender.clone(),
+        })
+    }).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    info!("webhook: listening on {}", addr);
+
+    let handle2 = handle.clone();
+    handle.spawn(
+        server
+            .for_each(move |conn| {
+                handle2.spawn(
+                    conn.map(|_| ())
+                        .map_err(|e| warn!("webhook: connection error: {}", e)),
+                );
+                Ok(())
+            })
+            .map_err(|e| error!("webhook: server error: {}", e)),
+    );
+
+    Ok(receiver)
+}
+
+#[test]
+fn test_parse_update() {
+    let body = include_bytes!("../tests/data/update.json");
+
+    let update = parse_update("secret", Some(b"secret"), body).unwrap();
+    assert_eq!(update.update_id, 10000);
+    let message = update.message.unwrap();
+    assert_eq!(message.chat.id, 1111);
+    assert_eq!(message.text, Some("/rss".to_owned()));
+
+    assert_eq!(
+        parse_update("secret", Some(b"wrong"), body).unwrap_err(),
+        StatusCode::Unauthorized
+    );
+    assert_eq!(
+        parse_update("secret", Some(b"secret2"), body).unwrap_err(),
+        StatusCode::Unauthorized
+    );
+    assert_eq!(
+        parse_update("secret", None, body).unwrap_err(),
+        StatusCode::Unauthorized
+    );
+    assert_eq!(
+        parse_update("secret", Some(b"secret"), b"{}").unwrap_err(),
+        StatusCode::BadRequest
+    );
+}
+
+#[test]
+fn test_webhook_service() {
+    let (sender, receiver) = mpsc::unbounded();
+    let service = WebhookService {
+        secret: "secret".into(),
+        sender: sender,
+    };
+    {
+        let post = |token: &str, body: Vec<u8>| {
+            let mut req = Request::new(Method::Post, "http://127.0.0.1:8080/".parse().unwrap());
+            req.headers_mut().set_raw(SECRET_TOKEN_HEADER, token.to_owned());
+            req.set_body(body);
+            service.call(req).wait().unwrap().status()
+        };
+        let body = include_bytes!("../tests/data/update.json");
+        assert_eq!(post("wrong", body.to_vec()), StatusCode::Unauthorized);
+        assert_eq!(post("secret", vec![b' '; MAX_BODY_LEN + 1]), StatusCode::PayloadTooLarge);
+        assert_eq!(post("secret", body.to_vec()), StatusCode::Ok);
+    }
+    // the stream ends once every sender is gone
+    drop(service);
+    let updates = receiver.collect().wait().unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].update_id, 10000);
+}